@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Everything an instruction can change, captured between steps.
+pub struct Snapshot {
+    pub pc: u16,
+    pub i: u16,
+    pub sp: u8,
+    pub stack: [u16; 16],
+    pub registers: [u8; 16],
+    pub dt: u8,
+    pub st: u8,
+    pub ram: Vec<u8>,
+    pub display: Vec<u8>,
+    pub display_width: usize,
+}
+
+/// Writes a record of exactly what each step changed, one line per step.
+/// Every change is written as `name=old->new`, for example:
+///
+/// ```text
+/// step=4 pc=0x0206->0x0208 v3=0x00->0x05 ram[0x0300]=0x00->0x05
+/// ```
+///
+/// Pixels are named `px[X,Y]`. A change of resolution is written as
+/// `res=WxH->WxH` and replaces the pixel changes of that step. Replay tools
+/// can apply the records forwards, or swap old and new to step backwards.
+#[derive(Debug)]
+pub struct Changelog {
+    out: BufWriter<File>,
+    step: u64,
+}
+
+impl Changelog {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Changelog> {
+        Ok(Changelog {
+            out: BufWriter::new(File::create(path)?),
+            step: 0,
+        })
+    }
+
+    /// Writes the differences between the state before and after a step.
+    pub fn record(&mut self, before: &Snapshot, after: &Snapshot) -> io::Result<()> {
+        let out = &mut self.out;
+        write!(out, "step={}", self.step)?;
+        self.step += 1;
+
+        if before.pc != after.pc {
+            write!(out, " pc={:#06x}->{:#06x}", before.pc, after.pc)?;
+        }
+        if before.i != after.i {
+            write!(out, " i={:#06x}->{:#06x}", before.i, after.i)?;
+        }
+        if before.sp != after.sp {
+            write!(out, " sp={}->{}", before.sp, after.sp)?;
+        }
+        for (slot, (old, new)) in before.stack.iter().zip(after.stack.iter()).enumerate() {
+            if old != new {
+                write!(out, " stack[{}]={:#06x}->{:#06x}", slot, old, new)?;
+            }
+        }
+        for (reg, (old, new)) in before.registers.iter().zip(after.registers.iter()).enumerate() {
+            if old != new {
+                write!(out, " v{:x}={:#04x}->{:#04x}", reg, old, new)?;
+            }
+        }
+        if before.dt != after.dt {
+            write!(out, " dt={:#04x}->{:#04x}", before.dt, after.dt)?;
+        }
+        if before.st != after.st {
+            write!(out, " st={:#04x}->{:#04x}", before.st, after.st)?;
+        }
+        for (addr, (old, new)) in before.ram.iter().zip(after.ram.iter()).enumerate() {
+            if old != new {
+                write!(out, " ram[{:#06x}]={:#04x}->{:#04x}", addr, old, new)?;
+            }
+        }
+        if before.display.len() != after.display.len() {
+            write!(out, " res={}x{}->{}x{}",
+                   before.display_width, before.display.len() / before.display_width,
+                   after.display_width, after.display.len() / after.display_width)?;
+        } else {
+            let width = after.display_width;
+            for (pixel, (old, new)) in before.display.iter().zip(after.display.iter()).enumerate() {
+                if old != new {
+                    write!(out, " px[{},{}]={}->{}", pixel % width, pixel / width, old, new)?;
+                }
+            }
+        }
+
+        writeln!(out)
+    }
+
+    /// Flushes every buffered line to the file.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl Drop for Changelog {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::*;
+    use super::super::cpu::Cpu;
+    use super::super::interconnect::Interconnect;
+    use super::super::memory::RAM_SIZE;
+
+    #[test]
+    fn alu_ops_and_draws_record_what_they_changed() {
+        // 0x200: LD V1, 0x05, LD I, 0x20A, ADD V0, V1, DRW V0, V1, 1,
+        // JP 0x208, with a one row sprite at 0x20A.
+        let rom = [0x61, 0x05, 0xA2, 0x0A, 0x80, 0x14, 0xD0, 0x11, 0x12, 0x08, 0xC0];
+        let path = env::temp_dir().join(format!("notch-{}-changelog.txt", process::id()));
+
+        let interconnect = Interconnect::headless(&rom[..], RAM_SIZE).unwrap();
+        let mut cpu = Cpu::with_seed(interconnect, 0);
+        cpu.enable_turbo();
+        cpu.record_changes(Changelog::create(&path).unwrap());
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        cpu.shutdown();
+
+        let log = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[2], "step=2 pc=0x0204->0x0206 v0=0x00->0x05");
+        assert_eq!(lines[3], "step=3 pc=0x0206->0x0208 px[5,5]=0->1 px[6,5]=0->1");
+    }
+}
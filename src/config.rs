@@ -0,0 +1,140 @@
+use std::io::Write;
+
+use super::sdl2::controller::Button;
+use super::sdl2::keyboard::Keycode;
+use super::sdl2::pixels::Color;
+
+use super::changelog::Changelog;
+use super::flags::FlagStorage;
+use super::input::InputProvider;
+use super::pacing::Pacing;
+use super::quirks::Quirks;
+use super::reference::ReferenceTrace;
+use super::replay::InputLog;
+use super::sound::BeepListener;
+use super::state::StateSlot;
+use super::timing::TimingProfile;
+use super::wav::WavRecorder;
+
+/// Options chosen on the command line that are applied to the virtual machine
+/// while it is being set up.
+#[derive(Default)]
+pub struct Config {
+    // Run without opening a window or playing audio.
+    pub headless: bool,
+
+    // Draw the display in the terminal instead of a window, without audio.
+    pub terminal: bool,
+
+    // Show the frame rate, speed and whether execution is paused in the
+    // window title. Ignored without a window.
+    pub title_status: bool,
+
+    // Size of a display pixel in the window, uses the default when unset.
+    pub scale: Option<u32>,
+
+    // Frames drawn each second at most, uses the default when unset.
+    pub fps: Option<u64>,
+
+    // Cover the whole screen instead of opening a window.
+    pub fullscreen: bool,
+
+    // How many times larger than the display screenshots are, uses the
+    // default when unset.
+    pub screenshot_scale: Option<usize>,
+
+    // Colors of lit pixels and of the background, white on black unless set.
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+
+    // Receives the beeper output when audio recording is enabled.
+    pub audio_recorder: Option<WavRecorder>,
+
+    // Pitch in Hz and loudness from 0.0 to 1.0 of the beep, uses the
+    // defaults when unset.
+    pub beep_frequency: Option<f32>,
+    pub volume: Option<f32>,
+
+    // Persists the HP48 flag registers between runs when set. Otherwise they
+    // only live in memory.
+    pub flag_storage: Option<FlagStorage>,
+
+    // Where F6 saves the machine state and F9 loads it back from.
+    pub quicksave_slot: Option<StateSlot>,
+
+    // Frames kept for rewinding, uses the default when unset and disables
+    // rewinding when 0.
+    pub rewind_frames: Option<usize>,
+
+    // Value the general purpose registers start out with. Real interpreters
+    // don't always clear them, so this helps catch roms relying on zero.
+    pub register_fill: u8,
+
+    // Seeds the random number generator so CXNN gives the same numbers on
+    // every run. Seeded from the system otherwise.
+    pub seed: Option<u64>,
+
+    // Run roms written for the Chip-8X color extension.
+    pub chip8x: bool,
+
+    // Run roms written for the Super-CHIP.
+    pub schip: bool,
+
+    // Run roms written for XO-CHIP, with 64kB of ram.
+    pub xochip: bool,
+
+    // How the instructions interpreters disagree on behave.
+    pub quirks: Quirks,
+
+    // Wrap memory accesses past the end of ram instead of failing.
+    pub mirror_ram: bool,
+
+    // How long instructions take and how the cpu waits between them.
+    pub timing: TimingProfile,
+    pub pacing: Pacing,
+
+    // Instructions per second, replaces the timing profile when set.
+    pub clock_hz: Option<u64>,
+
+    // Instructions executed every 60Hz frame, replaces the timing profile
+    // and the clock when set.
+    pub cycles_per_frame: Option<u64>,
+
+    // Run as fast as possible without drawing, with the timers following
+    // instruction time.
+    pub turbo: bool,
+
+    // Log the keys of every frame so the run can be replayed.
+    pub record_input: bool,
+
+    // Recorded input played back in place of live input when set.
+    pub input_replay: Option<InputLog>,
+
+    // Keyboard keys bound to keypad keys in place of the default layout.
+    pub key_bindings: Vec<(u8, Keycode)>,
+
+    // Controller buttons bound to keypad keys in place of the defaults.
+    pub button_bindings: Vec<(u8, Button)>,
+
+    // Extra sources of keypad input merged with the keyboard.
+    pub input_providers: Vec<Box<dyn InputProvider>>,
+
+    // Told when the beep starts and stops, on top of playing it.
+    pub beep_listeners: Vec<Box<dyn BeepListener>>,
+
+    // Receives a record of the state changed by every step when set.
+    pub changelog: Option<Changelog>,
+
+    // Execution is compared against this trace when set.
+    pub reference: Option<ReferenceTrace>,
+
+    // Start paused so the rom can be stepped through from its first
+    // instruction.
+    pub debug: bool,
+
+    // Addresses execution pauses at before running the instruction there.
+    pub breakpoints: Vec<u16>,
+
+    // Receives a line for every executed instruction when set.
+    pub trace: Option<Box<dyn Write>>,
+}
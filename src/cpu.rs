@@ -1,20 +1,61 @@
+use std::cmp;
+use std::collections::{HashSet, VecDeque};
+use std::fmt::{self, Write};
+use std::io;
 use std::thread::sleep;
 use std::time::Duration;
 
-use super::rand::random;
+use super::rand::{Rng, SeedableRng, StdRng};
 use super::time;
 
-use super::memory::END_RESERVED;
+use super::changelog::{Changelog, Snapshot};
+use super::disasm;
+use super::error::{CpuError, StateError};
+use super::flags::{FLAG_COUNT, FlagStorage};
+use super::graphics::ZONE_BLOCK_HEIGHT;
+use super::instruction::{self, Extensions, Instruction};
+use super::memory::{END_PROGRAM_SPACE, END_RESERVED};
+use super::pacing::{Pacing, SystemClock};
+use super::quirks::Quirks;
+use super::reference::{ReferenceTrace, TraceCheck};
+use super::replay::InputLog;
+use super::state::{MachineState, StateSlot};
+use super::timing::TimingProfile;
 use super::interconnect::Interconnect;
 
 // Instructions are 2 bytes long and stored as BigEndian.
 const INSTRUCTION_SIZE: u16 = 2;
 
-// Round about 60Hz delay for timers.
-const TIMER_DELAY: u64 = 16;
+// Timers count down at 60Hz, this is the time between ticks in nanoseconds.
+const TIMER_PERIOD: u64 = 1_000_000_000 / 60;
 
-// Around 500Hz clock speed.
-const EXECUTION_DELAY: u64 = 2;
+// When execution falls further behind than this (after waiting for a key for
+// example) the schedule is reset instead of racing to catch up.
+const MAX_EXECUTION_LAG: u64 = 100_000_000;
+
+// Range the emulation speed multiplier is kept within.
+const MIN_SPEED: f64 = 0.25;
+const MAX_SPEED: f64 = 8.0;
+
+// How often the frame rate in the window title is measured, in nanoseconds.
+const FPS_INTERVAL: u64 = 1_000_000_000;
+
+// Columns the Super-CHIP horizontal scrolls move the display by.
+const SCROLL_COLUMNS: usize = 4;
+
+// How often input is checked while paused, in milliseconds.
+const PAUSE_POLL_DELAY: u64 = 16;
+
+// How many frames of save states are kept for rewinding unless chosen
+// otherwise, ten seconds worth.
+const DEFAULT_REWIND_FRAMES: usize = 600;
+
+// Screenshots draw every pixel this many times larger unless chosen
+// otherwise.
+const DEFAULT_SCREENSHOT_SCALE: usize = 1;
+
+// How many bytes of ram are shown around PC and I in state dumps.
+const DUMP_RAM_WINDOW: usize = 8;
 
 #[derive(Debug)]
 pub struct Cpu {
@@ -31,22 +72,7 @@ pub struct Cpu {
     sp: u8,
 
     // General purpose registers v0-vf.
-    v0: u8,
-    v1: u8,
-    v2: u8,
-    v3: u8,
-    v4: u8,
-    v5: u8,
-    v6: u8,
-    v7: u8,
-    v8: u8,
-    v9: u8,
-    va: u8,
-    vb: u8,
-    vc: u8,
-    vd: u8,
-    ve: u8,
-    vf: u8,
+    v: [u8; 16],
 
     // Address register "I".
     i: u16,
@@ -55,12 +81,150 @@ pub struct Cpu {
     dt: u8,
     st: u8,
 
-    dt_end: u64,
-    st_end: u64,
+    // When the timers were last updated and how much time has passed since
+    // their last tick.
+    timer_clock: u64,
+    timer_elapsed: u64,
+
+    // Instructions executed and frames shown since boot or the last reset.
+    cycles: u64,
+    frames: u64,
+
+    // SUPER-CHIP flag registers, backed by the HP48's user flags.
+    hp48_flags: [u8; FLAG_COUNT],
+
+    // Keeps the flag registers between runs if set.
+    flag_storage: Option<FlagStorage>,
+
+    // Where quicksaves are written to and read back from, if anywhere.
+    quicksave_slot: Option<StateSlot>,
+
+    // A save state for each of the most recent frames, oldest first, and how
+    // many of them are kept.
+    rewind_buffer: VecDeque<MachineState>,
+    rewind_capacity: usize,
+
+    // The keys of every frame are logged here while recording input.
+    input_recording: Option<InputLog>,
+
+    // Input being played back and the frame it is at.
+    input_replay: Option<(InputLog, usize)>,
+
+    // How many times larger than the display screenshots are.
+    screenshot_scale: usize,
+
+    // Instruction sets decoded on top of CHIP-8.
+    extensions: Extensions,
+
+    // Behaviors interpreters disagree on.
+    quirks: Quirks,
+
+    // How long instructions take, how to wait them out and when the next one
+    // is due.
+    timing: TimingProfile,
+    pacing: Pacing,
+    next_instruction: u64,
+
+    // Instructions executed every 60Hz frame when set, in place of the
+    // timing profile, and how many the current frame has executed.
+    cycles_per_frame: Option<u64>,
+    frame_cycles: u64,
+
+    // Run as fast as possible without drawing, the timers following the
+    // time instructions would have taken instead of the wall clock.
+    turbo: bool,
+
+    // Scales how many instructions run in the same time, 1.0 being the
+    // speed chosen by the timing profile or cycles per frame. The timers
+    // keep ticking at 60Hz.
+    speed: f64,
+
+    // Frame rate shown in the window title when set, and the time and frame
+    // count it was last measured at.
+    title_fps: Option<f64>,
+    fps_time: u64,
+    fps_frames: u64,
+
+    // Records what every step changes when set.
+    changelog: Option<Changelog>,
+
+    // Execution is checked against this trace step by step when set.
+    reference: Option<ReferenceTrace>,
+
+    // Every executed instruction is logged here when set.
+    trace: Option<TraceSink>,
+
+    // Set when execution must stop for good, even inside subroutines.
+    halted: bool,
+
+    // Set while FX0A waits for a key. Only presses after the wait started
+    // end it.
+    waiting_for_key: bool,
+
+    // No instructions run and the timers are frozen while set.
+    paused: bool,
+
+    // Execution pauses before running the instruction at any of these
+    // addresses. The breakpoint at the program counter is passed over once
+    // after resuming, otherwise execution could never continue past it.
+    breakpoints: HashSet<u16>,
+    skip_breakpoint: bool,
+
+    // Source of the numbers returned by CXNN.
+    rng: CpuRng,
+}
+
+/// Why execution stopped without an error.
+#[derive(Debug, PartialEq)]
+pub enum Stopped {
+    // The window was closed.
+    Closed,
+
+    // An instruction or the reference trace halted execution for good.
+    Halted,
+
+    // `run_for` executed as many instructions as it was asked to.
+    CycleLimit,
+
+    // Execution reached a breakpoint at this address without a window to
+    // pause in. The instruction there hasn't run yet, it runs first once
+    // execution continues.
+    Breakpoint(u16),
+}
+
+/// Wraps the trace output since writers don't implement Debug.
+struct TraceSink(Box<dyn io::Write>);
+
+impl fmt::Debug for TraceSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "trace")
+    }
+}
+
+/// Wraps the random number generator since it doesn't implement Debug.
+struct CpuRng(StdRng);
+
+impl fmt::Debug for CpuRng {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rng")
+    }
 }
 
 impl Cpu {
+    /// Creates a cpu with a randomly seeded number generator.
     pub fn new(interconnect: Interconnect) -> Cpu {
+        let rng = StdRng::new().expect("Cannot seed the random number generator");
+        Cpu::with_rng(interconnect, rng)
+    }
+
+    /// Creates a cpu whose random numbers are the same on every run with the
+    /// same seed, which makes runs of roms using CXNN reproducible.
+    pub fn with_seed(interconnect: Interconnect, seed: u64) -> Cpu {
+        let rng = StdRng::from_seed(&[seed as usize]);
+        Cpu::with_rng(interconnect, rng)
+    }
+
+    fn with_rng(interconnect: Interconnect, rng: StdRng) -> Cpu {
         Cpu {
             // Interconnect is used to control system resources like rom and memory.
             interconnect: interconnect,
@@ -75,22 +239,7 @@ impl Cpu {
             sp: 0,
 
             // General purpose registers v0-vf.
-            v0: 0,
-            v1: 0,
-            v2: 0,
-            v3: 0,
-            v4: 0,
-            v5: 0,
-            v6: 0,
-            v7: 0,
-            v8: 0,
-            v9: 0,
-            va: 0,
-            vb: 0,
-            vc: 0,
-            vd: 0,
-            ve: 0,
-            vf: 0,
+            v: [0; 16],
 
             // Address register "I".
             i: 0,
@@ -99,614 +248,2826 @@ impl Cpu {
             dt: 0,
             st: 0,
 
-            dt_end: 0,
-            st_end: 0,
+            timer_clock: time::precise_time_ns(),
+            timer_elapsed: 0,
+
+            cycles: 0,
+            frames: 0,
+
+            // SUPER-CHIP flag registers.
+            hp48_flags: [0; FLAG_COUNT],
+            flag_storage: None,
+            quicksave_slot: None,
+            rewind_buffer: VecDeque::new(),
+            rewind_capacity: DEFAULT_REWIND_FRAMES,
+            input_recording: None,
+            screenshot_scale: DEFAULT_SCREENSHOT_SCALE,
+            input_replay: None,
+
+            // Plain CHIP-8 unless requested.
+            extensions: Extensions::default(),
+            quirks: Quirks::default(),
+
+            timing: TimingProfile::default(),
+            pacing: Pacing::default(),
+            next_instruction: 0,
+            cycles_per_frame: None,
+            frame_cycles: 0,
+            turbo: false,
+            speed: 1.0,
+            title_fps: None,
+            fps_time: 0,
+            fps_frames: 0,
+
+            changelog: None,
+            reference: None,
+            trace: None,
+            halted: false,
+            waiting_for_key: false,
+            paused: false,
+            breakpoints: HashSet::new(),
+            skip_breakpoint: false,
+
+            rng: CpuRng(rng),
         }
     }
 
-    /// Execute instructions from ram.
-    pub fn run(&mut self) {
-        loop {
-            // Interconnect can signal the emulator to halt.
-            // This is because interconnect works with the native window system
-            // and handles close events.
-            if self.interconnect.input.close_requested {
-                break
-            }
+    /// The peripherals the cpu is connected to.
+    pub fn interconnect(&self) -> &Interconnect {
+        &self.interconnect
+    }
 
-            // Read a word from ram where the program counter currently points
-            // to execute.
-            let word = self.interconnect.memory.read_word(self.pc);
+    /// The peripherals the cpu is connected to, for changing them.
+    pub fn interconnect_mut(&mut self) -> &mut Interconnect {
+        &mut self.interconnect
+    }
 
-            // Execute until the subroutine ends if we are in one.
-            if self.execute_instruction(word) {
-                break
-            }
+    /// Checks every step against the passed trace and halts on the first
+    /// step that doesn't match.
+    pub fn compare_against(&mut self, reference: ReferenceTrace) {
+        self.reference = Some(reference);
+    }
 
-            // Poll for input and set the input state.
-            self.interconnect.input.handle_input();
+    /// Starts recording the changes made by every step.
+    pub fn record_changes(&mut self, changelog: Changelog) {
+        self.changelog = Some(changelog);
+    }
 
-            // Monitor the beeping state.
-            self.interconnect.sound.handle_sound();
-        }
+    /// Logs every executed instruction to the passed writer, one line each
+    /// with the address, the instruction and the registers afterwards:
+    ///
+    /// ```text
+    /// 0x0200 6a02 LD VA, 0x02     i=0x000 v=00 00 00 00 00 00 00 00 00 00 02 00 00 00 00 00
+    /// ```
+    pub fn trace_to(&mut self, out: Box<dyn io::Write>) {
+        self.trace = Some(TraceSink(out));
     }
 
-    #[inline(always)]
-    fn execute_instruction(&mut self, instr: u16) -> bool {
-        self.handle_timers();
+    /// Changes how long each instruction takes to execute.
+    pub fn set_timing(&mut self, timing: TimingProfile) {
+        self.timing = timing;
+    }
 
-        let opcode = (instr >> 12) as u8;
-        let mut ret: bool = false;
-        let mut skip: bool = false;
+    /// Runs the passed number of instructions per second, every instruction
+    /// taking the same time. The timers keep counting down at 60Hz.
+    pub fn set_clock_hz(&mut self, hz: u64) {
+        self.timing = TimingProfile::clock(hz);
+    }
 
-        match opcode {
-            0x0 => {
-                let identifier = ((instr << 8) >> 8) as u8;
+    /// Executes a fixed number of instructions every 60Hz frame instead of
+    /// following the timing profile. The timers tick once at the end of
+    /// each frame, whatever the time the instructions took.
+    pub fn set_cycles_per_frame(&mut self, cycles: u64) {
+        self.cycles_per_frame = Some(cycles);
+    }
 
-                match identifier {
-                    0xE0 => {
-                        // 00E0 - CLS
-                        // Clears the screen.
+    /// Runs instructions as fast as the host allows and stops drawing frames.
+    /// The timers count down by the time the timing profile says each
+    /// instruction takes, or a tick every frame with cycles per frame, so
+    /// runs come out the same every time.
+    pub fn enable_turbo(&mut self) {
+        self.turbo = true;
+    }
 
-                        self.interconnect.graphics.clear_display();
-                    },
-                    0xEE => {
-                        // 00EE - RET
-                        // Returns from a subroutine.
+    /// Runs instructions `multiplier` times as fast as the timing profile or
+    /// cycles per frame would, within 0.25 to 8 times. The window title
+    /// shows the speed whenever it isn't normal.
+    pub fn set_speed_multiplier(&mut self, multiplier: f64) {
+        self.speed = multiplier.clamp(MIN_SPEED, MAX_SPEED);
+        self.update_title();
+    }
 
-                        self.sp -= 1;
-                        self.pc = self.stack[self.sp as usize];
-                        self.stack[self.sp as usize] = 0;
+    /// Shows the frame rate and whether execution is paused in the window
+    /// title, along with the speed. The frame rate is measured once a second.
+    pub fn show_title_status(&mut self) {
+        self.title_fps = Some(0.0);
+        self.fps_time = time::precise_time_ns();
+        self.fps_frames = self.frames;
+        self.update_title();
+    }
 
-                        // Break out of the execution loop for the current
-                        // subroutine.
-                        ret = true;
-                    },
-                    _ => {
-                        // 0NNN - SYS NNN
-                        //
-                        // Jump to a machine code routine at NNN. This operation
-                        // is not implemented on purpose.
-
-                        panic!("Unhandled, 0NNN is not implemented in most \
-                               modern interpreters and is not used by many \
-                               roms.");
-                    },
-                }
-            },
-            0x1 => {
-                // 1NNN - JP NNN
-                //
-                // Jumps to address NNN.
+    /// Changes how the cpu waits between instructions.
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacing = pacing;
+    }
 
-                let addr = ((instr << 4) >> 4) as u16;
-                self.pc = addr;
-                skip = true;
-            },
-            0x2 => {
-                // 2NNN - CALL NNN
-                //
-                // Calls subroutine at NNN.
+    /// Switches to the Chip-8X instruction set, which adds color instructions
+    /// and drops BNNN to make room for them.
+    pub fn enable_chip8x(&mut self) {
+        self.extensions.chip8x = true;
+    }
 
-                let addr = ((instr << 4) >> 4) as u16;
+    /// Enables the Super-CHIP instructions. Plain CHIP-8 roms are unaffected
+    /// since they don't use them.
+    pub fn enable_schip(&mut self) {
+        self.extensions.schip = true;
+    }
 
-                // Add the current program counter to the call stack.
-                self.stack[self.sp as usize] = self.pc;
-                self.sp += 1;
+    /// Enables the XO-CHIP long load and drawing planes. The interconnect has
+    /// to be created with XO_RAM_SIZE bytes of ram for I to reach past 4kB.
+    pub fn enable_xochip(&mut self) {
+        self.extensions.xochip = true;
+    }
 
-                // Set the program counter to the call address begin executing
-                // the subroutine.
-                self.pc = addr;
-                self.run();
-            },
-            0x3 => {
-                // 3XNN - SE VX, NN
-                //
-                // Skips the next instruction if VX equals NN.
+    /// Chooses how the instructions interpreters disagree on behave. Sprite
+    /// clipping is handed on to graphics.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.interconnect.graphics.set_clipping(quirks.clip_sprites);
+    }
 
-                let regx = ((instr << 4) >> 12) as u8;
-                let byte = ((instr << 8) >> 8) as u8;
-                let x = self.get_reg(regx);
+    /// Sets every general purpose register to the same value. Used to mimic
+    /// interpreters that don't clear the registers at boot.
+    pub fn fill_registers(&mut self, byte: u8) {
+        self.v = [byte; 16];
+    }
 
-                if x == byte {
-                    self.pc += INSTRUCTION_SIZE;
-                }
-            },
-            0x4 => {
-                // 4XNN - SNE VX, NN
-                //
-                // The interpreter compares register VX to NN, and if they are
-                // not equal, increments the program counter by 2.
+    /// Loads the flag registers from storage and writes them back whenever a
+    /// rom changes them.
+    pub fn persist_flags(&mut self, storage: FlagStorage) {
+        self.hp48_flags = storage.load();
+        self.flag_storage = Some(storage);
+    }
 
-                let regx = ((instr << 4) >> 12) as u8;
-                let byte = ((instr << 8) >> 8) as u8;
-                let x = self.get_reg(regx);
+    /// Keeps quicksaves in the passed slot.
+    pub fn set_quicksave_slot(&mut self, slot: StateSlot) {
+        self.quicksave_slot = Some(slot);
+    }
 
-                if x != byte {
-                    self.pc += INSTRUCTION_SIZE;
-                }
-            },
-            0x5 => {
-                // 5XY0 - SE VX, VY
-                //
-                // Skip the next instruction if VX == VY.
+    /// Changes how many frames can be rewound, 0 disables rewinding. Frames
+    /// past the new capacity are dropped, oldest first.
+    pub fn set_rewind_capacity(&mut self, frames: usize) {
+        self.rewind_capacity = frames;
+        while self.rewind_buffer.len() > frames {
+            self.rewind_buffer.pop_front();
+        }
+    }
 
-                let regx = ((instr << 4) >> 12) as u8;
-                let regy = ((instr << 8) >> 12) as u8;
-                let x = self.get_reg(regx);
-                let y = self.get_reg(regy);
+    /// Starts logging the keys held during every frame, replacing any
+    /// recording already in progress.
+    pub fn start_recording(&mut self) {
+        self.input_recording = Some(InputLog::default());
+    }
 
-                if x == y {
-                    self.pc += INSTRUCTION_SIZE;
-                }
-            },
-            0x6 => {
-                // 6XNN - LD VX, NN
-                //
-                // Sets VX to NN.
+    /// Stops recording input and returns everything recorded so far. The log
+    /// is empty if nothing was being recorded.
+    pub fn stop_recording(&mut self) -> InputLog {
+        self.input_recording.take().unwrap_or_default()
+    }
 
-                let regx = ((instr << 4) >> 12) as u8;
-                let byte = ((instr << 8) >> 8) as u8;
-                self.set_reg(regx, byte);
-            },
-            0x7 => {
-                // 7XNN - ADD VX, NN
-                //
-                // Adds NN to VX.
+    /// Plays back recorded input frame by frame in place of the keyboard and
+    /// input providers. Live input takes over again once the log runs out.
+    pub fn replay(&mut self, log: InputLog) {
+        self.interconnect.input.replay(log.frame(0));
+        self.interconnect.input.next_frame();
+        self.input_replay = Some((log, 1));
+    }
 
-                let regx = ((instr << 4) >> 12) as u8;
-                let byte = ((instr << 8) >> 8) as u8;
-                let result = self.get_reg(regx).wrapping_add(byte);
-                self.set_reg(regx, result);
-            },
-            0x8 => {
-                let regx = ((instr << 4) >> 12) as u8;
-                let regy = ((instr << 8) >> 12) as u8;
-                let identifier = ((instr << 12) >> 12) as u8;
+    /// Changes how many times larger than the display screenshots are.
+    pub fn set_screenshot_scale(&mut self, scale: usize) {
+        self.screenshot_scale = scale;
+    }
 
-                match identifier {
-                    0x0 => {
-                        // 8XY0 - LD VX, VY
-                        //
-                        // Sets VX to VY.
+    /// Execute instructions from ram until execution halts or the window is
+    /// closed, or a breakpoint is reached when there is no window to pause
+    /// in. Instructions that can't be executed stop execution with an
+    /// error, after the cpu state has been dumped.
+    pub fn run(&mut self) -> Result<Stopped, CpuError> {
+        self.run_until(None).map(|(_, stopped)| stopped)
+    }
 
-                        let y = self.get_reg(regy);
-                        self.set_reg(regx, y);
-                    },
-                    0x1 => {
-                        // 8XY1 - OR VX, VY
-                        //
-                        // Sets VX to VX or VY.
-
-                        let x = self.get_reg(regx);
-                        let y = self.get_reg(regy);
-                        self.set_reg(regx, x | y);
-                    },
-                    0x2 => {
-                        // 8XY2 - AND VX, VY
-                        //
-                        // Sets VX to VX and VY.
-
-                        let x = self.get_reg(regx);
-                        let y = self.get_reg(regy);
-                        self.set_reg(regx, x & y);
-                    },
-                    0x3 => {
-                        // 8XY3 - XOR VX, VY
-                        //
-                        // Sets VX to VX xor VY.
-
-                        let x = self.get_reg(regx);
-                        let y = self.get_reg(regy);
-                        self.set_reg(regx, x ^ y);
-                    },
-                    0x4 => {
-                        // 8XY4 - ADD VX, VY
-                        //
-                        // The values of VX and VY are added together. If the
-                        // result is greater than 8 bits (i.e., > 255,) VF is
-                        // set to 1, otherwise 0. Only the lowest 8 bits of the
-                        // result are kept, and stored in VX.
-
-                        let x = self.get_reg(regx) as u16;
-                        let y = self.get_reg(regy) as u16;
-
-                        let result = x.wrapping_add(y);
-                        if result > 255 {
-                            self.vf = 1;
-                        } else {
-                            self.vf = 0;
-                        }
-                        self.set_reg(regx, result as u8);
-                    },
-                    0x5 => {
-                        // 8XY5 - SUB VX, VY
-                        //
-                        // If VX > VY, then VF is set to 1, otherwise 0. Then
-                        // VY is subtracted from VX, and the results stored in
-                        // VX.
-
-                        let x = self.get_reg(regx) as u16;
-                        let y = self.get_reg(regy) as u16;
-
-                        if x > y {
-                            self.vf = 1;
-                        } else {
-                            self.vf = 0;
-                        }
-                        let result = x.wrapping_sub(y);
-                        self.set_reg(regx, result as u8);
-                    },
-                    0x6 => {
-                        // 8XY6 - SHR VX {, VY}
-                        //
-                        // If the least-significant bit of VX is 1, then VF is
-                        // set to 1, otherwise 0. Then VX is divided by 2.
+    /// Works like `run` but also stops once `cycles` instructions have been
+    /// executed, single steps taken while paused included. Returns how many
+    /// instructions ran, fewer than `cycles` when execution stopped first,
+    /// and why it stopped.
+    pub fn run_for(&mut self, cycles: u64) -> Result<(u64, Stopped), CpuError> {
+        self.run_until(Some(cycles))
+    }
 
-                        let x = self.get_reg(regx);
-                        let lsb = x & 0x1;
+    /// The execution loop behind `run` and `run_for`, stopping after `limit`
+    /// instructions when set.
+    fn run_until(&mut self, limit: Option<u64>) -> Result<(u64, Stopped), CpuError> {
+        let mut executed = 0;
 
-                        self.vf = lsb;
-                        self.set_reg(regx, x.wrapping_div(2));
-                    },
-                    0x7 => {
-                        // 8XY7 - SUBN VX, VY
-                        //
-                        // If VY > VX, then VF is set to 1, otherwise 0. Then
-                        // VX is subtracted from VY, and the results stored in
-                        // VX.
-
-                        let x = self.get_reg(regx);
-                        let y = self.get_reg(regy);
-
-                        if y > x {
-                            self.vf = 1;
-                        } else {
-                            self.vf = 0;
-                        }
-                        let result = y.wrapping_sub(x);
-                        self.set_reg(regx, result);
-                    },
-                    0xe => {
-                        // 8XYE - SHL VX {, VY}
-                        //
-                        // If the most-significant bit of VX is 1, then VF is
-                        // set to 1, otherwise to 0. Then VX is multiplied by 2.
+        loop {
+            // Interconnect can signal the emulator to halt.
+            // This is because interconnect works with the native window system
+            // and handles close events.
+            if self.interconnect.input.close_requested {
+                return Ok((executed, Stopped::Closed));
+            }
+            if self.halted {
+                return Ok((executed, Stopped::Halted));
+            }
+            if limit == Some(executed) {
+                return Ok((executed, Stopped::CycleLimit));
+            }
 
-                        let x = self.get_reg(regx);
-                        let msb = (x & 0x80) >> 7;
+            // Restart the rom when requested through the window.
+            if self.interconnect.input.reset_requested {
+                self.interconnect.input.reset_requested = false;
+                self.reset();
+            }
 
-                        self.vf = msb;
-                        self.set_reg(regx, x.wrapping_mul(2));
-                    },
-                    _ => {
-                        println!("cpu: {:#?}", self);
-                        panic!("Found unknown identifier at instruction: {:#x}, addr: {:#x}", instr, self.pc);
-                    },
-                }
-            },
-            0x9 => {
-                // 9XY0 - SNE VX, VY
-                //
-                // Skip the next instruction if VX != VY.
+            if self.interconnect.input.fullscreen_requested {
+                self.interconnect.input.fullscreen_requested = false;
+                self.interconnect.graphics.toggle_fullscreen();
+            }
 
-                let regx = ((instr << 4) >> 12) as u8;
-                let regy = ((instr << 8) >> 12) as u8;
-                let x = self.get_reg(regx);
-                let y = self.get_reg(regy);
+            if self.interconnect.input.screenshot_requested {
+                self.interconnect.input.screenshot_requested = false;
+                self.save_screenshot();
+            }
 
-                if x != y {
-                    self.pc += INSTRUCTION_SIZE;
+            if self.interconnect.input.quicksave_requested {
+                self.interconnect.input.quicksave_requested = false;
+                self.quicksave();
+            }
+            if self.interconnect.input.quickload_requested {
+                self.interconnect.input.quickload_requested = false;
+                self.quickload();
+            }
+
+            if self.interconnect.input.speed_up_requested {
+                self.interconnect.input.speed_up_requested = false;
+                let speed = self.speed * 2.0;
+                self.set_speed_multiplier(speed);
+            }
+            if self.interconnect.input.slow_down_requested {
+                self.interconnect.input.slow_down_requested = false;
+                let speed = self.speed / 2.0;
+                self.set_speed_multiplier(speed);
+            }
+            if self.interconnect.input.normal_speed_requested {
+                self.interconnect.input.normal_speed_requested = false;
+                self.set_speed_multiplier(1.0);
+            }
+
+            if self.interconnect.input.pause_requested {
+                self.interconnect.input.pause_requested = false;
+                if self.paused {
+                    self.resume();
+                } else {
+                    self.pause();
                 }
-            },
-            0xa => {
-                // ANNN - LD I, NNN
-                //
-                // Sets I to the address NNN.
+            }
 
-                let addr = ((instr << 4) >> 4) as u16;
-                self.i = addr;
-            },
-            0xb => {
-                // BNNN - JP V0, NNN
-                //
-                // The program counter is set to NNN plus the value of V0.
+            if self.interconnect.input.breakpoint_requested {
+                self.interconnect.input.breakpoint_requested = false;
+                if self.paused {
+                    self.toggle_breakpoint();
+                }
+            }
 
-                let addr = ((instr << 4) >> 4) as u16;
-                self.pc = addr.wrapping_add(self.v0 as u16);
-                skip = true;
-            },
-            0xc => {
-                // CXNN - RND VX, NN
-                //
-                // Sets VX to the result of a bitwise and operation on a
-                // random number and NN.
+            // Single steps are only taken while paused, a request made while
+            // running is dropped.
+            let step_requested = self.interconnect.input.step_requested;
+            self.interconnect.input.step_requested = false;
+
+            // Keep the window responsive while paused without executing
+            // anything but the requested single steps. The timers stay
+            // frozen, none of the time spent between steps counts.
+            if self.paused {
+                if step_requested {
+                    let now = time::precise_time_ns();
+                    self.timer_clock = now;
+                    self.next_instruction = now;
+
+                    if let Err(why) = self.step() {
+                        print!("{}", self.dump());
+                        return Err(why);
+                    }
+                    executed += 1;
+                    print!("{}", self.dump());
+                }
 
-                let regx = ((instr << 4) >> 12) as u8;
-                let byte = ((instr << 8) >> 8) as u8;
-                let rnd = random::<u8>();
-                self.set_reg(regx, rnd & byte);
+                self.interconnect.input.handle_input();
+                self.interconnect.graphics.present();
+                sleep(Duration::from_millis(PAUSE_POLL_DELAY));
+                continue
             }
-            0xd => {
-                // DXYN - DRW VX, VY, N
-                //
-                // Sprites stored in memory at location in index register (I),
-                // 8bits wide. Wraps around the screen. If when drawn, clears a
-                // pixel, register VF is set to 1 otherwise it is zero.
-                //
-                // All drawing is XOR drawing (i.e. it toggles the screen
-                // pixels). Sprites are drawn starting at position VX, VY. N is
-                // the number of 8bit rows that need to be drawn. If N is
-                // greater than 1, second line continues at position VX, VY+1,
-                // and so on.
 
-                let regx = ((instr << 4) >> 12) as u8;
-                let regy = ((instr << 8) >> 12) as u8;
-                let nibble = ((instr << 12) >> 12) as usize;
+            // Step backwards a frame at a time for as long as the rewind key
+            // is held.
+            if self.interconnect.input.rewind_held {
+                if self.interconnect.sound.beeping() {
+                    self.interconnect.sound.stop_beep();
+                }
+                self.rewind();
+                self.interconnect.input.handle_input();
+                self.interconnect.graphics.present();
+                sleep(Duration::new(0, TIMER_PERIOD as u32));
+                continue
+            }
 
-                // Read N (nibble) bytes out out of memory starting at address
-                // register I into our sprite.
-                let mut sprite = vec![0 as u8; nibble];
-                for i in 0..nibble {
-                    sprite[i] = self.interconnect.memory.read(self.i as usize + i);
+            // Stop before the instruction at a breakpoint is executed, jumps
+            // and calls included, so the state leading up to it can be
+            // inspected. Without a window nothing could resume a pause, so
+            // execution returns to the caller instead.
+            if !self.skip_breakpoint && self.breakpoints.contains(&self.pc) {
+                println!("notch: breakpoint at {:#06x}", self.pc);
+                print!("{}", self.dump());
+                if !self.interconnect.input.has_keyboard() {
+                    self.skip_breakpoint = true;
+                    return Ok((executed, Stopped::Breakpoint(self.pc)));
                 }
+                self.pause();
+                continue
+            }
+            self.skip_breakpoint = false;
 
-                // Get screen coordinates from the requested registers.
-                let x = self.get_reg(regx);
-                let y = self.get_reg(regy);
+            if let Err(why) = self.step() {
+                print!("{}", self.dump());
+                return Err(why);
+            }
+            executed += 1;
 
-                // Draw the sprite and store collision detection results in vf.
-                self.vf = self.interconnect.graphics.draw(x as usize, y as usize, sprite);
-            },
-            0xe => {
-                let regx = ((instr << 4) >> 12) as u8;
-                let identifier = ((instr << 8) >> 8) as u8;
-
-                match identifier {
-                    0x9e => {
-                        // EX9E - SKP VX
-                        //
-                        // Skips the next instruction if the key stored in VX
-                        // is pressed.
-
-                        let x = self.get_reg(regx);
-                        if self.interconnect.input.input_state[x as usize] {
-                            self.pc += INSTRUCTION_SIZE;
-                        }
-                    },
-                    0xa1 => {
-                        // EXA1 - SKNP VX
-                        //
-                        // Skips the next instruction if the key stored in VX
-                        // isn't pressed.
-
-                        let x = self.get_reg(regx);
-                        if !self.interconnect.input.input_state[x as usize] {
-                            self.pc += INSTRUCTION_SIZE;
-                        }
-                    },
-                    _ => {
-                        println!("cpu: {:#?}", self);
-                        panic!("Found unknown identifier at instruction: {:#x}, addr: {:#x}", instr, self.pc);
-                    },
-                }
-            },
-            0xf => {
-                let regx = ((instr << 4) >> 12) as u8;
-                let identifier = ((instr << 8) >> 8) as u8;
+            // Poll for input, the keypad follows it once the frame ends.
+            self.interconnect.input.handle_input();
 
-                match identifier {
-                    0x07 => {
-                        // FX07 - LD VX, DT
-                        //
-                        // Sets VX to the value of the delay timer.
+            // Keep the audio recording up to date.
+            self.interconnect.sound.handle_sound();
+        }
+    }
 
-                        let dt = self.dt;
-                        self.set_reg(regx, dt);
-                    },
-                    0x0a => {
-                        // FX0A - LD VX, N
-                        //
-                        // All execution stops until a key is pressed, then the
-                        // value of that key is stored in VX.
+    /// Fetches and executes the instruction at the program counter, exactly
+    /// one. The step is recorded, traced and compared against the reference
+    /// like any other. Nothing is executed once the reference halted
+    /// execution.
+    pub fn step(&mut self) -> Result<(), CpuError> {
+        // Make sure execution still matches the reference, if any.
+        self.check_reference();
+        if self.halted {
+            return Ok(());
+        }
 
-                        let key = self.interconnect.input.wait_input();
-                        self.set_reg(regx, key);
-                    },
-                    0x15 => {
-                        // FX15 - LD DT, VX
-                        //
-                        // Sets the delay timer to VX.
+        // Read a word from ram where the program counter currently points
+        // to execute.
+        let pc = self.pc as usize;
+        self.check_ram(pc, INSTRUCTION_SIZE as usize)?;
+        let word = self.interconnect.memory.read_word(self.pc);
 
-                        let x = self.get_reg(regx);
-                        self.dt = x;
-                    },
-                    0x18 => {
-                        // FX18 - LD ST, VX
-                        //
-                        // ST is set equal to the value of VX.
+        // Capture the state beforehand when changes are being recorded.
+        let before = self.changelog.as_ref().map(|_| self.snapshot());
 
-                        let x = self.get_reg(regx);
-                        self.st = x;
-                    },
-                    0x1e => {
-                        // FX1E - ADD I, VX
-                        //
-                        // The values of I and VX are added, and the results
-                        // are stored in I.
-
-                        let x = self.get_reg(regx);
-                        self.i = self.i.wrapping_add(x as u16);
-                    },
-                    0x29 => {
-                        // FX29 - LD F, VX
-                        //
-                        // Sets I to the location of the sprite for the
-                        // character in VX. Characters 0-F (in hexadecimal) are
-                        // represented by a 4x5 font.
-
-                        let x = self.get_reg(regx);
-                        self.i = self.interconnect.memory.get_font(x);
-                    },
-                    0x33 => {
-                        // FX33 - LD B, VX
-                        //
-                        // Stores the Binary-coded decimal representation of VX,
-                        // with the most significant of three digits at the
-                        // address in I, the middle digit at I plus 1, and the
-                        // least significant digit at I plus 2. (In other words,
-                        // take the decimal representation of VX, place the
-                        // hundreds digit in memory at location in I, the tens
-                        // digit at location I+1, and the ones digit at
-                        // location I+2.)
-
-                        const DECIMAL_LENGTH: usize = 3;
-
-                        let mut x = self.get_reg(regx);
-                        let mut digits = vec![0 as u8; DECIMAL_LENGTH];
-                        let mut digit_count: usize = 0;
-
-                        // Organize the digits in the decimal into a slice.
-                        while x > 0 {
-                            digit_count += 1;
-                            digits[DECIMAL_LENGTH - digit_count] = x % 10;
-                            x /= 10;
-                        }
-
-                        // Set I, I+1, and I+3 to the values of the digits.
-                        let i = self.i as usize;
-                        self.interconnect.memory.write(i, digits[0]);
-                        self.interconnect.memory.write(i + 1, digits[1]);
-                        self.interconnect.memory.write(i + 2, digits[2]);
-                    },
-                    0x55 => {
-                        // FX55 - LD [I], VX
-                        //
-                        // The interpreter copies the values of registers V0
-                        // through VX into memory, starting at the address in I.
-
-                        let i = self.i as usize;
-                        let end_reg = (regx + 1) as usize;
-
-                        for register in 0x0..end_reg {
-                            let val = self.get_reg(register as u8);
-                            self.interconnect.memory.write(i + register, val);
-                        }
-                    },
-                    0x65 => {
-                        // FX65 - LD VX, [I]
-                        //
-                        // Fills V0 to VX with values from memory starting at
-                        // address I.
-
-                        let i = self.i as usize;
-                        let end_reg = (regx + 1) as usize;
-
-                        for register in 0x0..end_reg {
-                            let mem = self.interconnect.memory.read(i + register);
-                            self.set_reg(register as u8, mem);
-                        }
-                    },
-                    _ => {
-                        println!("cpu: {:#?}", self);
-                        panic!("Found unknown identifier at instruction: {:#x}, addr: {:#x}", instr, self.pc);
-                    },
-                }
-            },
-            _ => {
-                println!("cpu: {:#?}", self);
-                panic!("Found unknown opcode at instruction: {:#x}, addr: {:#x}", instr, self.pc);
-            },
+        self.execute_instruction(word)?;
+        self.cycles += 1;
+
+        if let Some(before) = before {
+            self.record_step(&before);
+        }
+
+        if self.trace.is_some() {
+            self.trace_step(pc as u16, word);
         }
 
-        // Increment the program counter to the next instruction.
-        if !ret && !skip {
-            self.pc += INSTRUCTION_SIZE;
+        Ok(())
+    }
+
+    /// Restarts the loaded rom. The cpu goes back to its state at boot, the
+    /// display is cleared back to low resolution and the fonts are restored,
+    /// but the rest of ram is left as it is, so the program itself is not
+    /// reloaded. A paused machine runs again, the rewind buffer is emptied
+    /// and input recordings and replays start over from the first frame.
+    pub fn reset(&mut self) {
+        self.pc = END_RESERVED as u16;
+        self.stack = [0; 16];
+        self.sp = 0;
+        self.v = [0; 16];
+        self.i = 0;
+        self.dt = 0;
+        self.st = 0;
+        self.timer_elapsed = 0;
+        self.frame_cycles = 0;
+        self.cycles = 0;
+        self.frames = 0;
+        self.halted = false;
+        self.waiting_for_key = false;
+        self.paused = false;
+        self.rewind_buffer.clear();
+
+        if let Some(ref mut recording) = self.input_recording {
+            *recording = InputLog::default();
+        }
+        if let Some((ref log, ref mut frame)) = self.input_replay {
+            self.interconnect.input.replay(log.frame(0));
+            self.interconnect.input.next_frame();
+            *frame = 1;
         }
 
-        // By default the execution loop in not broken. True will be returned
-        // only by a successful RET instruction is executed.
-        ret
+        self.interconnect.graphics.set_hires(false);
+        self.interconnect.graphics.reset_planes();
+        self.interconnect.memory.reload_fonts();
+        self.interconnect.sound.stop_beep();
+        self.update_title();
     }
 
-    /// Handle the delay timer and play sounds.
-    fn handle_timers(&mut self) {
-        let current_time = time::precise_time_ns() / 1000000;
+    /// Stops executing instructions and freezes the timers until resumed.
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.interconnect.sound.stop_beep();
+        self.update_title();
+    }
 
-        let dt_enabled = self.dt > 0;
-        let st_enabled = self.st > 0;
+    /// Continues execution after a pause. The time spent paused is not
+    /// counted by the timers or the instruction schedule.
+    pub fn resume(&mut self) {
+        let now = time::precise_time_ns();
+        self.timer_clock = now;
+        self.next_instruction = now;
+        self.paused = false;
+        self.skip_breakpoint = true;
+
+        // Time spent paused doesn't count towards the frame rate.
+        self.fps_time = now;
+        self.fps_frames = self.frames;
+        self.update_title();
+    }
 
-        if st_enabled {
-            self.interconnect.sound.beeping = true;
+    /// Pauses execution before the instruction at the passed address runs.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
 
-            if current_time >= self.st_end {
-                self.st -= 1;
-                self.st_end = current_time + TIMER_DELAY;
-            }
+    /// Removes the breakpoint at the passed address, if there is one.
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    /// Sets a breakpoint at the program counter or clears the one already
+    /// there.
+    fn toggle_breakpoint(&mut self) {
+        let pc = self.pc;
+        if self.breakpoints.contains(&pc) {
+            self.remove_breakpoint(pc);
+            println!("notch: cleared breakpoint at {:#06x}", pc);
         } else {
-            self.interconnect.sound.beeping = false;
+            self.add_breakpoint(pc);
+            println!("notch: set breakpoint at {:#06x}", pc);
         }
+    }
 
-        if dt_enabled {
-            // TODO: Replace with time check.
-            //sleep(Duration::from_millis(TIMER_DELAY));
+    /// Finalizes everything execution was writing to: the audio and GIF
+    /// recordings, the changelog and the persisted flags. Called once
+    /// execution halts so none of the files are left truncated.
+    pub fn shutdown(&mut self) {
+        self.interconnect.sound.shutdown();
+
+        if let Err(why) = self.interconnect.stop_gif() {
+            println!("notch: cannot finish GIF: {}", why);
+        }
 
-            if current_time >= self.dt_end {
-                self.dt -= 1;
-                self.dt_end = current_time + TIMER_DELAY;
+        if let Some(mut changelog) = self.changelog.take() {
+            if let Err(why) = changelog.finish() {
+                println!("notch: cannot finish changelog: {}", why);
             }
         }
 
-        sleep(Duration::from_millis(EXECUTION_DELAY));
-    }
+        if let Some(mut trace) = self.trace.take() {
+            if let Err(why) = trace.0.flush() {
+                println!("notch: cannot finish trace: {}", why);
+            }
+        }
 
-    /// Gets the value at a specified register.
-    fn get_reg(&mut self, reg: u8) -> u8 {
-        match reg {
-            0x0 => self.v0,
-            0x1 => self.v1,
-            0x2 => self.v2,
-            0x3 => self.v3,
-            0x4 => self.v4,
-            0x5 => self.v5,
-            0x6 => self.v6,
-            0x7 => self.v7,
-            0x8 => self.v8,
-            0x9 => self.v9,
-            0xa => self.va,
-            0xb => self.vb,
-            0xc => self.vc,
-            0xd => self.vd,
-            0xe => self.ve,
-            0xf => self.vf,
-            _ => {
-                panic!("Cannot get unknown register: V{:X}", reg);
+        if let Some(ref storage) = self.flag_storage {
+            if let Err(why) = storage.save(&self.hp48_flags) {
+                println!("notch: cannot save flags: {}", why);
             }
         }
     }
 
+    /// Saves the machine state: registers, timers, flags, ram, the display
+    /// with its planes and colors, and the keypad. The result can be written
+    /// to a file and loaded back later.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.capture_state().encode()
+    }
+
+    /// Puts the machine back into a saved state. Nothing changes when the
+    /// state can't be read. Execution continues from the saved program
+    /// counter, without counting the time since the state was saved.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let state = MachineState::decode(bytes)?;
+        if state.ram.len() != self.interconnect.memory.size() {
+            return Err(StateError::Invalid { reason: "ram is not the size of this machine's" });
+        }
+        self.restore_state(&state);
+
+        Ok(())
+    }
+
+    /// Saves a screenshot to the working directory, named after the time it
+    /// was taken.
+    fn save_screenshot(&mut self) {
+        let stamp = time::strftime("%Y%m%d-%H%M%S", &time::now()).unwrap();
+        let path = format!("notch-{}.png", stamp);
+
+        match self.interconnect.screenshot(&path, self.screenshot_scale) {
+            Ok(()) => println!("notch: saved screenshot to {}", path),
+            Err(why) => println!("notch: cannot save screenshot: {}", why),
+        }
+    }
+
+    /// Saves the machine state to the quicksave slot. Failing to save only
+    /// reports the problem, the game keeps running.
+    fn quicksave(&mut self) {
+        let slot = match self.quicksave_slot {
+            Some(ref slot) => slot,
+            None => return,
+        };
+
+        match slot.save(&self.save_state()) {
+            Ok(()) => println!("notch: saved state to {}", slot.path().display()),
+            Err(why) => println!("notch: cannot save state: {}", why),
+        }
+    }
+
+    /// Restores the machine state from the quicksave slot. An empty slot or
+    /// a bad state only reports the problem and leaves the machine alone.
+    fn quickload(&mut self) {
+        let state = match self.quicksave_slot {
+            Some(ref slot) => match slot.load() {
+                Ok(Some(state)) => state,
+                Ok(None) => {
+                    println!("notch: nothing saved in {} yet", slot.path().display());
+                    return;
+                },
+                Err(why) => {
+                    println!("notch: cannot load state: {}", why);
+                    return;
+                },
+            },
+            None => return,
+        };
+
+        match self.load_state(&state) {
+            Ok(()) => println!("notch: loaded state"),
+            Err(why) => println!("notch: cannot load state: {}", why),
+        }
+    }
+
+    /// Goes back to the state at the start of the most recent frame in the
+    /// rewind buffer, removing it from the buffer. Returns false once there
+    /// is nothing left to rewind.
+    pub fn rewind(&mut self) -> bool {
+        match self.rewind_buffer.pop_back() {
+            Some(state) => {
+                self.restore_state(&state);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Moves input on to the next frame: the keys of the frame that just
+    /// ended are logged, and the keypad state of the next frame is taken
+    /// from the next frame of a replay or from live input.
+    fn advance_input_frame(&mut self) {
+        if let Some(ref mut recording) = self.input_recording {
+            recording.push(&self.interconnect.input.input_state);
+        }
+
+        let finished = match self.input_replay {
+            Some((ref log, ref mut frame)) => {
+                let keys = log.frame(*frame);
+                self.interconnect.input.replay(keys);
+                *frame += 1;
+                keys.is_none()
+            },
+            None => false,
+        };
+        self.interconnect.input.next_frame();
+
+        if finished {
+            let frames = self.input_replay.take().map_or(0, |(log, _)| log.len());
+            println!("notch: replayed all {} input frames", frames);
+        }
+    }
+
+    /// Keeps the current state for rewinding, dropping the oldest frame once
+    /// the buffer is full.
+    fn push_rewind_frame(&mut self) {
+        if self.rewind_capacity == 0 {
+            return;
+        }
+        if self.rewind_buffer.len() == self.rewind_capacity {
+            self.rewind_buffer.pop_front();
+        }
+
+        let state = self.capture_state();
+        self.rewind_buffer.push_back(state);
+    }
+
+    /// Captures the part of the machine a save state holds.
+    fn capture_state(&self) -> MachineState {
+        MachineState {
+            pc: self.pc,
+            i: self.i,
+            sp: self.sp,
+            stack: self.stack,
+            registers: self.v,
+            dt: self.dt,
+            st: self.st,
+            timer_elapsed: self.timer_elapsed,
+            hp48_flags: self.hp48_flags,
+            ram: self.interconnect.memory.ram().to_vec(),
+            hires: self.interconnect.graphics.hires(),
+            display: self.interconnect.framebuffer().to_vec(),
+            input_state: self.interconnect.input.input_state,
+            halted: self.halted,
+            planes: self.interconnect.graphics.planes(),
+            colors: Some(self.interconnect.graphics.colors()),
+        }
+    }
+
+    /// Applies a captured state to the machine.
+    fn restore_state(&mut self, state: &MachineState) {
+        self.pc = state.pc;
+        self.i = state.i;
+        self.sp = state.sp;
+        self.stack = state.stack;
+        self.v = state.registers;
+        self.dt = state.dt;
+        self.st = state.st;
+        self.timer_elapsed = state.timer_elapsed;
+        self.hp48_flags = state.hp48_flags;
+        self.interconnect.memory.load_ram(&state.ram);
+        self.interconnect.graphics.load_display(state.hires, &state.display);
+        self.interconnect.graphics.set_planes(state.planes);
+        if let Some(ref colors) = state.colors {
+            self.interconnect.graphics.load_colors(colors);
+        }
+        self.interconnect.input.input_state = state.input_state;
+        self.halted = state.halted;
+        self.waiting_for_key = false;
+
+        let now = time::precise_time_ns();
+        self.timer_clock = now;
+        self.next_instruction = now;
+    }
+
+    /// Positions the program counter at an arbitrary address so a specific
+    /// routine can be explored. The address must be inside program space and
+    /// leave room for a full instruction.
+    pub fn warp_to(&mut self, addr: u16) -> Result<(), String> {
+        if !(END_RESERVED..END_PROGRAM_SPACE).contains(&(addr as usize)) {
+            return Err(format!("cannot warp to {:#x}, address is outside of \
+                                program space ({:#x}-{:#x})",
+                               addr, END_RESERVED, END_PROGRAM_SPACE - 1));
+        }
+
+        self.pc = addr;
+        Ok(())
+    }
+
+    /// A copy of the general purpose registers V0-VF.
+    pub fn registers(&self) -> [u8; 16] {
+        self.v
+    }
+
+    /// The program counter, the address of the next instruction.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// The address register I.
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    /// The number of return addresses on the call stack.
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    /// The delay timer.
+    pub fn dt(&self) -> u8 {
+        self.dt
+    }
+
+    /// The sound timer.
+    pub fn st(&self) -> u8 {
+        self.st
+    }
+
+    /// True while the sound timer is running, which is when the beep plays.
+    pub fn is_beeping(&self) -> bool {
+        self.st > 0
+    }
+
+    /// Instructions executed since boot or the last reset.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Frames shown at 60Hz since boot or the last reset. Frames drawn while
+    /// paused or rewinding don't count.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Formats the full cpu state as text: registers, the call stack, timers
+    /// and a short look at ram around PC and I. Used for crash dumps.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "pc: {:#06x}  i: {:#06x}  sp: {}",
+                 self.pc(), self.i(), self.sp()).unwrap();
+        writeln!(out, "dt: {:#04x}    st: {:#04x}", self.dt(), self.st()).unwrap();
+        writeln!(out, "cycles: {}  frames: {}  drawn: {}",
+                 self.cycles(), self.frames(), self.interconnect.graphics.presents()).unwrap();
+        writeln!(out, "last draw erased {} pixels",
+                 self.interconnect.last_collision_count()).unwrap();
+
+        // General purpose registers in a 4x4 grid.
+        let registers = self.registers();
+        for row in 0..4 {
+            for col in 0..4 {
+                let reg = row * 4 + col;
+                write!(out, "v{:x}: {:02x}  ", reg, registers[reg]).unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+
+        // Only the occupied part of the stack is interesting.
+        write!(out, "stack:").unwrap();
+        for addr in &self.stack[..self.sp as usize] {
+            write!(out, " {:#06x}", addr).unwrap();
+        }
+        writeln!(out).unwrap();
+
+        // A compact ram summary, the bytes being executed and addressed.
+        let size = self.interconnect.memory.size();
+        let ram = self.interconnect.peek_range(0, size).unwrap();
+        let used = ram.iter().filter(|&&byte| byte != 0).count();
+        writeln!(out, "ram: {} of {} bytes non-zero", used, size).unwrap();
+        self.dump_ram(&mut out, "pc", self.pc as usize);
+        self.dump_ram(&mut out, "i", self.i as usize);
+
+        out
+    }
+
+    /// Compares the current state with the next step of the reference trace.
+    /// Execution halts on a mismatch or once the trace has been exhausted.
+    fn check_reference(&mut self) {
+        let registers = self.v;
+        let result = match self.reference {
+            Some(ref mut reference) => reference.check(self.pc, self.i, &registers),
+            None => return,
+        };
+
+        match result {
+            TraceCheck::Match => {},
+            TraceCheck::Mismatch(why) => {
+                println!("notch: {}", why);
+                print!("{}", self.dump());
+                self.halted = true;
+            },
+            TraceCheck::Finished => {
+                let steps = self.reference.as_ref().map_or(0, |reference| reference.len());
+                println!("notch: matched all {} reference steps", steps);
+                self.halted = true;
+            },
+        }
+    }
+
+    /// Captures everything an instruction can change.
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc,
+            i: self.i,
+            sp: self.sp,
+            stack: self.stack,
+            registers: self.v,
+            dt: self.dt,
+            st: self.st,
+            ram: self.interconnect.memory.ram().to_vec(),
+            display: self.interconnect.framebuffer().to_vec(),
+            display_width: self.interconnect.graphics.width(),
+        }
+    }
+
+    /// Writes the changes made since the passed snapshot to the changelog.
+    fn record_step(&mut self, before: &Snapshot) {
+        let after = self.snapshot();
+        let failed = match self.changelog {
+            Some(ref mut changelog) => changelog.record(before, &after).is_err(),
+            None => false,
+        };
+
+        if failed {
+            println!("notch: cannot write changelog, stopping the recording");
+            self.changelog = None;
+        }
+    }
+
+    /// Writes the instruction just executed from `addr` and the registers it
+    /// left behind to the trace.
+    fn trace_step(&mut self, addr: u16, instr: u16) {
+        let registers: Vec<String> = self.v.iter().map(|reg| format!("{:02x}", reg)).collect();
+        let line = format!("{:#06x} {:04x} {:<16} i={:#05x} v={}",
+                           addr, instr, disasm::mnemonic(instr, addr), self.i, registers.join(" "));
+
+        let failed = match self.trace {
+            Some(ref mut trace) => writeln!(trace.0, "{}", line).is_err(),
+            None => false,
+        };
+
+        if failed {
+            println!("notch: cannot write trace, stopping the trace");
+            self.trace = None;
+        }
+    }
+
+    /// Writes a line of ram bytes starting at the passed address.
+    fn dump_ram(&self, out: &mut String, label: &str, start: usize) {
+        let end = cmp::min(start + DUMP_RAM_WINDOW, self.interconnect.memory.size());
+
+        write!(out, "  {:<2} {:#06x}:", label, start).unwrap();
+        for addr in start..end {
+            if let Ok(byte) = self.interconnect.peek(addr) {
+                write!(out, " {:02x}", byte).unwrap();
+            }
+        }
+        writeln!(out).unwrap();
+    }
+
+    #[inline(always)]
+    fn execute_instruction(&mut self, instr: u16) -> Result<(), CpuError> {
+        match self.cycles_per_frame {
+            Some(cycles) => self.handle_frame(cycles),
+            None => {
+                self.handle_timers(instr);
+                self.wait_for_instruction(instr);
+            },
+        }
+
+        let decoded = instruction::decode(instr, self.pc, self.extensions)?;
+        let mut skip: bool = false;
+
+        match decoded {
+            Instruction::Cls => {
+                // 00E0 - CLS
+                // Clears the screen.
+
+                self.interconnect.graphics.clear_display();
+            },
+            Instruction::StepBackground => {
+                // 02A0 - Chip-8X: Step background color
+                // Cycles the background through blue, black, green and red.
+
+                self.interconnect.graphics.step_background();
+            },
+            Instruction::ScrollDown { nibble } => {
+                // 00CN - SCD N
+                // Scrolls the display down by N rows.
+
+                self.interconnect.graphics.scroll_down(nibble as usize);
+            },
+            Instruction::ScrollRight => {
+                // 00FB - SCR
+                // Scrolls the display right by 4 pixels.
+
+                self.interconnect.graphics.scroll_right(SCROLL_COLUMNS);
+            },
+            Instruction::ScrollLeft => {
+                // 00FC - SCL
+                // Scrolls the display left by 4 pixels.
+
+                self.interconnect.graphics.scroll_left(SCROLL_COLUMNS);
+            },
+            Instruction::LowRes => {
+                // 00FE - LOW
+                // Switches to the 64x32 display.
+
+                self.interconnect.graphics.set_hires(false);
+            },
+            Instruction::HighRes => {
+                // 00FF - HIGH
+                // Switches to the Super-CHIP 128x64 display.
+
+                self.interconnect.graphics.set_hires(true);
+            },
+            Instruction::Exit => {
+                // 00FD - EXIT
+                // Halts the interpreter.
+
+                self.halted = true;
+            },
+            Instruction::Halt => {
+                // 0000 - HALT
+                //
+                // Not a real instruction, but roms are padded with zeroes and
+                // running off the end of the code lands on them. Execution
+                // stops cleanly instead of carrying on through the padding.
+
+                println!("notch: halted by 0000 at {:#06x}", self.pc);
+                self.halted = true;
+            },
+            Instruction::Ret => {
+                // 00EE - RET
+                // Returns from a subroutine.
+
+                // Stop instead of underflowing when there is no subroutine to
+                // return from.
+                if self.sp == 0 {
+                    return Err(CpuError::StackUnderflow { sp: self.sp, addr: self.pc });
+                }
+
+                // Execution continues past the CALL since the program counter
+                // is incremented like any other instruction.
+                self.sp -= 1;
+                self.pc = self.stack[self.sp as usize];
+                self.stack[self.sp as usize] = 0;
+            },
+            Instruction::Sys { .. } => {
+                // 0NNN - SYS NNN
+                //
+                // Jump to a machine code routine at NNN. Only the original
+                // hardware could run these, modern interpreters ignore the
+                // instruction and so does this one. It still shows up in the
+                // trace like any other instruction.
+            },
+            Instruction::Jump { addr } => {
+                // 1NNN - JP NNN
+                //
+                // Jumps to address NNN.
+                //
+                // Roms often end with a jump to itself. That needs no special
+                // handling: the loop keeps polling input, ticking timers and
+                // pacing instructions, so the window stays responsive until
+                // it is closed.
+
+                self.pc = addr;
+                skip = true;
+            },
+            Instruction::Call { addr } => {
+                // 2NNN - CALL NNN
+                //
+                // Calls subroutine at NNN.
+
+                // Stop instead of writing past the end of the call stack.
+                if self.sp as usize >= self.stack.len() {
+                    return Err(CpuError::StackOverflow { sp: self.sp, addr: self.pc });
+                }
+
+                // Add the current program counter to the call stack.
+                self.stack[self.sp as usize] = self.pc;
+                self.sp += 1;
+
+                // Set the program counter to the call address and let the
+                // execution loop continue from there.
+                self.pc = addr;
+                skip = true;
+            },
+            Instruction::SkipEqualByte { regx, byte } => {
+                // 3XNN - SE VX, NN
+                //
+                // Skips the next instruction if VX equals NN.
+
+                let x = self.get_reg(regx);
+
+                if x == byte {
+                    self.skip_instruction();
+                }
+            },
+            Instruction::SkipNotEqualByte { regx, byte } => {
+                // 4XNN - SNE VX, NN
+                //
+                // The interpreter compares register VX to NN, and if they are
+                // not equal, increments the program counter by 2.
+
+                let x = self.get_reg(regx);
+
+                if x != byte {
+                    self.skip_instruction();
+                }
+            },
+            Instruction::SkipEqual { regx, regy } => {
+                // 5XY0 - SE VX, VY
+                //
+                // Skip the next instruction if VX == VY.
+
+                let x = self.get_reg(regx);
+                let y = self.get_reg(regy);
+
+                if x == y {
+                    self.skip_instruction();
+                }
+            },
+            Instruction::LoadByte { regx, byte } => {
+                // 6XNN - LD VX, NN
+                //
+                // Sets VX to NN.
+
+                self.set_reg(regx, byte);
+            },
+            Instruction::AddByte { regx, byte } => {
+                // 7XNN - ADD VX, NN
+                //
+                // Adds NN to VX.
+
+                let result = self.get_reg(regx).wrapping_add(byte);
+                self.set_reg(regx, result);
+            },
+            Instruction::Load { regx, regy } => {
+                // 8XY0 - LD VX, VY
+                //
+                // Sets VX to VY.
+
+                let y = self.get_reg(regy);
+                self.set_reg(regx, y);
+            },
+            Instruction::Or { regx, regy } => {
+                // 8XY1 - OR VX, VY
+                //
+                // Sets VX to VX or VY. VF is reset with the VF reset quirk, as
+                // are AND and XOR.
+
+                let x = self.get_reg(regx);
+                let y = self.get_reg(regy);
+                self.set_reg(regx, x | y);
+                self.reset_vf();
+            },
+            Instruction::And { regx, regy } => {
+                // 8XY2 - AND VX, VY
+                //
+                // Sets VX to VX and VY.
+
+                let x = self.get_reg(regx);
+                let y = self.get_reg(regy);
+                self.set_reg(regx, x & y);
+                self.reset_vf();
+            },
+            Instruction::Xor { regx, regy } => {
+                // 8XY3 - XOR VX, VY
+                //
+                // Sets VX to VX xor VY.
+
+                let x = self.get_reg(regx);
+                let y = self.get_reg(regy);
+                self.set_reg(regx, x ^ y);
+                self.reset_vf();
+            },
+            Instruction::Add { regx, regy } => {
+                // 8XY4 - ADD VX, VY
+                //
+                // The values of VX and VY are added together. If the result is
+                // greater than 8 bits (i.e., > 255,) VF is set to 1, otherwise
+                // 0. Only the lowest 8 bits of the result are kept, and stored
+                // in VX.
+
+                let x = self.get_reg(regx) as u16;
+                let y = self.get_reg(regy) as u16;
+
+                // VF is written last since it can also be VX.
+                let result = x.wrapping_add(y);
+                let carry = if result > 255 { 1 } else { 0 };
+                self.set_reg(regx, result as u8);
+                self.v[0xF] = carry;
+            },
+            Instruction::Sub { regx, regy } => {
+                // 8XY5 - SUB VX, VY
+                //
+                // If VX >= VY, then VF is set to 1, otherwise 0. Then VY is
+                // subtracted from VX, and the results stored in VX. VF is the
+                // inverse of the borrow, so equal values don't borrow.
+
+                let x = self.get_reg(regx);
+                let y = self.get_reg(regy);
+
+                let not_borrow = if x >= y { 1 } else { 0 };
+                self.set_reg(regx, x.wrapping_sub(y));
+                self.v[0xF] = not_borrow;
+            },
+            Instruction::ShiftRight { regx, regy } => {
+                // 8XY6 - SHR VX {, VY}
+                //
+                // If the least-significant bit of VX is 1, then VF is set to 1,
+                // otherwise 0. Then VX is divided by 2. With the shift quirk VY
+                // is shifted into VX instead.
+
+                let x = self.shift_source(regx, regy);
+                let lsb = x & 0x1;
+
+                self.set_reg(regx, x >> 1);
+                self.v[0xF] = lsb;
+            },
+            Instruction::SubN { regx, regy } => {
+                // 8XY7 - SUBN VX, VY
+                //
+                // If VY >= VX, then VF is set to 1, otherwise 0. Then VX is
+                // subtracted from VY, and the results stored in VX.
+
+                let x = self.get_reg(regx);
+                let y = self.get_reg(regy);
+
+                let not_borrow = if y >= x { 1 } else { 0 };
+                self.set_reg(regx, y.wrapping_sub(x));
+                self.v[0xF] = not_borrow;
+            },
+            Instruction::ShiftLeft { regx, regy } => {
+                // 8XYE - SHL VX {, VY}
+                //
+                // If the most-significant bit of VX is 1, then VF is set to 1,
+                // otherwise to 0. Then VX is multiplied by 2. With the shift
+                // quirk VY is shifted into VX instead.
+
+                let x = self.shift_source(regx, regy);
+                let msb = (x & 0x80) >> 7;
+
+                self.set_reg(regx, x << 1);
+                self.v[0xF] = msb;
+            },
+            Instruction::SkipNotEqual { regx, regy } => {
+                // 9XY0 - SNE VX, VY
+                //
+                // Skip the next instruction if VX != VY.
+
+                let x = self.get_reg(regx);
+                let y = self.get_reg(regy);
+
+                if x != y {
+                    self.skip_instruction();
+                }
+            },
+            Instruction::LoadI { addr } => {
+                // ANNN - LD I, NNN
+                //
+                // Sets I to the address NNN.
+
+                self.i = addr;
+            },
+            Instruction::SetZoneColor { regx, regy, nibble } => {
+                // BXYN - Chip-8X: Set foreground color
+                //
+                // Colors zones of the display with the color in VY. With N
+                // set to 0 whole blocks of 8x4 pixels are colored: the low
+                // nibble of VX is the first block column and the high nibble
+                // the number of extra columns, V(X+1) works the same way for
+                // block rows. Otherwise N rows starting at row V(X+1) are
+                // colored in the 8 pixel wide column that contains pixel VX.
+
+                let horizontal = self.get_reg(regx) as usize;
+                let vertical = self.get_reg((regx + 1) & 0xF) as usize;
+                let color = self.get_reg(regy);
+
+                if nibble == 0 {
+                    self.interconnect.graphics.set_zone_color(
+                        horizontal & 0xF,
+                        (vertical & 0xF) * ZONE_BLOCK_HEIGHT,
+                        (horizontal >> 4) + 1,
+                        ((vertical >> 4) + 1) * ZONE_BLOCK_HEIGHT,
+                        color);
+                } else {
+                    self.interconnect.graphics.set_zone_color(
+                        horizontal / 8, vertical, 1, nibble as usize, color);
+                }
+            },
+            Instruction::JumpOffset { addr } => {
+                // BNNN - JP V0, NNN
+                //
+                // The program counter is set to NNN plus the value of V0.
+                // With the jump quirk it is NNN plus VX instead, X being the
+                // highest nibble of NNN.
+
+                // The sum is kept within the 12-bit address space like the
+                // original interpreter, so a large V0 wraps around instead of
+                // running past the end of ram.
+                let reg = if self.quirks.jump_vx { (addr >> 8) as u8 } else { 0x0 };
+                self.pc = addr.wrapping_add(self.get_reg(reg) as u16) & 0xFFF;
+                skip = true;
+            },
+            Instruction::Random { regx, byte } => {
+                // CXNN - RND VX, NN
+                //
+                // Sets VX to the result of a bitwise and operation on a
+                // random number and NN.
+
+                let rnd = self.rng.0.gen::<u8>();
+                self.set_reg(regx, rnd & byte);
+            },
+            Instruction::Draw { regx, regy, nibble } => {
+                // DXYN - DRW VX, VY, N
+                //
+                // Sprites stored in memory at location in index register (I),
+                // 8bits wide. Wraps around the screen. If when drawn, clears a
+                // pixel, register VF is set to 1 otherwise it is zero.
+                //
+                // All drawing is XOR drawing (i.e. it toggles the screen
+                // pixels). Sprites are drawn starting at position VX, VY. N is
+                // the number of 8bit rows that need to be drawn. If N is
+                // greater than 1, second line continues at position VX, VY+1,
+                // and so on.
+                //
+                // On the Super-CHIP in high resolution, DXY0 draws a 16x16
+                // sprite stored as 32 bytes, two for every row. With several
+                // XO-CHIP planes selected the sprite is stored once for each.
+
+                let large = nibble == 0 && self.extensions.schip &&
+                            self.interconnect.graphics.hires();
+                let plane_size = if large { 32 } else { nibble as usize };
+                let size = plane_size * self.interconnect.graphics.plane_count();
+
+                // Read the sprite out of memory starting at address register
+                // I. Rows past the end of ram are blank when padding sprites.
+                if !self.quirks.pad_sprites {
+                    self.check_ram(self.i as usize, size)?;
+                }
+                let mut sprite = vec![0 as u8; size];
+                for i in 0..size {
+                    let addr = self.i as usize + i;
+                    if self.interconnect.memory.contains(addr, 1) {
+                        sprite[i] = self.interconnect.memory.read(addr);
+                    }
+                }
+
+                // Get screen coordinates from the requested registers.
+                let x = self.get_reg(regx) as usize;
+                let y = self.get_reg(regy) as usize;
+
+                // Draw the sprite and store collision detection results in vf.
+                // VF only flags that a collision happened, not how many
+                // pixels were erased.
+                let collisions = if large {
+                    self.interconnect.graphics.draw_large(x, y, sprite)
+                } else {
+                    self.interconnect.graphics.draw(x, y, sprite)
+                };
+                self.v[0xF] = if collisions > 0 { 1 } else { 0 };
+            },
+            Instruction::SkipKey { regx } => {
+                // EX9E - SKP VX
+                //
+                // Skips the next instruction if the key stored in VX is
+                // pressed.
+
+                let x = self.get_reg(regx);
+                if self.key_held(x)? {
+                    self.skip_instruction();
+                }
+            },
+            Instruction::SkipNotKey { regx } => {
+                // EXA1 - SKNP VX
+                //
+                // Skips the next instruction if the key stored in VX isn't
+                // pressed.
+
+                let x = self.get_reg(regx);
+                if !self.key_held(x)? {
+                    self.skip_instruction();
+                }
+            },
+            Instruction::LoadDelay { regx } => {
+                // FX07 - LD VX, DT
+                //
+                // Sets VX to the value of the delay timer.
+
+                let dt = self.dt;
+                self.set_reg(regx, dt);
+            },
+            Instruction::WaitKey { regx } => {
+                // FX0A - LD VX, N
+                //
+                // All execution stops until a key is pressed, then the value
+                // of that key is stored in VX. If several keys are pressed at
+                // once the lowest one is stored.
+
+                // Without a press the instruction runs again on the next
+                // step, so frames, timers and recorded input move on while
+                // waiting and the window stays responsive. Keys pressed
+                // before the wait started don't end it.
+                if !self.waiting_for_key {
+                    self.interconnect.input.clear_presses();
+                    self.waiting_for_key = true;
+                }
+
+                match self.interconnect.input.take_press() {
+                    Some(key) => {
+                        self.set_reg(regx, key);
+                        self.waiting_for_key = false;
+                    },
+                    None => skip = true,
+                }
+            },
+            Instruction::SetDelay { regx } => {
+                // FX15 - LD DT, VX
+                //
+                // Sets the delay timer to VX.
+
+                let x = self.get_reg(regx);
+                self.dt = x;
+            },
+            Instruction::SetSound { regx } => {
+                // FX18 - LD ST, VX
+                //
+                // ST is set equal to the value of VX.
+
+                let x = self.get_reg(regx);
+                self.st = x;
+            },
+            Instruction::AddI { regx } => {
+                // FX1E - ADD I, VX
+                //
+                // The values of I and VX are added, and the results are stored
+                // in I. VF is left alone unless the overflow quirk is enabled,
+                // in which case it flags sums past the addressable ram: 0xFFF,
+                // or 0xFFFF with XO-CHIP.
+
+                let x = self.get_reg(regx);
+                let limit = if self.extensions.xochip { 0xFFFF } else { 0xFFF };
+                let overflow = self.i as u32 + x as u32 > limit;
+                self.i = self.i.wrapping_add(x as u16);
+
+                if self.quirks.i_overflow_flag {
+                    self.v[0xF] = if overflow { 1 } else { 0 };
+                }
+            },
+            Instruction::LoadFont { regx } => {
+                // FX29 - LD F, VX
+                //
+                // Sets I to the location of the sprite for the character in
+                // VX. Characters 0-F (in hexadecimal) are represented by a 4x5
+                // font.
+                //
+                // Only the low nibble of VX selects the character, so values
+                // above 0xF wrap around to the glyphs 0-F instead of pointing
+                // past the font.
+
+                let x = self.get_reg(regx) & 0xF;
+                self.i = self.interconnect.memory.get_font(x);
+            },
+            Instruction::Bcd { regx } => {
+                // FX33 - LD B, VX
+                //
+                // Stores the Binary-coded decimal representation of VX, with
+                // the most significant of three digits at the address in I,
+                // the middle digit at I plus 1, and the least significant
+                // digit at I plus 2. (In other words, take the decimal
+                // representation of VX, place the hundreds digit in memory at
+                // location in I, the tens digit at location I+1, and the ones
+                // digit at location I+2.)
+
+                const DECIMAL_LENGTH: usize = 3;
+
+                // Every digit is taken directly so leading zeros are always
+                // written, 5 is stored as 0, 0, 5.
+                let x = self.get_reg(regx);
+                let digits = [x / 100, (x / 10) % 10, x % 10];
+
+                // Set I, I+1, and I+2 to the values of the digits.
+                let i = self.i as usize;
+                self.check_ram(i, DECIMAL_LENGTH)?;
+                for (offset, &digit) in digits.iter().enumerate() {
+                    self.interconnect.memory.write(i + offset, digit);
+                }
+            },
+            Instruction::StoreRegisters { regx } => {
+                // FX55 - LD [I], VX
+                //
+                // The interpreter copies the values of registers V0 through VX
+                // into memory, starting at the address in I.
+
+                let i = self.i as usize;
+                let end_reg = (regx + 1) as usize;
+                self.check_ram(i, end_reg)?;
+
+                for register in 0x0..end_reg {
+                    let val = self.get_reg(register as u8);
+                    self.interconnect.memory.write(i + register, val);
+                }
+
+                if self.quirks.increment_i {
+                    self.i = self.i.wrapping_add(end_reg as u16);
+                }
+            },
+            Instruction::LoadRegisters { regx } => {
+                // FX65 - LD VX, [I]
+                //
+                // Fills V0 to VX with values from memory starting at address
+                // I.
+
+                let i = self.i as usize;
+                let end_reg = (regx + 1) as usize;
+                self.check_ram(i, end_reg)?;
+
+                for register in 0x0..end_reg {
+                    let mem = self.interconnect.memory.read(i + register);
+                    self.set_reg(register as u8, mem);
+                }
+
+                if self.quirks.increment_i {
+                    self.i = self.i.wrapping_add(end_reg as u16);
+                }
+            },
+            Instruction::StoreFlags { regx } => {
+                // FX75 - LD R, VX
+                //
+                // Stores V0 through VX in the HP48 flag registers. Only V0-V7
+                // can be stored since there are eight flags.
+
+                let end_reg = self.flag_range(regx)?;
+
+                for register in 0x0..end_reg {
+                    self.hp48_flags[register] = self.get_reg(register as u8);
+                }
+
+                if let Some(ref storage) = self.flag_storage {
+                    if let Err(why) = storage.save(&self.hp48_flags) {
+                        println!("notch: cannot save flags: {}", why);
+                    }
+                }
+            },
+            Instruction::LoadFlags { regx } => {
+                // FX85 - LD VX, R
+                //
+                // Fills V0 through VX from the HP48 flag registers.
+
+                let end_reg = self.flag_range(regx)?;
+
+                for register in 0x0..end_reg {
+                    let flag = self.hp48_flags[register];
+                    self.set_reg(register as u8, flag);
+                }
+            },
+            Instruction::LoadLongI => {
+                // F000 NNNN - XO-CHIP: LD I, NNNN
+                //
+                // Sets I to the 16-bit address in the word following the
+                // instruction, reaching all 64kB of XO-CHIP ram. Execution
+                // continues after the address.
+
+                let addr = self.pc as usize + INSTRUCTION_SIZE as usize;
+                self.check_ram(addr, INSTRUCTION_SIZE as usize)?;
+                self.i = self.interconnect.memory.read_word(addr as u16);
+                self.pc = self.pc.wrapping_add(INSTRUCTION_SIZE);
+            },
+            Instruction::SelectPlanes { mask } => {
+                // FN01 - XO-CHIP: PLANE N
+                //
+                // Selects the planes DXYN, CLS and the scrolls work on, N
+                // being a bit mask with plane 1 in its lowest bit. Sprites
+                // then hold a set of rows for every selected plane.
+
+                self.interconnect.graphics.set_planes(mask);
+            },
+        }
+
+        // Increment the program counter to the next instruction. Only
+        // XO-CHIP ram is large enough for it to wrap around.
+        if !skip {
+            self.pc = self.pc.wrapping_add(INSTRUCTION_SIZE);
+        }
+
+        Ok(())
+    }
+
+    /// Whether the passed keypad key is held during the current frame. Only
+    /// the low 16 values are keys.
+    fn key_held(&self, key: u8) -> Result<bool, CpuError> {
+        match self.interconnect.input.input_state.get(key as usize) {
+            Some(&held) => Ok(held),
+            None => Err(CpuError::InvalidKey { key: key, addr: self.pc }),
+        }
+    }
+
+    /// Returns the number of registers FX75/FX85 transfer, checking the
+    /// instruction doesn't reach past the last flag register.
+    fn flag_range(&self, regx: u8) -> Result<usize, CpuError> {
+        let end_reg = (regx + 1) as usize;
+        if end_reg > FLAG_COUNT {
+            return Err(CpuError::BadRegister { reg: regx, addr: self.pc });
+        }
+
+        Ok(end_reg)
+    }
+
+    /// Checks that `len` bytes of ram starting at `target` can be accessed.
+    fn check_ram(&self, target: usize, len: usize) -> Result<(), CpuError> {
+        if self.interconnect.memory.contains(target, len) {
+            Ok(())
+        } else {
+            Err(CpuError::AddressOutOfRange { target: target, len: len, addr: self.pc })
+        }
+    }
+
+    /// Counts the timers down by the wall clock time passed since the last
+    /// call and starts or stops the beep to match the sound timer. With
+    /// emulated time the time the passed instruction takes is counted instead.
+    fn handle_timers(&mut self, instr: u16) {
+        let now = time::precise_time_ns();
+        let elapsed = if self.emulated_time() {
+            self.timing.cost(instr)
+        } else {
+            now - self.timer_clock
+        };
+        self.timer_clock = now;
+
+        // Frames are shown at the same 60Hz the timers tick at, every tick
+        // ends a frame.
+        for _ in 0..self.tick_timers(elapsed) {
+            self.end_frame();
+        }
+        self.update_beep();
+    }
+
+    /// Whether time follows the instructions executed rather than the wall
+    /// clock. Turbo mode runs too fast for the wall clock, and recorded input
+    /// only plays back the same if frames end after the same instructions.
+    fn emulated_time(&self) -> bool {
+        self.turbo || self.input_recording.is_some() || self.input_replay.is_some()
+    }
+
+    /// Frame based counterpart of `handle_timers` and `wait_for_instruction`.
+    /// Once `cycles` instructions, scaled by the speed, have executed the
+    /// timers tick once, the frame is shown and execution waits for the next
+    /// 60Hz deadline.
+    fn handle_frame(&mut self, cycles: u64) {
+        if self.frame_cycles < self.frame_budget(cycles) {
+            self.frame_cycles += 1;
+            return;
+        }
+        self.frame_cycles = 1;
+
+        self.tick_timers(TIMER_PERIOD);
+        self.end_frame();
+        self.update_beep();
+        if self.turbo {
+            return;
+        }
+
+        let now = time::precise_time_ns();
+        if self.next_instruction + MAX_EXECUTION_LAG < now {
+            self.next_instruction = now;
+        }
+        self.next_instruction += TIMER_PERIOD;
+        self.pacing.wait_until(&mut SystemClock, self.next_instruction);
+    }
+
+    /// How many instructions a frame executes at the current speed, at least
+    /// one.
+    fn frame_budget(&self, cycles: u64) -> u64 {
+        cmp::max(1, (cycles as f64 * self.speed).round() as u64)
+    }
+
+    /// Shows the finished frame, unless in turbo mode, and keeps it for
+    /// rewinding.
+    fn end_frame(&mut self) {
+        if !self.turbo {
+            self.interconnect.graphics.present();
+        }
+        self.frames += 1;
+        self.push_rewind_frame();
+        self.advance_input_frame();
+
+        if self.title_fps.is_some() {
+            self.measure_fps();
+        }
+    }
+
+    /// Updates the frame rate in the window title once a second has passed
+    /// since it was last measured.
+    fn measure_fps(&mut self) {
+        let now = time::precise_time_ns();
+        let elapsed = now - self.fps_time;
+        if elapsed < FPS_INTERVAL {
+            return;
+        }
+
+        // The frame count starts over on reset.
+        let frames = self.frames.saturating_sub(self.fps_frames);
+        self.title_fps = Some(frames as f64 * 1_000_000_000.0 / elapsed as f64);
+        self.fps_time = now;
+        self.fps_frames = self.frames;
+        self.update_title();
+    }
+
+    /// Shows the current speed, and the status when enabled, in the window
+    /// title.
+    fn update_title(&mut self) {
+        self.interconnect.show_status(self.title_fps, self.speed, self.paused);
+    }
+
+    /// Starts or stops the beep to match the sound timer. Only changes are
+    /// passed on to the beeper, the sound timer is checked before every
+    /// instruction.
+    fn update_beep(&mut self) {
+        let beeping = self.is_beeping();
+        if beeping && !self.interconnect.sound.beeping() {
+            self.interconnect.sound.start_beep();
+        } else if !beeping && self.interconnect.sound.beeping() {
+            self.interconnect.sound.stop_beep();
+        }
+    }
+
+    /// Advances the timers by the passed amount of nanoseconds. Both timers
+    /// decrement once for every full 60Hz tick and stop at zero. Time left
+    /// over is carried into the next call. Returns the number of ticks.
+    fn tick_timers(&mut self, elapsed: u64) -> u64 {
+        self.timer_elapsed += elapsed;
+        let ticks = self.timer_elapsed / TIMER_PERIOD;
+        self.timer_elapsed %= TIMER_PERIOD;
+
+        let decrement = cmp::min(ticks, u8::MAX as u64) as u8;
+        self.dt = self.dt.saturating_sub(decrement);
+        self.st = self.st.saturating_sub(decrement);
+
+        ticks
+    }
+
+    /// Holds execution to the speed of the timing profile by waiting out the
+    /// time the passed instruction takes. Instructions are scheduled on fixed
+    /// deadlines so time lost while waiting doesn't add up. Turbo mode
+    /// doesn't wait at all.
+    fn wait_for_instruction(&mut self, instr: u16) {
+        if self.turbo {
+            return;
+        }
+
+        let now = time::precise_time_ns();
+        if self.next_instruction + MAX_EXECUTION_LAG < now {
+            self.next_instruction = now;
+        }
+
+        self.next_instruction += (self.timing.cost(instr) as f64 / self.speed) as u64;
+        self.pacing.wait_until(&mut SystemClock, self.next_instruction);
+    }
+
+    /// Moves the program counter past the next instruction for a skip. With
+    /// XO-CHIP enabled F000 NNNN is skipped as a whole.
+    fn skip_instruction(&mut self) {
+        let next = self.pc as usize + INSTRUCTION_SIZE as usize;
+        let long = self.extensions.xochip &&
+                   self.interconnect.memory.contains(next, INSTRUCTION_SIZE as usize) &&
+                   self.interconnect.memory.read_word(next as u16) == 0xF000;
+
+        let skipped = if long { 2 * INSTRUCTION_SIZE } else { INSTRUCTION_SIZE };
+        self.pc = self.pc.wrapping_add(skipped);
+    }
+
+    /// Clears VF after a logic instruction when the VF reset quirk is on.
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
+    }
+
+    /// The register value a shift instruction shifts, VY with the shift
+    /// quirk and VX otherwise.
+    fn shift_source(&self, regx: u8, regy: u8) -> u8 {
+        if self.quirks.shift_vy {
+            self.get_reg(regy)
+        } else {
+            self.get_reg(regx)
+        }
+    }
+
+    /// Gets the value at a specified register.
+    fn get_reg(&self, reg: u8) -> u8 {
+        self.v[reg as usize]
+    }
+
     /// Sets the value of a general purpose register.
     fn set_reg(&mut self, reg: u8, byte: u8) {
-        match reg {
-            0x0 => self.v0 = byte,
-            0x1 => self.v1 = byte,
-            0x2 => self.v2 = byte,
-            0x3 => self.v3 = byte,
-            0x4 => self.v4 = byte,
-            0x5 => self.v5 = byte,
-            0x6 => self.v6 = byte,
-            0x7 => self.v7 = byte,
-            0x8 => self.v8 = byte,
-            0x9 => self.v9 = byte,
-            0xa => self.va = byte,
-            0xb => self.vb = byte,
-            0xc => self.vc = byte,
-            0xd => self.vd = byte,
-            0xe => self.ve = byte,
-            0xf => self.vf = byte,
-            _ => {
-                panic!("Cannot set unknown register: V{:X}", reg);
+        self.v[reg as usize] = byte;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::*;
+    use super::super::memory::{CHARACTER_SIZE, FONT_OFFSET, RAM_SIZE, XO_RAM_SIZE};
+    use super::super::providers::ScriptedProvider;
+    use super::super::sound::BeepLog;
+    use super::super::wav::WavRecorder;
+
+    /// A headless cpu running `rom` as fast as the host allows, with the
+    /// random number generator seeded so runs repeat.
+    fn cpu(rom: &[u8]) -> Cpu {
+        let interconnect = Interconnect::headless(rom, RAM_SIZE).unwrap();
+        let mut cpu = Cpu::with_seed(interconnect, 0);
+        cpu.enable_turbo();
+        cpu
+    }
+
+    #[test]
+    fn warp_moves_execution_to_the_routine() {
+        // 0x200: JP 0x200, 0x202: LD V0, 0x2A
+        let mut cpu = cpu(&[0x12, 0x00, 0x60, 0x2A]);
+
+        cpu.warp_to(0x202).unwrap();
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.registers()[0], 0x2A);
+        assert_eq!(cpu.pc(), 0x204);
+    }
+
+    #[test]
+    fn warp_rejects_addresses_outside_program_space() {
+        let mut cpu = cpu(&[0x12, 0x00]);
+
+        assert!(cpu.warp_to(0x1FE).is_err());
+        assert!(cpu.warp_to(0xFFF).is_err());
+        assert_eq!(cpu.pc(), 0x200);
+    }
+
+    #[test]
+    fn replayed_keys_end_the_key_wait_on_the_same_frame() {
+        // 0x200: LD V0, 0x30, LD DT, V0, LD V2, K, LD V3, DT, JP 0x208
+        let rom = [0x60, 0x30, 0xF0, 0x15, 0xF2, 0x0A, 0xF3, 0x07, 0x12, 0x08];
+
+        // Key 7 goes down on the fourth frame.
+        let mut log = InputLog::default();
+        let mut keys = [false; 16];
+        for frame in 0..10 {
+            keys[7] = frame >= 3;
+            log.push(&keys);
+        }
+
+        // Paced in real time, a replay still counts frames in instructions.
+        let interconnect = Interconnect::headless(rom, RAM_SIZE).unwrap();
+        let mut cpu = Cpu::with_seed(interconnect, 0);
+        cpu.set_clock_hz(6000);
+        cpu.replay(log);
+        cpu.run_for(1000).unwrap();
+
+        assert_eq!(cpu.pc(), 0x208);
+        assert_eq!(cpu.registers()[2], 7);
+        assert_eq!(cpu.registers()[3], 0x30 - 3);
+    }
+
+    #[test]
+    fn recorded_input_replays_to_the_same_display() {
+        // 0x200: LD V0, K, LD F, V0, DRW V1, V2, 5, ADD V1, 0x05, JP 0x200
+        let rom = [0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x25, 0x71, 0x05, 0x12, 0x00];
+        let script = ScriptedProvider::parse("5 3 2\n20 a 2\n40 7 2\n").unwrap();
+
+        let mut recorded = cpu(&rom);
+        recorded.interconnect.input.add_provider(Box::new(script));
+        recorded.start_recording();
+        recorded.run_for(1000).unwrap();
+        let log = InputLog::decode(&recorded.stop_recording().encode()).unwrap();
+
+        let mut replayed = cpu(&rom);
+        replayed.replay(log);
+        replayed.run_for(1000).unwrap();
+
+        // All three keys were drawn, the digits side by side.
+        assert_eq!(recorded.registers()[1], 15);
+        assert_eq!(replayed.registers(), recorded.registers());
+        assert_eq!(replayed.interconnect.framebuffer(), recorded.interconnect.framebuffer());
+    }
+
+    #[test]
+    fn subroutines_return_past_the_call() {
+        // 0x200: CALL 0x206, LD V1, 0x01, JP 0x204
+        // 0x206: LD V0, 0x2A, RET
+        let mut cpu = cpu(&[0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x60, 0x2A, 0x00, 0xEE]);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), 0x206);
+        assert_eq!(cpu.sp(), 1);
+
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.registers()[0], 0x2A);
+        assert_eq!(cpu.registers()[1], 0x01);
+        assert_eq!(cpu.sp(), 0);
+        assert_eq!(cpu.pc(), 0x204);
+    }
+
+    #[test]
+    fn runaway_calls_overflow_the_stack() {
+        // 0x200: CALL 0x200
+        let mut cpu = cpu(&[0x22, 0x00]);
+
+        for _ in 0..16 {
+            cpu.step().unwrap();
+        }
+        match cpu.step() {
+            Err(error @ CpuError::StackOverflow { .. }) => {
+                assert!(matches!(error, CpuError::StackOverflow { sp: 16, addr: 0x200 }));
+                assert_eq!(error.to_string(), "stack overflow, too many nested calls \
+                                               (sp: 16) at addr: 0x200");
+            },
+            other => panic!("expected a stack overflow, got {:?}", other),
+        }
+        assert_eq!(cpu.sp(), 16);
+    }
+
+    #[test]
+    fn ret_resumes_after_the_call_site() {
+        // 0x200: CALL 0x204, JP 0x202
+        // 0x204: RET
+        let mut cpu = cpu(&[0x22, 0x04, 0x12, 0x02, 0x00, 0xEE]);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), 0x202);
+        assert_eq!(cpu.sp(), 0);
+    }
+
+    #[test]
+    fn ret_outside_a_subroutine_underflows() {
+        let mut cpu = cpu(&[0x00, 0xEE]);
+
+        match cpu.step() {
+            Err(CpuError::StackUnderflow { sp, addr }) => assert_eq!((sp, addr), (0, 0x200)),
+            other => panic!("expected a stack underflow, got {:?}", other),
+        }
+        assert_eq!(cpu.sp(), 0);
+    }
+
+    #[test]
+    fn unbalanced_returns_underflow_after_the_subroutine() {
+        // 0x200: CALL 0x204, RET
+        // 0x204: RET
+        let mut cpu = cpu(&[0x22, 0x04, 0x00, 0xEE, 0x00, 0xEE]);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let error = cpu.step().unwrap_err();
+        assert!(matches!(error, CpuError::StackUnderflow { sp: 0, addr: 0x202 }));
+        assert_eq!(error.to_string(), "stack underflow, RET outside of a subroutine \
+                                       (sp: 0) at addr: 0x202");
+    }
+
+    #[test]
+    fn cls_clears_a_drawn_sprite() {
+        // 0x200: DRW V0, V0, 5 (the font glyph for 0 at I = 0), CLS
+        let mut cpu = cpu(&[0xD0, 0x05, 0x00, 0xE0]);
+
+        cpu.step().unwrap();
+        assert!(cpu.interconnect().framebuffer().iter().any(|&pixel| pixel != 0));
+
+        cpu.step().unwrap();
+        assert!(cpu.interconnect().framebuffer().iter().all(|&pixel| pixel == 0));
+    }
+
+    /// Runs the setup instructions, then the skip, and returns where the
+    /// skip left the program counter relative to it.
+    fn skip_distance(setup: &[u8], skip: [u8; 2]) -> u16 {
+        let mut rom = setup.to_vec();
+        rom.extend_from_slice(&skip);
+        let mut cpu = cpu(&rom);
+
+        for _ in 0..setup.len() / 2 + 1 {
+            cpu.step().unwrap();
+        }
+        cpu.pc() - 0x200 - setup.len() as u16
+    }
+
+    #[test]
+    fn byte_compare_skips() {
+        // LD V3, 0x2A, then SE V3, NN or SNE V3, NN.
+        assert_eq!(skip_distance(&[0x63, 0x2A], [0x33, 0x2A]), 4);
+        assert_eq!(skip_distance(&[0x63, 0x2A], [0x33, 0x2B]), 2);
+        assert_eq!(skip_distance(&[0x63, 0x2A], [0x43, 0x2B]), 4);
+        assert_eq!(skip_distance(&[0x63, 0x2A], [0x43, 0x2A]), 2);
+    }
+
+    #[test]
+    fn register_compare_skips() {
+        // V0 and V1 are both zero, then V1 is set to 1 with LD V1, 0x01.
+        assert_eq!(skip_distance(&[], [0x50, 0x10]), 4);
+        assert_eq!(skip_distance(&[], [0x90, 0x10]), 2);
+        assert_eq!(skip_distance(&[0x61, 0x01], [0x50, 0x10]), 2);
+        assert_eq!(skip_distance(&[0x61, 0x01], [0x90, 0x10]), 4);
+    }
+
+    #[test]
+    fn register_compare_needs_a_zero_low_nibble() {
+        for &rom in &[[0x50, 0x11], [0x90, 0x1F]] {
+            match cpu(&rom).step() {
+                Err(CpuError::UnknownOpcode { .. }) => {},
+                other => panic!("expected an unknown opcode, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn immediate_adds_wrap_and_leave_vf_alone() {
+        // 0x200: LD VF, 0x07, LD V4, 0xFF, ADD V4, 0x01
+        let mut cpu = cpu(&[0x6F, 0x07, 0x64, 0xFF, 0x74, 0x01]);
+        cpu.run_for(3).unwrap();
+
+        assert_eq!(cpu.registers()[4], 0x00);
+        assert_eq!(cpu.registers()[0xF], 0x07);
+    }
+
+    /// Runs the 8XY_ instruction ending in `op` with V0 = `x` and V1 = `y`,
+    /// returning V0 and VF afterwards.
+    fn alu(op: u8, x: u8, y: u8) -> (u8, u8) {
+        // 0x200: LD V0, x, LD V1, y, 8 0 1 op
+        let mut cpu = cpu(&[0x60, x, 0x61, y, 0x80, 0x10 | op]);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        (cpu.registers()[0], cpu.registers()[0xF])
+    }
+
+    #[test]
+    fn alu_logic_and_loads() {
+        assert_eq!(alu(0x0, 0x12, 0x34), (0x34, 0));
+        assert_eq!(alu(0x1, 0b1100, 0b1010), (0b1110, 0));
+        assert_eq!(alu(0x2, 0b1100, 0b1010), (0b1000, 0));
+        assert_eq!(alu(0x3, 0b1100, 0b1010), (0b0110, 0));
+    }
+
+    #[test]
+    fn alu_add_sets_the_carry() {
+        assert_eq!(alu(0x4, 0x10, 0x20), (0x30, 0));
+        assert_eq!(alu(0x4, 0xFF, 0x01), (0x00, 1));
+        assert_eq!(alu(0x4, 0xF0, 0x20), (0x10, 1));
+    }
+
+    #[test]
+    fn alu_subtraction_sets_not_borrow() {
+        assert_eq!(alu(0x5, 0x30, 0x10), (0x20, 1));
+        assert_eq!(alu(0x5, 0x10, 0x30), (0xE0, 0));
+        assert_eq!(alu(0x5, 0x42, 0x42), (0x00, 1));
+
+        assert_eq!(alu(0x7, 0x10, 0x30), (0x20, 1));
+        assert_eq!(alu(0x7, 0x30, 0x10), (0xE0, 0));
+        assert_eq!(alu(0x7, 0x42, 0x42), (0x00, 1));
+    }
+
+    #[test]
+    fn alu_shifts_keep_the_shifted_out_bit() {
+        assert_eq!(alu(0x6, 0b101, 0), (0b10, 1));
+        assert_eq!(alu(0x6, 0b100, 0), (0b10, 0));
+        assert_eq!(alu(0xE, 0x81, 0), (0x02, 1));
+        assert_eq!(alu(0xE, 0x41, 0), (0x82, 0));
+    }
+
+    #[test]
+    fn alu_flag_wins_over_a_vf_result() {
+        // 0x200: LD VF, 0xFF, LD V1, 0x01, ADD VF, V1
+        let mut cpu = cpu(&[0x6F, 0xFF, 0x61, 0x01, 0x8F, 0x14]);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.registers()[0xF], 1);
+    }
+
+    #[test]
+    fn seeded_rnd_repeats_the_same_bytes() {
+        // 0x200: RND V0, 0xFF, RND V1, 0xFF, RND V2, 0xFF, RND V3, 0x0F
+        let mut cpu = cpu(&[0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF, 0xC3, 0x0F]);
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(&cpu.registers()[..4], &[0x41, 0xC7, 0x47, 0x02]);
+    }
+
+    #[test]
+    fn timers_tick_at_60hz_and_stop_at_zero() {
+        // 0x200: LD V0, 0x03, LD DT, V0, LD ST, V0
+        let mut cpu = cpu(&[0x60, 0x03, 0xF0, 0x15, 0xF0, 0x18]);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        let (dt, st) = (cpu.dt(), cpu.st());
+        cpu.timer_elapsed = 0;
+
+        // Time left over from a tick carries into the next call.
+        assert_eq!(cpu.tick_timers(TIMER_PERIOD - 1), 0);
+        assert_eq!((cpu.dt(), cpu.st()), (dt, st));
+        assert_eq!(cpu.tick_timers(1), 1);
+        assert_eq!((cpu.dt(), cpu.st()), (dt - 1, st - 1));
+
+        assert_eq!(cpu.tick_timers(10 * TIMER_PERIOD), 10);
+        assert_eq!((cpu.dt(), cpu.st()), (0, 0));
+    }
+
+    #[test]
+    fn malformed_instructions_report_their_error() {
+        // 0xFFFF isn't an instruction on plain CHIP-8.
+        match cpu(&[0xFF, 0xFF]).step() {
+            Err(CpuError::UnknownOpcode { instr, addr }) => {
+                assert_eq!((instr, addr), (0xFFFF, 0x200));
+            },
+            other => panic!("expected an unknown opcode, got {:?}", other),
+        }
+
+        // 0x200: LD V0, 0x10, SKP V0
+        let mut cpu = cpu(&[0x60, 0x10, 0xE0, 0x9E]);
+        cpu.step().unwrap();
+        match cpu.step() {
+            Err(CpuError::InvalidKey { key, addr }) => assert_eq!((key, addr), (0x10, 0x202)),
+            other => panic!("expected an invalid key, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_past_the_end_of_ram_are_out_of_range() {
+        // 0x200: LD I, 0xFFF, LD V1, [I]
+        let mut cpu = cpu(&[0xAF, 0xFF, 0xF1, 0x65]);
+        cpu.step().unwrap();
+        match cpu.step() {
+            Err(CpuError::AddressOutOfRange { target, len, .. }) => {
+                assert_eq!((target, len), (0xFFF, 2));
+            },
+            other => panic!("expected an out of range access, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fetches_past_the_end_of_ram_are_out_of_range() {
+        // The last byte of ram only holds half an instruction.
+        let mut cpu = cpu(&[0x12, 0x00]);
+        cpu.pc = 0xFFF;
+        match cpu.step() {
+            Err(CpuError::AddressOutOfRange { target, len, addr }) => {
+                assert_eq!((target, len, addr), (0xFFF, 2, 0xFFF));
+            },
+            other => panic!("expected an out of range fetch, got {:?}", other),
+        }
+
+        // With mirroring the instruction wraps around to the start of ram.
+        cpu.interconnect.memory.set_mirroring(true);
+        cpu.interconnect.poke(0xFFF, 0x60).unwrap();
+        cpu.interconnect.poke(0x000, 0x2A).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers()[0], 0x2A);
+    }
+
+    /// The digits FX33 stores for `value`.
+    fn bcd(value: u8) -> Vec<u8> {
+        // 0x200: LD V0, value, LD I, 0x300, LD B, V0
+        let mut cpu = cpu(&[0x60, value, 0xA3, 0x00, 0xF0, 0x33]);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        cpu.interconnect().peek_range(0x300, 3).unwrap().to_vec()
+    }
+
+    #[test]
+    fn bcd_pads_every_value_to_three_digits() {
+        assert_eq!(bcd(0), [0, 0, 0]);
+        assert_eq!(bcd(5), [0, 0, 5]);
+        assert_eq!(bcd(50), [0, 5, 0]);
+        assert_eq!(bcd(137), [1, 3, 7]);
+        assert_eq!(bcd(255), [2, 5, 5]);
+    }
+
+    #[test]
+    fn reset_returns_to_the_boot_state() {
+        // 0x200: LD V3, 0x42, LD I, 0x300, LD DT, V3, CALL 0x20A
+        // 0x208: JP 0x208
+        // 0x20A: DRW V0, V0, 5, JP 0x20A
+        let rom = [0x63, 0x42, 0xA3, 0x00, 0xF3, 0x15, 0x22, 0x0A, 0x12, 0x08,
+                   0xD0, 0x05, 0x12, 0x0A];
+        let mut cpu = cpu(&rom);
+        cpu.set_rewind_capacity(10);
+        cpu.run_for(100).unwrap();
+        cpu.pause();
+
+        cpu.reset();
+
+        assert_eq!(cpu.pc(), 0x200);
+        assert_eq!(cpu.registers(), [0; 16]);
+        assert_eq!((cpu.i(), cpu.sp(), cpu.dt(), cpu.st()), (0, 0, 0, 0));
+        assert_eq!(cpu.stack, [0; 16]);
+        assert_eq!((cpu.cycles(), cpu.frames()), (0, 0));
+        assert!(!cpu.paused);
+        assert!(cpu.rewind_buffer.is_empty());
+        assert!(cpu.interconnect().framebuffer().iter().all(|&pixel| pixel == 0));
+        assert_eq!(cpu.interconnect().peek_range(0x200, rom.len()).unwrap(), &rom[..]);
+    }
+
+    #[test]
+    fn large_sprites_draw_16x16_and_collide() {
+        // 0x200: HIGH, LD I, 0x208, DRW V0, V1, 0, DRW V0, V1, 0
+        // 0x208: a solid 16x16 sprite
+        let mut rom = vec![0x00, 0xFF, 0xA2, 0x08, 0xD0, 0x10, 0xD0, 0x10];
+        rom.extend_from_slice(&[0xFF; 32]);
+        let mut cpu = cpu(&rom);
+        cpu.enable_schip();
+
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        let lit: Vec<usize> = cpu.interconnect().framebuffer().iter()
+            .enumerate()
+            .filter(|&(_, &pixel)| pixel != 0)
+            .map(|(index, _)| index)
+            .collect();
+        let block: Vec<usize> = (0..16)
+            .flat_map(|y| (0..16).map(move |x| y * 128 + x))
+            .collect();
+        assert_eq!(lit, block);
+        assert_eq!(cpu.registers()[0xF], 0);
+
+        cpu.step().unwrap();
+        assert!(cpu.interconnect().framebuffer().iter().all(|&pixel| pixel == 0));
+        assert_eq!(cpu.registers()[0xF], 1);
+        assert_eq!(cpu.interconnect().last_collision_count(), 256);
+    }
+
+    /// A writer whose output stays readable after it is handed over.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(::std::rc::Rc<::std::cell::RefCell<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace_lines_show_each_instruction_and_registers() {
+        // 0x200: LD V0, 0x2A, LD I, 0x300, ADD V0, 0x01
+        let mut cpu = cpu(&[0x60, 0x2A, 0xA3, 0x00, 0x70, 0x01]);
+        let buffer = SharedBuffer::default();
+        cpu.trace_to(Box::new(buffer.clone()));
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+
+        let zeros = " 00".repeat(15);
+        let trace = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines, [
+            format!("0x0200 602a LD V0, 0x2A      i=0x000 v=2a{}", zeros),
+            format!("0x0202 a300 LD I, 0x300      i=0x300 v=2a{}", zeros),
+            format!("0x0204 7001 ADD V0, 0x01     i=0x300 v=2b{}", zeros),
+        ]);
+    }
+
+    #[test]
+    fn step_executes_exactly_one_instruction() {
+        // 0x200: LD V0, 0x01, LD V1, 0x02, LD V2, 0x03
+        let mut cpu = cpu(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03]);
+
+        cpu.step().unwrap();
+        assert_eq!((cpu.pc(), cpu.cycles()), (0x202, 1));
+        assert_eq!(&cpu.registers()[..3], &[1, 0, 0]);
+
+        cpu.step().unwrap();
+        assert_eq!((cpu.pc(), cpu.cycles()), (0x204, 2));
+        assert_eq!(&cpu.registers()[..3], &[1, 2, 0]);
+    }
+
+    #[test]
+    fn breakpoints_stop_headless_runs_before_the_instruction() {
+        // 0x200: CALL 0x206, JP 0x204
+        // 0x204: JP 0x204
+        // 0x206: LD V0, 0x2A, RET
+        let mut cpu = cpu(&[0x22, 0x06, 0x12, 0x04, 0x12, 0x04, 0x60, 0x2A, 0x00, 0xEE]);
+        cpu.add_breakpoint(0x206);
+        cpu.add_breakpoint(0x204);
+
+        assert_eq!(cpu.run().unwrap(), Stopped::Breakpoint(0x206));
+        assert_eq!(cpu.pc(), 0x206);
+        assert_eq!(cpu.registers()[0], 0);
+
+        // Continuing runs the instruction at the breakpoint first.
+        assert_eq!(cpu.run_for(100).unwrap(), (3, Stopped::Breakpoint(0x204)));
+        assert_eq!(cpu.registers()[0], 0x2A);
+
+        cpu.remove_breakpoint(0x204);
+        assert_eq!(cpu.run_for(10).unwrap(), (10, Stopped::CycleLimit));
+    }
+
+    #[test]
+    fn states_round_trip_keys_halt_planes_and_colors() {
+        // 0x200: LD V0, 0x05, LD F, V0, DRW V0, V0, 5, JP 0x206
+        let mut cpu = cpu(&[0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06]);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        cpu.interconnect.graphics.set_planes(0b10);
+        cpu.interconnect.graphics.set_zone_color(1, 2, 3, 1, 2);
+        cpu.interconnect.graphics.step_background();
+        cpu.interconnect.input.input_state[0xA] = true;
+        cpu.halted = true;
+        let saved = cpu.capture_state();
+        let bytes = cpu.save_state();
+
+        cpu.reset();
+        cpu.interconnect.graphics.set_planes(0b01);
+        cpu.interconnect.graphics.set_zone_color(0, 0, 8, 32, 5);
+        cpu.interconnect.input.input_state = [false; 16];
+        assert!(cpu.capture_state() != saved);
+
+        cpu.load_state(&bytes).unwrap();
+        assert_eq!(cpu.capture_state(), saved);
+        assert_eq!(cpu.pc(), 0x206);
+        assert_eq!(cpu.interconnect.graphics.planes(), 0b10);
+        assert!(cpu.interconnect.input.input_state[0xA]);
+        assert!(cpu.halted);
+    }
+
+    #[test]
+    fn quicksaves_load_back_from_memory() {
+        // 0x200: ADD V0, 0x01, LD I, 0x300, LD [I], V0, JP 0x200
+        let mut cpu = cpu(&[0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00]);
+        for _ in 0..10 {
+            cpu.step().unwrap();
+        }
+        let quicksave = cpu.save_state();
+        let saved = cpu.capture_state();
+
+        for _ in 0..25 {
+            cpu.step().unwrap();
+        }
+        assert!(cpu.registers()[0] != saved.registers[0]);
+
+        cpu.load_state(&quicksave).unwrap();
+        assert_eq!(cpu.capture_state(), saved);
+
+        // Execution carries on from where the state was saved.
+        for _ in 0..25 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.registers()[0], 9);
+        assert_eq!(cpu.interconnect.memory.read(0x300), 9);
+    }
+
+    #[test]
+    fn bad_quicksaves_leave_the_machine_alone() {
+        let mut cpu = cpu(&[0x70, 0x01, 0x12, 0x00]);
+        cpu.step().unwrap();
+        let before = cpu.capture_state();
+
+        let mut quicksave = cpu.save_state();
+        quicksave.truncate(20);
+        assert!(cpu.load_state(&quicksave).is_err());
+        assert!(cpu.load_state(b"NOTCH").is_err());
+        assert_eq!(cpu.capture_state(), before);
+    }
+
+    #[test]
+    fn rewinding_steps_back_through_earlier_frames() {
+        // 0x200: ADD V0, 0x01, JP 0x200
+        let mut cpu = cpu(&[0x70, 0x01, 0x12, 0x00]);
+        cpu.set_cycles_per_frame(4);
+        let mut frames = Vec::new();
+        for _ in 0..5 {
+            for _ in 0..4 {
+                cpu.step().unwrap();
             }
+            frames.push(cpu.capture_state());
+        }
+
+        // The last frame ends as the next one starts.
+        cpu.step().unwrap();
+
+        while let Some(frame) = frames.pop() {
+            assert!(cpu.rewind());
+            assert_eq!(cpu.capture_state(), frame);
+        }
+        assert_eq!(cpu.registers()[0], 2);
+        assert!(!cpu.rewind());
+    }
+
+    #[test]
+    fn rewinding_keeps_at_most_the_capacity() {
+        let mut cpu = cpu(&[0x70, 0x01, 0x12, 0x00]);
+        cpu.set_rewind_capacity(3);
+        cpu.set_cycles_per_frame(1);
+        for _ in 0..10 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.rewind_buffer.len(), 3);
+
+        cpu.set_rewind_capacity(1);
+        assert_eq!(cpu.rewind_buffer.len(), 1);
+        assert!(cpu.rewind());
+        assert!(!cpu.rewind());
+    }
+
+    /// Runs the first `steps` instructions of `rom` with the passed quirks.
+    fn run_with(quirks: Quirks, rom: &[u8], steps: usize) -> Cpu {
+        let mut cpu = cpu(rom);
+        cpu.set_quirks(quirks);
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+        cpu
+    }
+
+    #[test]
+    fn vip_logic_resets_vf() {
+        // LD VF, 0x05, LD V0, 0x03, LD V1, 0x05, OR V0, V1
+        let rom = [0x6F, 0x05, 0x60, 0x03, 0x61, 0x05, 0x80, 0x11];
+
+        assert_eq!(run_with(Quirks::cosmac_vip(), &rom, 4).registers()[0xF], 0);
+        assert_eq!(run_with(Quirks::modern(), &rom, 4).registers()[0xF], 5);
+    }
+
+    #[test]
+    fn vip_shifts_vy_into_vx() {
+        // LD V0, 0x01, LD V1, 0x06, SHR V0, V1
+        let rom = [0x60, 0x01, 0x61, 0x06, 0x80, 0x16];
+
+        let vip = run_with(Quirks::cosmac_vip(), &rom, 3).registers();
+        assert_eq!((vip[0], vip[0xF]), (0x03, 0));
+        let modern = run_with(Quirks::modern(), &rom, 3).registers();
+        assert_eq!((modern[0], modern[0xF]), (0x00, 1));
+    }
+
+    #[test]
+    fn vip_register_transfers_move_i() {
+        // LD I, 0x300, LD [I], V2
+        let rom = [0xA3, 0x00, 0xF2, 0x55];
+
+        assert_eq!(run_with(Quirks::cosmac_vip(), &rom, 2).i(), 0x303);
+        assert_eq!(run_with(Quirks::schip(), &rom, 2).i(), 0x300);
+    }
+
+    #[test]
+    fn schip_jumps_add_the_register_named_by_the_address() {
+        // LD V0, 0x02, LD V3, 0x06, JP V0, 0x300
+        let rom = [0x60, 0x02, 0x63, 0x06, 0xB3, 0x00];
+
+        assert_eq!(run_with(Quirks::cosmac_vip(), &rom, 3).pc(), 0x302);
+        assert_eq!(run_with(Quirks::schip(), &rom, 3).pc(), 0x306);
+    }
+
+    #[test]
+    fn vip_sprites_clip_at_the_edge() {
+        // LD V0, 0x3C, LD V1, 0x00, LD I, 0x20A, DRW V0, V1, 1, JP 0x208,
+        // then the sprite, a row of eight pixels.
+        let rom = [0x60, 0x3C, 0x61, 0x00, 0xA2, 0x0A, 0xD0, 0x11, 0x12, 0x08, 0xFF, 0x00];
+        let row = |cpu: Cpu| cpu.interconnect.framebuffer()[..64].to_vec();
+
+        let vip = row(run_with(Quirks::cosmac_vip(), &rom, 4));
+        let modern = row(run_with(Quirks::modern(), &rom, 4));
+        assert!(vip[60..].iter().chain(&modern[60..]).all(|&pixel| pixel != 0));
+        assert_eq!(vip[..4], [0; 4]);
+        assert!(modern[..4].iter().all(|&pixel| pixel != 0));
+    }
+
+    #[test]
+    fn accessors_read_back_loaded_values() {
+        // 0x200: LD V0, 0x11, LD VE, 0x22, LD I, 0x345, LD DT, V0, LD ST, VE,
+        // CALL 0x20C
+        // 0x20C: JP 0x20C
+        let mut cpu = cpu(&[0x60, 0x11, 0x6E, 0x22, 0xA3, 0x45, 0xF0, 0x15, 0xFE, 0x18,
+                            0x22, 0x0C, 0x12, 0x0C]);
+        cpu.set_cycles_per_frame(100);
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+
+        let mut registers = [0; 16];
+        registers[0x0] = 0x11;
+        registers[0xE] = 0x22;
+        assert_eq!(cpu.registers(), registers);
+        assert_eq!(cpu.i(), 0x345);
+        assert_eq!(cpu.dt(), 0x11);
+        assert_eq!(cpu.st(), 0x22);
+        assert_eq!(cpu.sp(), 1);
+        assert_eq!(cpu.pc(), 0x20C);
+        assert_eq!(cpu.cycles(), 6);
+    }
+
+    /// A rom of `count` SYS instructions, which do nothing.
+    fn sys_rom(count: usize) -> Vec<u8> {
+        [0x01, 0x23].iter().cloned().cycle().take(count * 2).collect()
+    }
+
+    #[test]
+    fn run_for_stops_after_exactly_the_quota() {
+        let mut cpu = cpu(&sys_rom(150));
+
+        assert_eq!(cpu.run_for(100).unwrap(), (100, Stopped::CycleLimit));
+        assert_eq!(cpu.cycles(), 100);
+        assert_eq!(cpu.pc(), 0x200 + 200);
+
+        assert_eq!(cpu.run_for(0).unwrap(), (0, Stopped::CycleLimit));
+        assert_eq!(cpu.pc(), 0x200 + 200);
+    }
+
+    #[test]
+    fn sys_calls_are_skipped() {
+        // 0x200: SYS 0x123, SYS 0xFFF, LD V0, 0x01
+        let mut cpu = cpu(&[0x01, 0x23, 0x0F, 0xFF, 0x60, 0x01]);
+        let trace = SharedBuffer::default();
+        cpu.trace_to(Box::new(trace.clone()));
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), 0x202);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), 0x206);
+        assert_eq!(cpu.registers()[0], 0x01);
+
+        let trace = String::from_utf8(trace.0.borrow().clone()).unwrap();
+        assert!(trace.lines().next().unwrap().contains("SYS 0x123"));
+    }
+
+    #[test]
+    fn cycles_count_every_instruction_until_a_reset() {
+        let mut cpu = cpu(&sys_rom(100));
+        cpu.set_cycles_per_frame(10);
+
+        cpu.run_for(37).unwrap();
+        for _ in 0..5 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.cycles(), 42);
+        assert_eq!(cpu.frames(), 4);
+
+        cpu.reset();
+        assert_eq!((cpu.cycles(), cpu.frames()), (0, 0));
+    }
+
+    #[test]
+    fn frames_end_after_the_configured_number_of_instructions() {
+        // 0x200: LD V0, 0x3C, LD DT, V0, JP 0x204
+        let rom = [0x60, 0x3C, 0xF0, 0x15, 0x12, 0x04];
+
+        for &cycles in [10, 20].iter() {
+            let mut cpu = cpu(&rom);
+            cpu.set_cycles_per_frame(cycles);
+            cpu.run_for(cycles * 20 + 2).unwrap();
+
+            // Each frame ends as the first instruction of the next starts,
+            // and ticks the delay timer once.
+            assert_eq!(cpu.frames(), 20);
+            assert_eq!(cpu.dt(), 0x3C - 20);
+        }
+    }
+
+    #[test]
+    fn zero_words_halt_the_run() {
+        // 0x200: LD V0, 0x01, LD V1, 0x02, then the zero padding after the rom.
+        let mut cpu = cpu(&[0x60, 0x01, 0x61, 0x02]);
+
+        assert_eq!(cpu.run().unwrap(), Stopped::Halted);
+        assert_eq!(cpu.cycles(), 3);
+        assert_eq!(cpu.pc(), 0x206);
+        assert_eq!(cpu.registers()[..2], [0x01, 0x02]);
+
+        // A halted machine stays halted until it is reset.
+        assert_eq!(cpu.run_for(10).unwrap(), (0, Stopped::Halted));
+        cpu.reset();
+        assert_eq!(cpu.run_for(2).unwrap(), (2, Stopped::CycleLimit));
+    }
+
+    #[test]
+    fn overlapping_sprites_count_erased_pixels_but_flag_vf_once() {
+        // 0x200: LD I, 0x20C, DRW V0, V0, 1, LD I, 0x20D, DRW V0, V0, 1,
+        // JP 0x208, a padding word, then the sprites 0xFF and 0x3C.
+        let rom = [0xA2, 0x0C, 0xD0, 0x01, 0xA2, 0x0D, 0xD0, 0x01, 0x12, 0x08, 0x00, 0x00,
+                   0xFF, 0x3C];
+        let mut cpu = cpu(&rom);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.interconnect.last_collision_count(), 0);
+        assert_eq!(cpu.registers()[0xF], 0);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.interconnect.last_collision_count(), 4);
+        assert_eq!(cpu.registers()[0xF], 1);
+        assert_eq!(cpu.interconnect.framebuffer_packed()[0], 0b1100_0011);
+    }
+
+    #[test]
+    fn sprites_past_the_end_of_ram_fail_unless_padded() {
+        // 0x200: LD I, 0xFFE, DRW V0, V0, 4
+        let rom = [0xAF, 0xFE, 0xD0, 0x04];
+
+        let mut strict = cpu(&rom);
+        strict.step().unwrap();
+        match strict.step() {
+            Err(CpuError::AddressOutOfRange { target, len, addr }) => {
+                assert_eq!((target, len, addr), (0xFFE, 4, 0x202));
+            },
+            other => panic!("expected an access out of range, got {:?}", other),
+        }
+
+        let mut cpu = cpu(&rom);
+        cpu.set_quirks(Quirks { pad_sprites: true, ..Quirks::default() });
+        cpu.interconnect.poke(0xFFE, 0xF0).unwrap();
+        cpu.interconnect.poke(0xFFF, 0x0F).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let rows: Vec<u8> = cpu.interconnect.framebuffer_packed()
+            .chunks(8)
+            .take(4)
+            .map(|row| row[0])
+            .collect();
+        assert_eq!(rows, [0xF0, 0x0F, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn frame_budgets_scale_with_the_speed() {
+        let mut cpu = cpu(&sys_rom(1));
+
+        assert_eq!(cpu.frame_budget(10), 10);
+        cpu.set_speed_multiplier(2.0);
+        assert_eq!(cpu.frame_budget(10), 20);
+        cpu.set_speed_multiplier(0.5);
+        assert_eq!(cpu.frame_budget(10), 5);
+
+        // Speeds are kept within the limits and frames run at least one
+        // instruction.
+        cpu.set_speed_multiplier(100.0);
+        assert_eq!(cpu.frame_budget(10), 80);
+        cpu.set_speed_multiplier(0.0);
+        assert_eq!(cpu.frame_budget(10), 3);
+        assert_eq!(cpu.frame_budget(1), 1);
+    }
+
+    #[test]
+    fn faster_speeds_fit_more_instructions_in_a_frame() {
+        let mut cpu = cpu(&[0x12, 0x00]);
+        cpu.set_cycles_per_frame(10);
+        cpu.set_speed_multiplier(2.0);
+
+        cpu.run_for(201).unwrap();
+        assert_eq!(cpu.frames(), 10);
+    }
+
+    #[test]
+    fn turbo_runs_repeat() {
+        // 0x200: RND V0, 0xFF, RND V1, 0x1F, LD I, 0x20C, DRW V0, V1, 1,
+        // JP 0x200, with a one row sprite at 0x20C.
+        let rom = [0xC0, 0xFF, 0xC1, 0x1F, 0xA2, 0x0C, 0xD0, 0x11, 0x12, 0x00,
+                   0x00, 0x00, 0xF0];
+        let mut first = cpu(&rom);
+        let mut second = cpu(&rom);
+        assert_eq!(first.run_for(100_000).unwrap(), (100_000, Stopped::CycleLimit));
+        assert_eq!(second.run_for(100_000).unwrap(), (100_000, Stopped::CycleLimit));
+
+        assert!(first.frames() > 0);
+        assert_eq!(first.frames(), second.frames());
+        assert_eq!(first.capture_state(), second.capture_state());
+    }
+
+    #[test]
+    fn beep_listeners_follow_the_sound_timer() {
+        // 0x200: LD V0, 0x02, LD ST, V0, JP 0x204
+        let mut cpu = cpu(&[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04]);
+        let log = BeepLog::default();
+        cpu.interconnect.sound.add_listener(Box::new(log.clone()));
+        cpu.set_cycles_per_frame(4);
+
+        cpu.run_for(6).unwrap();
+        assert!(cpu.is_beeping());
+        assert_eq!(*log.0.borrow(), [true]);
+
+        // Two frames later the sound timer has run out.
+        cpu.run_for(8).unwrap();
+        assert!(!cpu.is_beeping());
+        assert_eq!(*log.0.borrow(), [true, false]);
+    }
+
+    #[test]
+    fn shutdown_finishes_audio_recordings() {
+        let path = env::temp_dir().join(format!("notch-{}-shutdown.wav", process::id()));
+
+        // 0x200: LD V0, 0x3C, LD ST, V0, JP 0x204
+        let mut cpu = cpu(&[0x60, 0x3C, 0xF0, 0x18, 0x12, 0x04]);
+        cpu.interconnect.sound.record(WavRecorder::create(&path).unwrap());
+        cpu.set_cycles_per_frame(4);
+        sleep(Duration::from_millis(10));
+        cpu.run_for(10).unwrap();
+        assert!(cpu.is_beeping());
+
+        cpu.shutdown();
+        assert!(!cpu.interconnect.sound.beeping());
+
+        // The header holds the final size of the samples that were written.
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let data_size = wav.len() as u32 - 44;
+        assert!(data_size > 0);
+        assert_eq!(wav[40..44], data_size.to_le_bytes());
+    }
+
+    #[test]
+    fn adding_to_i_leaves_vf_alone_by_default() {
+        // 0x200: LD VF, 0x07, LD I, 0xFFF, LD V0, 0x02, ADD I, V0
+        let rom = [0x6F, 0x07, 0xAF, 0xFF, 0x60, 0x02, 0xF0, 0x1E];
+        let cpu = run_with(Quirks::default(), &rom, 4);
+
+        assert_eq!(cpu.i(), 0x1001);
+        assert_eq!(cpu.registers()[0xF], 0x07);
+    }
+
+    #[test]
+    fn adding_to_i_flags_overflow_with_the_quirk() {
+        let quirks = Quirks { i_overflow_flag: true, ..Quirks::default() };
+
+        // 0x200: LD VF, 0x07, LD I, 0xFFE, LD V0, 0x01, ADD I, V0, ADD I, V0
+        let rom = [0x6F, 0x07, 0xAF, 0xFE, 0x60, 0x01, 0xF0, 0x1E, 0xF0, 0x1E];
+        let mut chip8 = run_with(quirks, &rom, 4);
+        assert_eq!(chip8.i(), 0xFFF);
+        assert_eq!(chip8.registers()[0xF], 0);
+
+        chip8.step().unwrap();
+        assert_eq!(chip8.i(), 0x1000);
+        assert_eq!(chip8.registers()[0xF], 1);
+
+        // XO-CHIP addresses all 64kB, so I only overflows past 0xFFFF.
+        let mut xochip = cpu(&rom);
+        xochip.enable_xochip();
+        xochip.set_quirks(quirks);
+        for _ in 0..5 {
+            xochip.step().unwrap();
+        }
+        assert_eq!(xochip.i(), 0x1000);
+        assert_eq!(xochip.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn chip8x_colors_show_up_in_the_frame() {
+        // 0x200: LD V0, 0x11, LD V1, 0x00, LD V2, 0x01, COL V0, V2, 0, BGND,
+        // LD V3, 0x08, LD I, 0x212, DRW V3, V4, 1, JP 0x210, with a one pixel
+        // sprite at 0x212.
+        let mut cpu = cpu(&[0x60, 0x11, 0x61, 0x00, 0x62, 0x01, 0xB0, 0x20, 0x02, 0xA0,
+                            0x63, 0x08, 0xA2, 0x12, 0xD3, 0x41, 0x12, 0x10, 0x80]);
+        cpu.enable_chip8x();
+        cpu.run_for(9).unwrap();
+
+        // The lit pixel is in the red blocks, the rest shows the first
+        // background color, blue.
+        let (width, _, image) = cpu.interconnect.graphics.screenshot(1);
+        let pixel = |x: usize, y: usize| &image[(y * width + x) * 4..(y * width + x + 1) * 4];
+        assert_eq!(pixel(8, 0), &[0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(0, 0), &[0x00, 0x00, 0x80, 0xFF]);
+        assert_eq!(pixel(9, 0), &[0x00, 0x00, 0x80, 0xFF]);
+    }
+
+    /// Holds exactly the passed keys from the next frame on.
+    fn hold_keys(cpu: &mut Cpu, keys: &[usize]) {
+        let mut held = [false; 16];
+        for &key in keys {
+            held[key] = true;
+        }
+        cpu.interconnect.input.replay(Some(held));
+        cpu.interconnect.input.next_frame();
+    }
+
+    #[test]
+    fn waiting_for_a_key_takes_the_lowest_of_several_presses() {
+        // 0x200: LD V0, K, JP 0x202
+        let mut cpu = cpu(&[0xF0, 0x0A, 0x12, 0x02]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), 0x200);
+
+        hold_keys(&mut cpu, &[0x7, 0x3]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), 0x202);
+        assert_eq!(cpu.registers()[0], 0x3);
+    }
+
+    #[test]
+    fn waiting_for_a_key_ignores_earlier_presses() {
+        // 0x200: LD V0, K, JP 0x202
+        let mut cpu = cpu(&[0xF0, 0x0A, 0x12, 0x02]);
+        hold_keys(&mut cpu, &[0x5]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), 0x200);
+
+        // Releasing the key isn't a press either, pressing another is.
+        hold_keys(&mut cpu, &[]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), 0x200);
+
+        hold_keys(&mut cpu, &[0x9]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), 0x202);
+        assert_eq!(cpu.registers()[0], 0x9);
+    }
+
+    #[test]
+    fn dumps_show_the_registers_and_timers() {
+        // 0x200: LD V3, 0x2A, LD I, 0x300, LD V0, 0x05, LD DT, V0, LD ST, V0,
+        // CALL 0x20E, 0x20E: JP 0x20E
+        let mut cpu = cpu(&[0x63, 0x2A, 0xA3, 0x00, 0x60, 0x05, 0xF0, 0x15, 0xF0, 0x18,
+                            0x22, 0x0E, 0x00, 0x00, 0x12, 0x0E]);
+        cpu.run_for(6).unwrap();
+
+        let dump = cpu.dump();
+        assert!(dump.contains("pc: 0x020e  i: 0x0300  sp: 1"), "{}", dump);
+        assert!(dump.contains("dt: 0x05    st: 0x05"), "{}", dump);
+        assert!(dump.contains("v0: 05  v1: 00  v2: 00  v3: 2a"), "{}", dump);
+        assert!(dump.contains("vf: 00"), "{}", dump);
+        assert!(dump.contains("stack: 0x020a\n"), "{}", dump);
+    }
+
+    #[test]
+    fn filled_registers_hold_the_pattern_before_execution() {
+        // 0x200: LD V0, 0x01
+        let mut cpu = cpu(&[0x60, 0x01]);
+        assert_eq!(cpu.registers(), [0; 16]);
+
+        cpu.fill_registers(0xA5);
+        assert_eq!(cpu.registers(), [0xA5; 16]);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers()[0], 0x01);
+        assert_eq!(cpu.registers()[1..], [0xA5; 15]);
+    }
+
+    #[test]
+    fn font_lookups_point_at_the_glyph() {
+        // 0x200: LD V0, 0x0A, LD F, V0, LD V1, 0x1A, LD F, V1
+        let mut cpu = cpu(&[0x60, 0x0A, 0xF0, 0x29, 0x61, 0x1A, 0xF1, 0x29]);
+        cpu.run_for(2).unwrap();
+
+        let glyph = FONT_OFFSET + 0xA * CHARACTER_SIZE;
+        assert_eq!(cpu.i() as usize, glyph);
+        assert_eq!(cpu.interconnect.peek_range(glyph, CHARACTER_SIZE).unwrap(),
+                   &[0xF0, 0x90, 0xF0, 0x90, 0x90]);
+
+        // Only the low nibble selects the glyph.
+        cpu.i = 0;
+        cpu.run_for(2).unwrap();
+        assert_eq!(cpu.i() as usize, glyph);
+    }
+
+    #[test]
+    fn stored_registers_load_back() {
+        // 0x200: LD V0, 0x11, LD V1, 0x22, LD V2, 0x33, LD V3, 0x44,
+        // LD I, 0x300, LD [I], V3, LD V1, [I], LD V3, [I]
+        let mut cpu = cpu(&[0x60, 0x11, 0x61, 0x22, 0x62, 0x33, 0x63, 0x44,
+                            0xA3, 0x00, 0xF3, 0x55, 0xF1, 0x65, 0xF3, 0x65]);
+        cpu.run_for(6).unwrap();
+        assert_eq!(cpu.interconnect.peek_range(0x300, 5).unwrap(),
+                   &[0x11, 0x22, 0x33, 0x44, 0x00]);
+        assert_eq!(cpu.i(), 0x300);
+
+        // Loads only fill the registers up to VX.
+        cpu.fill_registers(0xFF);
+        cpu.run_for(1).unwrap();
+        assert_eq!(cpu.registers()[..5], [0x11, 0x22, 0xFF, 0xFF, 0xFF]);
+
+        cpu.run_for(1).unwrap();
+        assert_eq!(cpu.registers()[..5], [0x11, 0x22, 0x33, 0x44, 0xFF]);
+    }
+
+    #[test]
+    fn every_register_is_addressed_on_its_own() {
+        for reg in 0x0..0x10 {
+            // 0x200: LD VX, 0x10 + X, ADD VX, 0x01, LD VY, VX, Y being the
+            // next register.
+            let next = (reg + 1) & 0xF;
+            let rom = [0x60 | reg, 0x10 + reg, 0x70 | reg, 0x01, 0x80 | next, reg << 4];
+            let mut cpu = cpu(&rom);
+            cpu.run_for(3).unwrap();
+
+            let mut expected = [0; 16];
+            expected[reg as usize] = 0x11 + reg;
+            expected[next as usize] = 0x11 + reg;
+            assert_eq!(cpu.registers(), expected, "register V{:X}", reg);
         }
     }
+
+    #[test]
+    fn long_loads_set_i_and_skip_the_address() {
+        // 0x200: LD I, 0xABCD, LD [I], V0, LD V1, 0x01
+        let rom = [0xF0, 0x00, 0xAB, 0xCD, 0xF0, 0x55, 0x61, 0x01];
+        let interconnect = Interconnect::headless(&rom[..], XO_RAM_SIZE).unwrap();
+        let mut cpu = Cpu::with_seed(interconnect, 0);
+        cpu.enable_turbo();
+        cpu.enable_xochip();
+        cpu.fill_registers(0x5A);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.i(), 0xABCD);
+        assert_eq!(cpu.pc(), 0x204);
+
+        cpu.run_for(2).unwrap();
+        assert_eq!(cpu.interconnect.peek(0xABCD).unwrap(), 0x5A);
+        assert_eq!(cpu.registers()[1], 0x01);
+        assert_eq!(cpu.pc(), 0x208);
+    }
 }
@@ -0,0 +1,75 @@
+use super::instruction::{self, Extensions};
+
+/// Decodes the instructions stored between `start` and `end` of ram into
+/// mnemonics, one for every two bytes or four for F000 NNNN. Every line
+/// comes with the address it starts at and its length in bytes. Words that
+/// don't decode to a known instruction, data mixed in with the code for
+/// example, show up as `DB 0xNNNN`. A trailing odd byte is shown as `DB 0xNN`.
+pub fn disassemble(ram: &[u8], start: usize, end: usize) -> Vec<(usize, usize, String)> {
+    let mut lines = Vec::new();
+    let mut addr = start;
+
+    while addr + 1 < end {
+        let instr = read_word(ram, addr);
+
+        // The XO-CHIP long load carries its address in the following word.
+        if instr == 0xF000 && addr + 3 < end {
+            let long = read_word(ram, addr + 2);
+            lines.push((addr, 4, format!("LD I, 0x{:04X}", long)));
+            addr += 4;
+            continue;
+        }
+
+        lines.push((addr, 2, mnemonic(instr, addr as u16)));
+        addr += 2;
+    }
+    if addr < end {
+        lines.push((addr, 1, format!("DB 0x{:02X}", ram[addr])));
+    }
+
+    lines
+}
+
+/// Reads the big endian word at `addr`, which can be at any alignment.
+fn read_word(ram: &[u8], addr: usize) -> u16 {
+    (ram[addr] as u16) << 8 | ram[addr + 1] as u16
+}
+
+/// Decodes the instruction read from `addr` into its mnemonic. Super-CHIP
+/// and XO-CHIP instructions are always decoded since the mode a rom runs in
+/// isn't known from its bytes alone.
+pub fn mnemonic(instr: u16, addr: u16) -> String {
+    let extensions = Extensions { chip8x: false, schip: true, xochip: true };
+
+    match instruction::decode(instr, addr, extensions) {
+        Ok(decoded) => decoded.to_string(),
+        Err(_) => format!("DB 0x{:04X}", instr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_bytes_disassemble_to_mnemonics() {
+        let code = [0x60, 0x1F, 0xD1, 0x25, 0x22, 0x08, 0x00, 0xEE, 0xFF, 0xFF, 0x12];
+        let lines: Vec<String> = disassemble(&code, 0, code.len())
+            .into_iter()
+            .map(|(_, _, line)| line)
+            .collect();
+        assert_eq!(lines, ["LD V0, 0x1F", "DRW V1, V2, 5", "CALL 0x208", "RET", "DB 0xFFFF",
+                           "DB 0x12"]);
+    }
+
+    #[test]
+    fn long_loads_take_up_four_bytes() {
+        let code = [0x60, 0x01, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0, 0xF0];
+        assert_eq!(disassemble(&code, 0, code.len()), [
+            (0, 2, "LD V0, 0x01".to_string()),
+            (2, 4, "LD I, 0x1234".to_string()),
+            (6, 2, "CLS".to_string()),
+            (8, 1, "DB 0xF0".to_string()),
+        ]);
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+
+use super::sdl2::pixels::{Color, PixelFormatEnum};
+use super::sdl2::render::{Renderer, Texture};
+use super::sdl2::video::FullscreenType;
+use super::sdl2;
+
+// Bytes per pixel of the frames passed to displays, one each for red, green
+// and blue.
+pub const BYTES_PER_PIXEL: usize = 3;
+
+// How many window pixels make up one CHIP-8 pixel unless chosen otherwise.
+pub const DEFAULT_SCALE: u32 = 10;
+
+// Name of the window, followed by the emulation speed when it isn't normal.
+pub const WINDOW_TITLE: &str = "Notch";
+
+/// Somewhere to show the emulated display. Frames are passed as rows of RGB
+/// pixels, three bytes per pixel.
+pub trait Display {
+    /// Replaces the pending frame with the passed one.
+    fn draw(&mut self, buffer: &[u8], width: usize, height: usize);
+
+    /// Clears the pending frame to black.
+    fn clear(&mut self);
+
+    /// Shows the pending frame.
+    fn present(&mut self);
+
+    /// Switches between a window and the whole screen.
+    fn toggle_fullscreen(&mut self);
+
+    /// Names the window the display is shown in, if there is one.
+    fn set_title(&mut self, title: &str);
+}
+
+/// Shows frames in an SDL window.
+pub struct SdlDisplay {
+    // Declared before the renderer so it is destroyed first. Recreated when
+    // the size of the frames changes.
+    texture: Texture,
+    texture_size: (usize, usize),
+    renderer: Renderer<'static>,
+
+    // The size of the window before it went fullscreen, restored when it
+    // leaves fullscreen. None while windowed.
+    windowed_size: Option<(u32, u32)>,
+}
+
+impl SdlDisplay {
+    /// Opens a window the passed display size multiplied by `scale`, or
+    /// covering the whole screen when `fullscreen` is set. The CHIP-8 display
+    /// is very small for today's standards. Frames are scaled up to fill the
+    /// window, keeping their aspect ratio with black bars where the window is
+    /// wider or taller than the display.
+    pub fn new(sdl_context: &sdl2::Sdl, width: usize, height: usize, scale: u32,
+               fullscreen: bool) -> SdlDisplay {
+        assert!(scale >= 1, "Display scale must be at least 1");
+        let video_subsystem = sdl_context.video().unwrap();
+
+        let window = video_subsystem.window(WINDOW_TITLE,
+                                            width as u32 * scale,
+                                            height as u32 * scale)
+            .position_centered()
+            .build()
+            .unwrap();
+
+        let mut renderer = window.renderer().build().unwrap();
+        renderer.set_logical_size(width as u32, height as u32).unwrap();
+        let texture = renderer
+            .create_texture_streaming(PixelFormatEnum::RGB24, (width as u32, height as u32))
+            .unwrap();
+
+        // Clear the screen to black.
+        renderer.set_draw_color(Color::RGB(0, 0, 0));
+        renderer.clear();
+        renderer.present();
+
+        let mut display = SdlDisplay {
+            texture: texture,
+            texture_size: (width, height),
+            renderer: renderer,
+            windowed_size: None,
+        };
+        if fullscreen {
+            display.toggle_fullscreen();
+        }
+
+        display
+    }
+}
+
+impl Display for SdlDisplay {
+    fn draw(&mut self, buffer: &[u8], width: usize, height: usize) {
+        if self.texture_size != (width, height) {
+            self.texture = self.renderer
+                .create_texture_streaming(PixelFormatEnum::RGB24, (width as u32, height as u32))
+                .unwrap();
+            self.renderer.set_logical_size(width as u32, height as u32).unwrap();
+            self.texture_size = (width, height);
+        }
+
+        self.texture.update(None, buffer, width * BYTES_PER_PIXEL).unwrap();
+        self.renderer.copy(&self.texture, None, None);
+    }
+
+    fn clear(&mut self) {
+        self.renderer.set_draw_color(Color::RGB(0, 0, 0));
+        self.renderer.clear();
+    }
+
+    fn present(&mut self) {
+        self.renderer.present();
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let window = match self.renderer.window_mut() {
+            Some(window) => window,
+            None => return,
+        };
+
+        let result = match self.windowed_size.take() {
+            Some((width, height)) => {
+                let result = window.set_fullscreen(FullscreenType::Off);
+                window.set_size(width, height);
+                result
+            },
+            None => {
+                self.windowed_size = Some(window.size());
+                window.set_fullscreen(FullscreenType::Desktop)
+            },
+        };
+
+        if let Err(why) = result {
+            println!("notch: cannot toggle fullscreen: {}", why);
+        }
+    }
+
+    fn set_title(&mut self, title: &str) {
+        if let Some(window) = self.renderer.window_mut() {
+            window.set_title(title);
+        }
+    }
+}
+
+/// Shows frames nowhere. The display buffer kept by graphics is all there
+/// is, which is enough to run roms without a window.
+pub struct HeadlessDisplay;
+
+impl Display for HeadlessDisplay {
+    fn draw(&mut self, _buffer: &[u8], _width: usize, _height: usize) {}
+
+    fn clear(&mut self) {}
+
+    fn present(&mut self) {}
+
+    fn toggle_fullscreen(&mut self) {}
+
+    fn set_title(&mut self, _title: &str) {}
+}
+
+/// Shows frames in the terminal with ANSI escapes, for watching roms over
+/// SSH. Every character is an upper half block colored in 24-bit color, the
+/// top pixel as the foreground and the one below it as the background, so
+/// one line of text covers two rows of pixels.
+pub struct TerminalDisplay {
+    // The pending frame, already rendered to text.
+    frame: String,
+
+    // Size of the last frame shown, the screen is cleared when it changes.
+    shown_size: Option<(usize, usize)>,
+}
+
+impl TerminalDisplay {
+    pub fn new() -> TerminalDisplay {
+        TerminalDisplay {
+            frame: String::new(),
+            shown_size: None,
+        }
+    }
+
+    /// Renders a frame to text. The cursor is moved to the top left first so
+    /// every frame draws over the last one.
+    pub fn render(buffer: &[u8], width: usize, height: usize) -> String {
+        let mut out = String::from("\x1b[H");
+
+        for y in (0..height).step_by(2) {
+            for x in 0..width {
+                let top = (y * width + x) * BYTES_PER_PIXEL;
+                let bottom = top + width * BYTES_PER_PIXEL;
+                let (r, g, b) = (buffer[top], buffer[top + 1], buffer[top + 2]);
+                write!(out, "\x1b[38;2;{};{};{}m", r, g, b).unwrap();
+
+                // An odd row at the bottom has nothing below it.
+                if y + 1 < height {
+                    let (r, g, b) = (buffer[bottom], buffer[bottom + 1], buffer[bottom + 2]);
+                    write!(out, "\x1b[48;2;{};{};{}m", r, g, b).unwrap();
+                }
+                out.push('\u{2580}');
+            }
+            out.push_str("\x1b[0m\n");
+        }
+
+        out
+    }
+}
+
+impl Display for TerminalDisplay {
+    fn draw(&mut self, buffer: &[u8], width: usize, height: usize) {
+        if self.shown_size != Some((width, height)) {
+            self.frame.push_str("\x1b[2J");
+            self.shown_size = Some((width, height));
+        }
+        self.frame.push_str(&TerminalDisplay::render(buffer, width, height));
+    }
+
+    fn clear(&mut self) {
+        self.frame.clear();
+    }
+
+    fn present(&mut self) {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let result = out.write_all(self.frame.as_bytes()).and_then(|_| out.flush());
+
+        if let Err(why) = result {
+            println!("notch: cannot draw to the terminal: {}", why);
+        }
+    }
+
+    fn toggle_fullscreen(&mut self) {}
+
+    fn set_title(&mut self, _title: &str) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: [u8; 3] = [255, 255, 255];
+    const BLACK: [u8; 3] = [0, 0, 0];
+
+    #[test]
+    fn terminal_frames_pair_rows_into_half_blocks() {
+        // Two pixels wide and three high, a checkerboard.
+        let rows = [[WHITE, BLACK], [BLACK, WHITE], [WHITE, BLACK]];
+        let buffer: Vec<u8> = rows.iter()
+            .flat_map(|row| row.iter())
+            .flat_map(|pixel| pixel.iter())
+            .cloned()
+            .collect();
+
+        assert_eq!(TerminalDisplay::render(&buffer, 2, 3),
+                   "\x1b[H\
+                    \x1b[38;2;255;255;255m\x1b[48;2;0;0;0m\u{2580}\
+                    \x1b[38;2;0;0;0m\x1b[48;2;255;255;255m\u{2580}\x1b[0m\n\
+                    \x1b[38;2;255;255;255m\u{2580}\
+                    \x1b[38;2;0;0;0m\u{2580}\x1b[0m\n");
+    }
+
+    #[test]
+    fn terminals_are_cleared_when_the_resolution_changes() {
+        let mut display = TerminalDisplay::new();
+
+        display.draw(&[0; 2 * 2 * BYTES_PER_PIXEL], 2, 2);
+        assert!(display.frame.starts_with("\x1b[2J\x1b[H"));
+        display.clear();
+        display.draw(&[0; 2 * 2 * BYTES_PER_PIXEL], 2, 2);
+        assert!(display.frame.starts_with("\x1b[H"));
+        display.clear();
+        display.draw(&[0; 4 * 2 * BYTES_PER_PIXEL], 4, 2);
+        assert!(display.frame.starts_with("\x1b[2J"));
+    }
+}
@@ -0,0 +1,136 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Reasons the cpu can stop executing a rom. Every variant carries the
+/// address of the instruction that failed.
+#[derive(Debug)]
+pub enum CpuError {
+    // The instruction doesn't decode to any known opcode.
+    UnknownOpcode { instr: u16, addr: u16 },
+
+    // A CALL nested deeper than the call stack allows, `sp` being the
+    // stack pointer at the time.
+    StackOverflow { sp: u8, addr: u16 },
+
+    // A RET without a subroutine to return from.
+    StackUnderflow { sp: u8, addr: u16 },
+
+    // An access to ram started at `target` and reached past the end of ram.
+    AddressOutOfRange { target: usize, len: usize, addr: u16 },
+
+    // The instruction names a register the operation can't use.
+    BadRegister { reg: u8, addr: u16 },
+
+    // A key instruction was passed a value that isn't a keypad key.
+    InvalidKey { key: u8, addr: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuError::UnknownOpcode { instr, addr } => {
+                write!(f, "unknown opcode {:#06x} at addr: {:#x}", instr, addr)
+            },
+            CpuError::StackOverflow { sp, addr } => {
+                write!(f, "stack overflow, too many nested calls (sp: {}) at \
+                           addr: {:#x}", sp, addr)
+            },
+            CpuError::StackUnderflow { sp, addr } => {
+                write!(f, "stack underflow, RET outside of a subroutine (sp: {}) at \
+                           addr: {:#x}", sp, addr)
+            },
+            CpuError::AddressOutOfRange { target, len, addr } => {
+                write!(f, "access of {} bytes at {:#x} is out of range at \
+                           addr: {:#x}", len, target, addr)
+            },
+            CpuError::BadRegister { reg, addr } => {
+                write!(f, "register V{:X} cannot be used at addr: {:#x}", reg, addr)
+            },
+            CpuError::InvalidKey { key, addr } => {
+                write!(f, "key {:#04x} is not on the keypad at addr: {:#x}", key, addr)
+            },
+        }
+    }
+}
+
+impl error::Error for CpuError {}
+
+/// Reasons a rom can't be loaded into ram.
+#[derive(Debug)]
+pub enum RomError {
+    // The rom has no instructions at all.
+    Empty,
+
+    // The rom doesn't fit into program space.
+    TooLarge { size: usize, max: usize },
+
+    // Reading the rom failed.
+    Unreadable { why: io::Error },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::Empty => write!(f, "rom is empty"),
+            RomError::TooLarge { size, max } => {
+                write!(f, "rom is {} bytes, at most {} bytes fit in ram", size, max)
+            },
+            RomError::Unreadable { ref why } => write!(f, "cannot read rom: {}", why),
+        }
+    }
+}
+
+impl error::Error for RomError {}
+
+/// Reasons ram can't be accessed from outside the cpu.
+#[derive(Debug)]
+pub enum MemoryError {
+    // `len` bytes starting at `start` reach past the end of ram, which is
+    // `size` bytes long.
+    OutOfRange { start: usize, len: usize, size: usize },
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MemoryError::OutOfRange { start, len, size } => {
+                write!(f, "access of {} bytes at {:#x} is out of range, ram ends at \
+                           {:#x}", len, start, size - 1)
+            },
+        }
+    }
+}
+
+impl error::Error for MemoryError {}
+
+/// Reasons a save state can't be restored.
+#[derive(Debug)]
+pub enum StateError {
+    // The data doesn't start like a save state.
+    UnknownFormat,
+
+    // The save state was written in a format this version can't read.
+    UnsupportedVersion { version: u8 },
+
+    // The save state ends before all of the machine state was read.
+    Truncated,
+
+    // The save state is complete but holds a state the machine can't be in.
+    Invalid { reason: &'static str },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::UnknownFormat => write!(f, "not a save state"),
+            StateError::UnsupportedVersion { version } => {
+                write!(f, "save state version {} is not supported", version)
+            },
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid { reason } => write!(f, "invalid save state, {}", reason),
+        }
+    }
+}
+
+impl error::Error for StateError {}
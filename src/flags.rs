@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+// SUPER-CHIP has eight flag registers, one for each HP48 user flag.
+pub const FLAG_COUNT: usize = 8;
+
+/// Keeps the HP48 flag registers in a file between runs. Games use these as
+/// save slots, high scores for example.
+#[derive(Debug)]
+pub struct FlagStorage {
+    path: PathBuf,
+}
+
+impl FlagStorage {
+    pub fn new(path: PathBuf) -> FlagStorage {
+        FlagStorage {
+            path: path,
+        }
+    }
+
+    /// Reads the stored flags. A missing or short file is treated as zeroed
+    /// flags since the game simply hasn't saved anything yet.
+    pub fn load(&self) -> [u8; FLAG_COUNT] {
+        let mut flags = [0; FLAG_COUNT];
+
+        if let Ok(mut file) = File::open(&self.path) {
+            let mut buffer = Vec::new();
+            if file.read_to_end(&mut buffer).is_ok() {
+                for (flag, byte) in flags.iter_mut().zip(buffer) {
+                    *flag = byte;
+                }
+            }
+        }
+
+        flags
+    }
+
+    /// Overwrites the stored flags.
+    pub fn save(&self, flags: &[u8; FLAG_COUNT]) -> io::Result<()> {
+        let mut file = File::create(&self.path)?;
+        file.write_all(flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::process;
+
+    use super::*;
+    use super::super::cpu::Cpu;
+    use super::super::interconnect::Interconnect;
+    use super::super::memory::RAM_SIZE;
+
+    /// A Super-CHIP cpu running `rom` with its flags kept at `path`.
+    fn cpu(rom: &[u8], path: &Path) -> Cpu {
+        let interconnect = Interconnect::headless(rom, RAM_SIZE).unwrap();
+        let mut cpu = Cpu::with_seed(interconnect, 0);
+        cpu.enable_turbo();
+        cpu.enable_schip();
+        cpu.persist_flags(FlagStorage::new(path.to_path_buf()));
+        cpu
+    }
+
+    #[test]
+    fn flags_saved_by_one_run_load_in_the_next() {
+        let path = env::temp_dir().join(format!("notch-{}-flags.bin", process::id()));
+
+        // 0x200: LD V0, 0x12, LD V1, 0x34, LD V2, 0x56, LD R, V2
+        let mut saving = cpu(&[0x60, 0x12, 0x61, 0x34, 0x62, 0x56, 0xF2, 0x75], &path);
+        for _ in 0..4 {
+            saving.step().unwrap();
+        }
+        saving.shutdown();
+
+        // 0x200: LD V3, R
+        let mut loading = cpu(&[0xF3, 0x85], &path);
+        loading.step().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loading.registers()[..4], [0x12, 0x34, 0x56, 0x00]);
+    }
+
+    #[test]
+    fn missing_files_load_zeroed_flags() {
+        let path = env::temp_dir().join(format!("notch-{}-no-flags.bin", process::id()));
+        assert_eq!(FlagStorage::new(path).load(), [0; FLAG_COUNT]);
+    }
+}
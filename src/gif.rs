@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Frames are captured at most 30 times a second, every other 60Hz frame.
+const FRAME_INTERVAL: u64 = 1_000_000_000 / 30;
+
+// Recording stops by itself after two minutes worth of frames, so a
+// forgotten recording can't fill the disk.
+const MAX_FRAMES: usize = 30 * 60 * 2;
+
+// Frames have two colors, so pixels are coded starting from two bits.
+const MIN_CODE_SIZE: u8 = 2;
+
+// LZW codes never grow past 12 bits.
+const MAX_CODES: u16 = 4096;
+
+/// A frame waiting for the next different frame, which decides how long it
+/// is shown for.
+struct PendingFrame {
+    pixels: Vec<u8>,
+    palette: [(u8, u8, u8); 2],
+    start: u64,
+}
+
+/// Writes frames of the display to an animated GIF as they are shown. Frames
+/// are written one at a time so memory use doesn't grow with the length of
+/// the recording. A frame that looks the same as the one before it only
+/// makes the previous frame last longer.
+pub struct GifRecorder<W: Write = BufWriter<File>> {
+    out: W,
+    width: usize,
+    height: usize,
+
+    // When recording began, frame delays are rounded relative to it so
+    // rounding errors don't add up.
+    origin: Option<u64>,
+
+    pending: Option<PendingFrame>,
+    frames: usize,
+}
+
+impl GifRecorder {
+    /// Creates the file and writes the GIF header for frames of the passed
+    /// size.
+    pub fn create<P: AsRef<Path>>(path: P, width: usize, height: usize) -> io::Result<GifRecorder> {
+        GifRecorder::new(BufWriter::new(File::create(path)?), width, height)
+    }
+}
+
+impl<W: Write> GifRecorder<W> {
+    /// Writes the GIF header for frames of the passed size to `out`. The
+    /// animation loops forever.
+    pub fn new(mut out: W, width: usize, height: usize) -> io::Result<GifRecorder<W>> {
+        // Logical screen without a global color table, every frame brings
+        // its own colors.
+        out.write_all(b"GIF89a")?;
+        write_u16(&mut out, width as u16)?;
+        write_u16(&mut out, height as u16)?;
+        out.write_all(&[0x00, 0x00, 0x00])?;
+
+        // Loop forever.
+        out.write_all(&[0x21, 0xFF, 0x0B])?;
+        out.write_all(b"NETSCAPE2.0")?;
+        out.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+
+        Ok(GifRecorder {
+            out: out,
+            width: width,
+            height: height,
+            origin: None,
+            pending: None,
+            frames: 0,
+        })
+    }
+
+    /// Offers the frame shown at `now`, in nanoseconds. Pixels are palette
+    /// indices, 0 for the background and 1 for the foreground, one byte per
+    /// pixel. Frames arriving faster than 30 per second are skipped. Returns
+    /// false once the recording is as long as it is allowed to get.
+    pub fn capture(&mut self, now: u64, pixels: &[u8],
+                   palette: [(u8, u8, u8); 2]) -> io::Result<bool> {
+        assert_eq!(pixels.len(), self.width * self.height,
+                   "Frame size doesn't match the recording");
+        self.origin.get_or_insert(now);
+
+        let changed = match self.pending {
+            Some(ref pending) => {
+                if now - pending.start < FRAME_INTERVAL {
+                    return Ok(true);
+                }
+                pending.pixels.as_slice() != pixels || pending.palette != palette
+            },
+            None => true,
+        };
+
+        if changed {
+            self.write_pending(now)?;
+            self.pending = Some(PendingFrame {
+                pixels: pixels.to_vec(),
+                palette: palette,
+                start: now,
+            });
+        }
+
+        Ok(self.frames < MAX_FRAMES)
+    }
+
+    /// Writes the last frame, shown until `now`, and ends the file.
+    pub fn finish(mut self, now: u64) -> io::Result<()> {
+        self.write_pending(now)?;
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()
+    }
+
+    /// Writes the pending frame, if any, with the time it was shown for.
+    fn write_pending(&mut self, end: u64) -> io::Result<()> {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        // Delays are counted in hundredths of a second.
+        let origin = self.origin.unwrap_or(pending.start);
+        let start = (pending.start - origin) / 10_000_000;
+        let end = (end - origin) / 10_000_000;
+        let delay = if end > start { end - start } else { 1 };
+
+        // Graphic control extension holding the delay.
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        write_u16(&mut self.out, delay as u16)?;
+        self.out.write_all(&[0x00, 0x00])?;
+
+        // Image descriptor covering the whole screen, followed by a local
+        // color table of two colors.
+        self.out.write_all(&[0x2C])?;
+        write_u16(&mut self.out, 0)?;
+        write_u16(&mut self.out, 0)?;
+        write_u16(&mut self.out, self.width as u16)?;
+        write_u16(&mut self.out, self.height as u16)?;
+        self.out.write_all(&[0x80])?;
+        for &(r, g, b) in pending.palette.iter() {
+            self.out.write_all(&[r, g, b])?;
+        }
+
+        // The pixels, compressed and split into blocks of at most 255 bytes.
+        self.out.write_all(&[MIN_CODE_SIZE])?;
+        for block in lzw_encode(&pending.pixels).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])?;
+
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+/// Compresses palette indices with the variable length LZW coding GIF uses.
+fn lzw_encode(pixels: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << MIN_CODE_SIZE;
+    let end = clear + 1;
+
+    let mut out = BitWriter::default();
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut code_size = MIN_CODE_SIZE + 1;
+    let mut next = end + 1;
+
+    out.write(clear, code_size);
+
+    let mut prefix = match pixels.first() {
+        Some(&pixel) => pixel as u16,
+        None => {
+            out.write(end, code_size);
+            return out.finish();
+        },
+    };
+
+    for &pixel in &pixels[1..] {
+        if let Some(&code) = codes.get(&(prefix, pixel)) {
+            prefix = code;
+            continue;
+        }
+
+        out.write(prefix, code_size);
+        if next < MAX_CODES {
+            codes.insert((prefix, pixel), next);
+            next += 1;
+
+            // The decoder widens its codes one code later than this point,
+            // once its table catches up.
+            if next > 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        } else {
+            // The table is full, start over.
+            out.write(clear, code_size);
+            codes.clear();
+            code_size = MIN_CODE_SIZE + 1;
+            next = end + 1;
+        }
+        prefix = pixel as u16;
+    }
+
+    out.write(prefix, code_size);
+    out.write(end, code_size);
+    out.finish()
+}
+
+/// Packs codes into bytes starting from the least significant bit.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+fn write_u16<W: Write>(out: &mut W, value: u16) -> io::Result<()> {
+    out.write_all(&[value as u8, (value >> 8) as u8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame read back from a recording: its delay, colors and pixels.
+    type Frame = (u16, Vec<u8>, Vec<u8>);
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        bytes[at] as u16 | (bytes[at + 1] as u16) << 8
+    }
+
+    /// Reads LZW codes back into palette indices.
+    fn lzw_decode(data: &[u8]) -> Vec<u8> {
+        let clear = 1usize << MIN_CODE_SIZE;
+        let mut pixels = Vec::new();
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut code_size = MIN_CODE_SIZE + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let (mut buffer, mut bits) = (0u32, 0u8);
+
+        for &byte in data {
+            buffer |= (byte as u32) << bits;
+            bits += 8;
+            while bits >= code_size {
+                let code = (buffer & ((1 << code_size) - 1)) as usize;
+                buffer >>= code_size;
+                bits -= code_size;
+
+                if code == clear {
+                    table = (0..clear as u8).map(|pixel| vec![pixel]).collect();
+                    table.push(Vec::new());
+                    table.push(Vec::new());
+                    code_size = MIN_CODE_SIZE + 1;
+                    previous = None;
+                    continue;
+                }
+                if code == clear + 1 {
+                    return pixels;
+                }
+
+                let entry = match (table.get(code), previous.as_ref()) {
+                    (Some(entry), _) => entry.clone(),
+                    (None, Some(previous)) => {
+                        let mut entry = previous.clone();
+                        entry.push(previous[0]);
+                        entry
+                    },
+                    (None, None) => panic!("Code {} isn't in the table", code),
+                };
+                if let Some(mut previous) = previous.take() {
+                    previous.push(entry[0]);
+                    table.push(previous);
+                    if table.len() == 1 << code_size && code_size < 12 {
+                        code_size += 1;
+                    }
+                }
+                pixels.extend_from_slice(&entry);
+                previous = Some(entry);
+            }
+        }
+
+        panic!("Pixels don't finish with an end code");
+    }
+
+    /// Checks the header of a recording of the passed size and reads its
+    /// frames.
+    fn frames(gif: &[u8], width: usize, height: usize) -> Vec<Frame> {
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(u16_at(gif, 6) as usize, width);
+        assert_eq!(u16_at(gif, 8) as usize, height);
+        assert_eq!(&gif[16..27], b"NETSCAPE2.0");
+
+        let mut frames = Vec::new();
+        let mut at = 32;
+        while gif[at] != 0x3B {
+            assert_eq!(&gif[at..at + 4], &[0x21, 0xF9, 0x04, 0x00]);
+            let delay = u16_at(gif, at + 4);
+            at += 8;
+
+            assert_eq!(gif[at], 0x2C);
+            assert_eq!(u16_at(gif, at + 5) as usize, width);
+            assert_eq!(u16_at(gif, at + 7) as usize, height);
+            let palette = gif[at + 10..at + 16].to_vec();
+            assert_eq!(gif[at + 16], MIN_CODE_SIZE);
+            at += 17;
+
+            let mut data = Vec::new();
+            while gif[at] != 0 {
+                let len = gif[at] as usize;
+                data.extend_from_slice(&gif[at + 1..at + 1 + len]);
+                at += 1 + len;
+            }
+            at += 1;
+
+            frames.push((delay, palette, lzw_decode(&data)));
+        }
+        assert_eq!(at, gif.len() - 1);
+
+        frames
+    }
+
+    #[test]
+    fn recordings_hold_each_different_frame_with_its_delay() {
+        let black_white = [(0, 0, 0), (255, 255, 255)];
+        let first: Vec<u8> = (0..64 * 32).map(|pixel| (pixel % 3 == 0) as u8).collect();
+        let second: Vec<u8> = (0..64 * 32).map(|pixel| (pixel / 64 % 2) as u8).collect();
+
+        let mut gif = Vec::new();
+        {
+            let mut recorder = GifRecorder::new(&mut gif, 64, 32).unwrap();
+            assert!(recorder.capture(0, &first, black_white).unwrap());
+
+            // Too soon after the first frame, then the same frame again.
+            assert!(recorder.capture(1, &second, black_white).unwrap());
+            assert!(recorder.capture(FRAME_INTERVAL, &first, black_white).unwrap());
+
+            let blue_red = [(0, 0, 255), (255, 0, 0)];
+            assert!(recorder.capture(2 * FRAME_INTERVAL, &second, blue_red).unwrap());
+            recorder.finish(3 * FRAME_INTERVAL).unwrap();
+        }
+
+        let frames = frames(&gif, 64, 32);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0], (6, vec![0, 0, 0, 255, 255, 255], first));
+        assert_eq!(frames[1], (3, vec![0, 0, 255, 255, 0, 0], second));
+    }
+
+    #[test]
+    fn long_frames_fill_the_code_table() {
+        // Noisy pixels use up the 4096 codes and clear the table.
+        let mut seed = 1u32;
+        let pixels: Vec<u8> = (0..128 * 64).map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u8 & 1
+        }).collect();
+
+        assert_eq!(lzw_decode(&lzw_encode(&pixels)), pixels);
+    }
+}
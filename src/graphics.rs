@@ -1,133 +1,842 @@
+use std::cmp;
+use std::io;
+
 use super::sdl2::pixels::Color;
-use super::sdl2::rect::Point;
-use super::sdl2;
+use super::time;
+
+use super::display::{BYTES_PER_PIXEL, Display};
+use super::gif::GifRecorder;
 
 // Display size parameters.
-const DISPLAY_WIDTH: usize = 64;
-const DISPLAY_HEIGHT: usize = 32;
-const DISPLAY_SIZE: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+
+// Size of the Super-CHIP high resolution display.
+const HIRES_WIDTH: usize = 128;
+const HIRES_HEIGHT: usize = 64;
+
+// XO-CHIP draws on two bit planes. Every display pixel keeps one bit per
+// plane, so without XO-CHIP only plane 1 is used and pixels are 0 or 1.
+pub const PLANE_COUNT: usize = 2;
+pub const DEFAULT_PLANES: u8 = 0x1;
+
+// Colors of pixels set in plane 2 alone and in both planes. Pixels only set
+// in plane 1 use the foreground color.
+const PLANE_COLORS: [Color; 2] = [
+    Color::RGB(170, 170, 170), // Light gray
+    Color::RGB(85, 85, 85),    // Dark gray
+];
+
+// Chip-8X colors the display in zones that are 8 pixels wide and one row tall.
+// Block coloring (BXY0) works on groups of four of these rows.
+const ZONE_WIDTH: usize = 8;
+const ZONE_COLUMNS: usize = DISPLAY_WIDTH / ZONE_WIDTH;
+pub const ZONE_COUNT: usize = ZONE_COLUMNS * DISPLAY_HEIGHT;
+pub const ZONE_BLOCK_HEIGHT: usize = 4;
+
+// Frames drawn to the backend each second at most, unless chosen otherwise.
+const DEFAULT_FPS: u64 = 60;
+
+// Foreground colors available to Chip-8X programs, indexed by color number.
+const FOREGROUND_COLORS: [Color; 8] = [
+    Color::RGB(0, 0, 0),       // Black
+    Color::RGB(255, 0, 0),     // Red
+    Color::RGB(0, 0, 255),     // Blue
+    Color::RGB(255, 0, 255),   // Violet
+    Color::RGB(0, 255, 0),     // Green
+    Color::RGB(255, 255, 0),   // Yellow
+    Color::RGB(0, 255, 255),   // Aqua
+    Color::RGB(255, 255, 255), // White
+];
+
+// The order Chip-8X steps the background color through.
+pub const BACKGROUND_COLORS: [Color; 4] = [
+    Color::RGB(0, 0, 128), // Blue
+    Color::RGB(0, 0, 0),   // Black
+    Color::RGB(0, 128, 0), // Green
+    Color::RGB(128, 0, 0), // Red
+];
+
+/// The Chip-8X colors of the display, as kept in save states.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisplayColors {
+    // Color of every zone, row by row.
+    pub zones: Vec<(u8, u8, u8)>,
+
+    // The background color and its position in the background color cycle.
+    pub background: (u8, u8, u8),
+    pub background_index: usize,
+}
 
 pub struct Graphics {
-    renderer: sdl2::render::Renderer<'static>,
+    // Where the colored frames end up, an SDL window or nowhere.
+    backend: Box<dyn Display>,
 
-    // 64x32 buffer for the application to write to. The contents of this buffer
-    // is rendered to the SDL surface.
+    // 64x32 buffer for the application to write to, or 128x64 in high
+    // resolution mode. The contents of this buffer is rendered to the backend.
+    // Bit 0 of a pixel is plane 1, bit 1 is plane 2.
     display: Vec<u8>,
+    width: usize,
+    height: usize,
+
+    // The planes drawing, clearing and scrolling work on, one bit each.
+    planes: u8,
+
+    // The display in color, as passed to the backend.
+    frame: Vec<u8>,
+
+    // Color of each 8x1 zone of the display. Every zone is white and the
+    // background black unless a Chip-8X program changes them. Zones are laid
+    // out over the low resolution display, in high resolution each covers
+    // twice as many pixels in both directions.
+    zone_colors: Vec<Color>,
+    background: Color,
+
+    // Position in the Chip-8X background color cycle.
+    background_index: usize,
+
+    // Number of pixels the most recent sprite erased.
+    last_collision_count: usize,
+
+    // Set when the display changed since it was last drawn to the backend.
+    dirty: bool,
+
+    // Number of frames drawn to the backend so far.
+    presents: u64,
+
+    // Cut sprites off at the edges of the display instead of wrapping them
+    // around to the other side.
+    clipping: bool,
+
+    // Receives the frames shown while recording a GIF.
+    gif: Option<GifRecorder>,
+
+    // Shortest time between frames drawn to the backend in nanoseconds, and
+    // when the last one was drawn.
+    frame_interval: u64,
+    last_draw: u64,
 }
 
 impl Graphics {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Graphics {
-        let video_subsystem = sdl_context.video().unwrap();
+    pub fn new(backend: Box<dyn Display>) -> Graphics {
+        Graphics {
+            backend: backend,
+            display: vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            width: DISPLAY_WIDTH,
+            height: DISPLAY_HEIGHT,
+            planes: DEFAULT_PLANES,
+            frame: vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT * BYTES_PER_PIXEL],
+            zone_colors: vec![Color::RGB(255, 255, 255); ZONE_COUNT],
+            background: Color::RGB(0, 0, 0),
+            background_index: BACKGROUND_COLORS.len() - 1,
+            last_collision_count: 0,
+            dirty: true,
+            presents: 0,
+            clipping: false,
+            gif: None,
+            frame_interval: 1_000_000_000 / DEFAULT_FPS,
+            last_draw: 0,
+        }
+    }
+
+    /// Switches between the 64x32 display and the Super-CHIP 128x64 display.
+    /// Switching clears the display.
+    pub fn set_hires(&mut self, hires: bool) {
+        let (width, height) = if hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+        };
 
-        // Create a window 10x the scale of CHIP-8's display.
-        let window = video_subsystem.window("Notch", 640, 320)
-            .position_centered()
-            .build()
-            .unwrap();
+        self.width = width;
+        self.height = height;
+        self.display = vec![0; width * height];
+        self.frame = vec![0; width * height * BYTES_PER_PIXEL];
+        self.dirty = true;
+    }
 
-        // Create a renderer that is scaled up a bit. The CHIP-8 display is
-        // very small for today's standards.
-        let mut renderer = window.renderer().build().unwrap();
-        renderer.set_scale(10.0, 10.0);
+    /// True while the 128x64 display is active.
+    pub fn hires(&self) -> bool {
+        self.width == HIRES_WIDTH
+    }
 
-        // Clear the screen to black.
-        renderer.set_draw_color(Color::RGB(0, 0, 0));
-        renderer.clear();
-        renderer.present();
+    /// Width of the current display in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
 
-        Graphics {
-            renderer: renderer,
-            display: vec![0; DISPLAY_SIZE],
+    /// Steps the background to the next Chip-8X background color. The first
+    /// step selects blue.
+    pub fn step_background(&mut self) {
+        self.background_index = (self.background_index + 1) % BACKGROUND_COLORS.len();
+        self.background = BACKGROUND_COLORS[self.background_index];
+        self.dirty = true;
+    }
+
+    /// Sets the color pixels are drawn in. Chip-8X programs can still color
+    /// zones differently afterwards.
+    pub fn set_foreground(&mut self, color: Color) {
+        for zone in self.zone_colors.iter_mut() {
+            *zone = color;
+        }
+        self.dirty = true;
+    }
+
+    /// Sets the color behind the pixels. Chip-8X programs can still step
+    /// through their own background colors afterwards.
+    pub fn set_background(&mut self, color: Color) {
+        self.background = color;
+        self.dirty = true;
+    }
+
+    /// Sets the foreground color of a rectangle of zones. Coordinates wrap
+    /// around the display the same way sprites do and the color number is
+    /// masked to the eight available colors.
+    pub fn set_zone_color(&mut self, x: usize, y: usize, width: usize, height: usize, color: u8) {
+        let color = FOREGROUND_COLORS[(color & 0x7) as usize];
+
+        for row in y..y + height {
+            for column in x..x + width {
+                let index = (row % DISPLAY_HEIGHT) * ZONE_COLUMNS + column % ZONE_COLUMNS;
+                self.zone_colors[index] = color;
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// The zone and background colors.
+    pub fn colors(&self) -> DisplayColors {
+        DisplayColors {
+            zones: self.zone_colors.iter().map(|color| color.rgb()).collect(),
+            background: self.background.rgb(),
+            background_index: self.background_index,
+        }
+    }
+
+    /// Replaces the zone and background colors with saved ones. There must
+    /// be a color for every zone.
+    pub fn load_colors(&mut self, colors: &DisplayColors) {
+        for (zone, &(r, g, b)) in self.zone_colors.iter_mut().zip(colors.zones.iter()) {
+            *zone = Color::RGB(r, g, b);
+        }
+        let (r, g, b) = colors.background;
+        self.background = Color::RGB(r, g, b);
+        self.background_index = colors.background_index;
+        self.dirty = true;
+    }
+
+    /// Selects the XO-CHIP planes later drawing, clearing and scrolling work
+    /// on, bit 0 being plane 1. With no plane selected nothing is drawn.
+    pub fn set_planes(&mut self, mask: u8) {
+        self.planes = mask & ((1 << PLANE_COUNT) - 1);
+    }
+
+    /// The selected planes, bit 0 being plane 1.
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// Goes back to drawing on plane 1 alone.
+    pub fn reset_planes(&mut self) {
+        self.planes = DEFAULT_PLANES;
+    }
+
+    /// How many planes are selected. Sprites hold one set of rows for each.
+    pub fn plane_count(&self) -> usize {
+        self.planes.count_ones() as usize
+    }
+
+    /// Chooses between wrapping sprites that cross an edge of the display
+    /// around to the other side, and clipping them. The position a sprite
+    /// starts at wraps either way.
+    pub fn set_clipping(&mut self, clipping: bool) {
+        self.clipping = clipping;
+    }
+
+    /// The display buffer, one byte per pixel in row-major order. Each pixel
+    /// holds a bit for every plane.
+    pub fn display(&self) -> &[u8] {
+        &self.display
+    }
+
+    /// Replaces the display with a saved one, switching to the resolution it
+    /// was saved in. The buffer must hold one byte for every pixel.
+    pub fn load_display(&mut self, hires: bool, display: &[u8]) {
+        self.set_hires(hires);
+        self.display.copy_from_slice(display);
+    }
+
+    /// Number of pixels the most recent sprite erased.
+    pub fn last_collision_count(&self) -> usize {
+        self.last_collision_count
+    }
+
+    /// Draws a sprite to the display and returns the number of pixels it
+    /// erased. Any erased pixel counts as a collision. The sprite holds the
+    /// rows for every selected plane one after the other, lowest plane
+    /// first.
+    pub fn draw(&mut self, x: usize, y: usize, sprite: Vec<u8>) -> usize {
+        let rows: Vec<u16> = sprite.iter().map(|&row| row as u16).collect();
+        self.draw_planes(x, y, &rows, 8)
+    }
+
+    /// Draws a Super-CHIP 16x16 sprite, stored as two bytes per row, and
+    /// returns the number of pixels it erased.
+    pub fn draw_large(&mut self, x: usize, y: usize, sprite: Vec<u8>) -> usize {
+        let rows: Vec<u16> = sprite.chunks(2)
+            .map(|row| (row[0] as u16) << 8 | row[1] as u16)
+            .collect();
+        self.draw_planes(x, y, &rows, 16)
+    }
+
+    /// Splits the sprite rows between the selected planes and draws each
+    /// share on its plane.
+    fn draw_planes(&mut self, x: usize, y: usize, sprite: &[u16], width: usize) -> usize {
+        let planes: Vec<u8> = (0..PLANE_COUNT as u8)
+            .map(|plane| 1 << plane)
+            .filter(|&plane| self.planes & plane != 0)
+            .collect();
+        let mut collisions = 0;
+
+        if !planes.is_empty() {
+            let rows = sprite.len() / planes.len();
+            for (i, &plane) in planes.iter().enumerate() {
+                collisions += self.draw_rows(x, y, &sprite[i * rows..(i + 1) * rows], width, plane);
+            }
         }
+
+        // Draw to the backend on the next frame. Humans have these things
+        // called "eyes" and they get upset when they cannot see things.
+        self.dirty = true;
+
+        self.last_collision_count = collisions;
+        collisions
     }
 
-    /// Draws a sprite to the display.
-    pub fn draw(&mut self, x: usize, y: usize, sprite: Vec<u8>) -> u8 {
-        let line = y * DISPLAY_WIDTH;
-        let mut collision: u8 = 0;
-        let mut values = vec![0 as u8; 8];
+    /// XORs sprite rows of the passed width onto a plane of the display, the
+    /// leftmost pixel of a row being its most significant bit.
+    fn draw_rows(&mut self, x: usize, y: usize, sprite: &[u16], width: usize, plane: u8) -> usize {
+        // The starting position always wraps around the display, VX and VY
+        // can be as large as 255.
+        let x = x % self.width;
+        let y = y % self.height;
+        let mut collisions: usize = 0;
+        let mut values = vec![0 as u8; width];
 
         for i in 0..sprite.len() {
-            // Each byte in a sprite draws on one line.
-            let offset = line + DISPLAY_WIDTH * i;
+            // Each row in a sprite draws on one line. Lines past the bottom
+            // either wrap around to the top or are clipped.
+            let row = y + i;
+            if row >= self.height && self.clipping {
+                break;
+            }
+            let offset = (row % self.height) * self.width;
 
-            // Organize the bits from the current sprite byte into a slice.
+            // Organize the bits from the current sprite row into a slice.
             for j in 0..values.len() {
-                let bit = (sprite[i] >> j) & 0x01;
-                values[8 - 1 - j] = bit;
+                let bit = ((sprite[i] >> j) & 0x01) as u8;
+                values[width - 1 - j] = bit;
             }
 
-            // Loop through the bits in the current byte and set the display
+            // Loop through the bits in the current row and set the display
             // values based on them.
             for j in 0..values.len() {
-                let value = values[j];
-                let pos: usize = x + j;
-                let mut index: usize;
+                let value = if values[j] == 1 { plane } else { 0 };
 
                 // Draw a pixel in the sprite onto the display. If the pixel x
-                // position is greater than the width of the display, the sprite
-                // wraps around the display.
-                if pos >= DISPLAY_WIDTH {
-                    // Wrap around to the left side to draw.
-                    index = offset + pos - DISPLAY_WIDTH;
-                } else {
-                    // Draw at the current offset.
-                    index = offset + pos;
+                // position is past the width of the display, the sprite wraps
+                // around to the left side of the same row or is clipped.
+                let pos: usize = x + j;
+                if pos >= self.width && self.clipping {
+                    break;
                 }
+                let index = offset + pos % self.width;
 
-                if index >= DISPLAY_SIZE {
-                    index -= DISPLAY_SIZE;
+                // Save the previous state of the pixel before setting it
+                // for collision detection.
+                let prev = self.display[index];
+
+                // Draw the bit to the display.
+                self.display[index] = value ^ prev;
+
+                // Check the previous state of the pixel and check if it
+                // was erased, if so then there was a sprite collision.
+                if prev & plane != 0 && self.display[index] & plane == 0 {
+                    collisions += 1;
                 }
+            }
+        }
 
-                if index < DISPLAY_SIZE {
-                    // Save the previous state of the pixel before setting it
-                    // for collision detection.
-                    let prev = self.display[index];
+        collisions
+    }
 
-                    // Draw the bit to the display.
-                    self.display[index] = value ^ prev;
+    /// Clears all pixels on the selected planes by setting them to an off
+    /// state.
+    pub fn clear_display(&mut self) {
+        for pixel in self.display.iter_mut() {
+            *pixel &= !self.planes;
+        }
+        self.dirty = true;
+    }
 
-                    // Check the previous state of the pixel and check if it
-                    // was erased, if so then there was a sprite collision.
-                    if prev == 1 && self.display[index] == 0 {
-                        collision = 1;
-                    }
-                }
+    /// Moves the selected planes down by the passed number of rows, rows
+    /// scrolled in at the top are blank.
+    pub fn scroll_down(&mut self, rows: usize) {
+        let rows = cmp::min(rows, self.height);
+        let shift = rows * self.width;
+        let len = self.display.len();
+
+        let mut scrolled = self.display.clone();
+        scrolled.copy_within(0..len - shift, shift);
+        for pixel in &mut scrolled[..shift] {
+            *pixel = 0;
+        }
+        self.merge_planes(&scrolled);
+    }
+
+    /// Moves the selected planes right by the passed number of columns,
+    /// columns scrolled in at the left are blank.
+    pub fn scroll_right(&mut self, columns: usize) {
+        let columns = cmp::min(columns, self.width);
+        let mut scrolled = self.display.clone();
+        for row in scrolled.chunks_mut(self.width) {
+            let width = row.len();
+            row.copy_within(0..width - columns, columns);
+            for pixel in &mut row[..columns] {
+                *pixel = 0;
+            }
+        }
+        self.merge_planes(&scrolled);
+    }
+
+    /// Moves the selected planes left by the passed number of columns,
+    /// columns scrolled in at the right are blank.
+    pub fn scroll_left(&mut self, columns: usize) {
+        let columns = cmp::min(columns, self.width);
+        let mut scrolled = self.display.clone();
+        for row in scrolled.chunks_mut(self.width) {
+            let width = row.len();
+            row.copy_within(columns..width, 0);
+            for pixel in &mut row[width - columns..] {
+                *pixel = 0;
             }
         }
+        self.merge_planes(&scrolled);
+    }
 
-        // Draw to the SDL surface. Humans have these things called "eyes" and
-        // they get upset when they cannot see things.
-        self.draw_display();
+    /// Takes the selected planes from a scrolled copy of the display, the
+    /// other planes stay where they are.
+    fn merge_planes(&mut self, scrolled: &[u8]) {
+        for (pixel, &moved) in self.display.iter_mut().zip(scrolled) {
+            *pixel = (*pixel & !self.planes) | (moved & self.planes);
+        }
+        self.dirty = true;
+    }
 
-        collision
+    /// Switches the backend between a window and the whole screen. The
+    /// display is redrawn on the next frame to fill the new size.
+    pub fn toggle_fullscreen(&mut self) {
+        self.backend.toggle_fullscreen();
+        self.dirty = true;
     }
 
-    /// Clears all pixels on the display by setting them all to an off state.
-    pub fn clear_display(&mut self) {
-        for i in 0..DISPLAY_SIZE {
-            self.display[i] = 0;
+    /// Names the window the backend shows frames in, if there is one.
+    pub fn set_title(&mut self, title: &str) {
+        self.backend.set_title(title);
+    }
+
+    /// Starts adding every shown frame to a GIF. Frames are recorded at the
+    /// size of the high resolution display, so low resolution pixels are
+    /// doubled. Replaces a recording already in progress without finishing
+    /// it.
+    pub fn start_gif(&mut self, recorder: GifRecorder) {
+        self.gif = Some(recorder);
+    }
+
+    /// The size GIF recordings must be created with.
+    pub fn gif_size(&self) -> (usize, usize) {
+        (HIRES_WIDTH, HIRES_HEIGHT)
+    }
+
+    /// Ends the GIF recording, if any, writing out the last frame.
+    pub fn stop_gif(&mut self) -> io::Result<()> {
+        match self.gif.take() {
+            Some(recorder) => recorder.finish(time::precise_time_ns()),
+            None => Ok(()),
         }
-        self.draw_display();
     }
 
-    /// Draw the display in it's current state to the SDL surface.
-    /// All pixels are white but this may be subject to change.
-    fn draw_display(&mut self) {
-        // Clear the screen to black.
-        self.renderer.set_draw_color(Color::RGB(0, 0, 0));
-        self.renderer.clear();
-
-        // Draw the display to the SDL surface.
-        self.renderer.set_draw_color(Color::RGB(255, 255, 255));
-        for i in 0..DISPLAY_HEIGHT {
-            let offset = DISPLAY_WIDTH * i;
-            for j in 0..DISPLAY_WIDTH {
-                if self.display[offset + j] == 1 {
-                    self.renderer.draw_point(Point::new(j as i32, i as i32));
+    /// Draws the display to the backend if it changed since the last call.
+    /// Called once per frame, so any number of changes within a frame only
+    /// cost a single redraw. Frames coming quicker than the frame rate limit
+    /// allows aren't drawn, the changes are drawn with a later frame.
+    pub fn present(&mut self) {
+        // Frames may come a little early, the 60Hz frames jitter.
+        let now = time::precise_time_ns();
+        if self.dirty && now - self.last_draw >= self.frame_interval * 3 / 4 {
+            self.draw_display();
+            self.dirty = false;
+            self.last_draw = now;
+        }
+
+        if self.gif.is_some() {
+            self.capture_gif();
+        }
+    }
+
+    /// Limits how many frames are drawn to the backend each second. Frames
+    /// end at the 60Hz timer tick, so limits above 60 have no effect.
+    pub fn set_fps(&mut self, fps: u64) {
+        self.frame_interval = 1_000_000_000 / fps;
+    }
+
+    /// Offers the frame being shown to the GIF recording, stopping the
+    /// recording once it is full or can't be written.
+    fn capture_gif(&mut self) {
+        // Colors come from the first zone, GIF frames have two colors. A
+        // pixel set on any plane is shown in the foreground color.
+        let palette = [self.background.rgb(), self.zone_colors[0].rgb()];
+
+        let scale = HIRES_WIDTH / self.width;
+        let mut pixels = Vec::with_capacity(HIRES_WIDTH * HIRES_HEIGHT);
+        for y in 0..HIRES_HEIGHT {
+            let row = (y / scale) * self.width;
+            for x in 0..HIRES_WIDTH {
+                pixels.push((self.display[row + x / scale] != 0) as u8);
+            }
+        }
+
+        let result = match self.gif {
+            Some(ref mut recorder) => recorder.capture(time::precise_time_ns(), &pixels, palette),
+            None => return,
+        };
+
+        match result {
+            Ok(true) => {},
+            Ok(false) => {
+                println!("notch: GIF recording reached its length limit, stopping");
+                if let Err(why) = self.stop_gif() {
+                    println!("notch: cannot finish GIF: {}", why);
                 }
+            },
+            Err(why) => {
+                println!("notch: cannot write GIF, stopping the recording: {}", why);
+                self.gif = None;
+            },
+        }
+    }
+
+    /// Renders the display in color with every pixel drawn as a `scale` by
+    /// `scale` square. Returns the width, height and the RGBA pixels.
+    pub fn screenshot(&mut self, scale: usize) -> (usize, usize, Vec<u8>) {
+        self.render_frame();
+
+        let (width, height) = (self.width * scale, self.height * scale);
+        let mut image = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = ((y / scale) * self.width + x / scale) * BYTES_PER_PIXEL;
+                image.extend_from_slice(&self.frame[pixel..pixel + BYTES_PER_PIXEL]);
+                image.push(0xFF);
+            }
+        }
+
+        (width, height, image)
+    }
+
+    /// Number of frames drawn to the backend so far. Presents without a
+    /// change to the display don't count, they aren't drawn.
+    pub fn presents(&self) -> u64 {
+        self.presents
+    }
+
+    /// Draw the display in it's current state to the backend.
+    fn draw_display(&mut self) {
+        self.render_frame();
+
+        self.backend.clear();
+        self.backend.draw(&self.frame, self.width, self.height);
+        self.backend.present();
+        self.presents += 1;
+    }
+
+    /// Colors the display into the frame buffer. Pixels on plane 1 take the
+    /// color of the zone they are in, pixels on plane 2 a gray of their own.
+    fn render_frame(&mut self) {
+        // Zones are sized for the low resolution display.
+        let scale = self.width / DISPLAY_WIDTH;
+
+        for i in 0..self.height {
+            let offset = self.width * i;
+            for j in 0..self.width {
+                let color = match self.display[offset + j] {
+                    0 => self.background,
+                    1 => self.zone_colors[(i / scale) * ZONE_COLUMNS + (j / scale) / ZONE_WIDTH],
+                    pixel => PLANE_COLORS[pixel as usize - 2],
+                };
+
+                let (r, g, b) = color.rgb();
+                let pixel = (offset + j) * BYTES_PER_PIXEL;
+                self.frame[pixel] = r;
+                self.frame[pixel + 1] = g;
+                self.frame[pixel + 2] = b;
             }
         }
-        self.renderer.present();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use super::super::display::HeadlessDisplay;
+
+    fn graphics() -> Graphics {
+        Graphics::new(Box::new(HeadlessDisplay))
+    }
+
+    /// A backend noting every call made to it, with the size and first pixel
+    /// of drawn frames.
+    #[derive(Clone, Default)]
+    struct RecordingDisplay(Rc<RefCell<Vec<String>>>);
+
+    impl Display for RecordingDisplay {
+        fn draw(&mut self, buffer: &[u8], width: usize, height: usize) {
+            self.0.borrow_mut().push(format!("draw {}x{} {:?}", width, height, &buffer[..3]));
+        }
+
+        fn clear(&mut self) {
+            self.0.borrow_mut().push("clear".to_string());
+        }
+
+        fn present(&mut self) {
+            self.0.borrow_mut().push("present".to_string());
+        }
+
+        fn toggle_fullscreen(&mut self) {
+            self.0.borrow_mut().push("fullscreen".to_string());
+        }
+
+        fn set_title(&mut self, title: &str) {
+            self.0.borrow_mut().push(format!("title {}", title));
+        }
+    }
+
+    #[test]
+    fn backends_are_drawn_to_once_per_changed_frame() {
+        let calls = RecordingDisplay::default();
+        let mut graphics = Graphics::new(Box::new(calls.clone()));
+        graphics.set_foreground(Color::RGB(0x33, 0xFF, 0x33));
+        graphics.draw(0, 0, vec![0x80]);
+        graphics.present();
+        graphics.present();
+        assert_eq!(*calls.0.borrow(), ["clear", "draw 64x32 [51, 255, 51]", "present"]);
+
+        calls.0.borrow_mut().clear();
+        graphics.last_draw = 0;
+        graphics.set_hires(true);
+        graphics.present();
+        assert_eq!(*calls.0.borrow(), ["clear", "draw 128x64 [0, 0, 0]", "present"]);
+    }
+
+    /// Whether the pixel at x, y is lit on any plane.
+    fn lit(graphics: &Graphics, x: usize, y: usize) -> bool {
+        graphics.display()[y * graphics.width() + x] != 0
+    }
+
+    #[test]
+    fn sprites_wrap_to_the_left_of_the_same_row() {
+        let mut graphics = graphics();
+        graphics.draw(63, 0, vec![0b1100_0000]);
+
+        assert!(lit(&graphics, 63, 0));
+        assert!(lit(&graphics, 0, 0));
+        assert!(!lit(&graphics, 0, 1));
+        assert_eq!(graphics.display().iter().filter(|&&pixel| pixel != 0).count(), 2);
+    }
+
+    #[test]
+    fn tall_sprites_wrap_to_the_top() {
+        let mut graphics = graphics();
+        let collisions = graphics.draw(0, 30, vec![0x80; 4]);
+        assert_eq!(collisions, 0);
+
+        for &y in &[30, 31, 0, 1] {
+            assert!(lit(&graphics, 0, y));
+        }
+        assert!(!lit(&graphics, 0, 2));
+
+        // Drawing over the wrapped rows collides like anywhere else.
+        assert_eq!(graphics.draw(0, 0, vec![0x80]), 1);
+        assert!(!lit(&graphics, 0, 0));
+    }
+
+    #[test]
+    fn clipped_sprites_stop_at_the_bottom() {
+        let mut graphics = graphics();
+        graphics.set_clipping(true);
+        graphics.draw(0, 30, vec![0x80; 4]);
+
+        assert!(lit(&graphics, 0, 30));
+        assert!(lit(&graphics, 0, 31));
+        assert!(!lit(&graphics, 0, 0));
+    }
+
+    #[test]
+    fn set_colors_are_applied_to_the_frame() {
+        let mut graphics = graphics();
+        graphics.set_foreground(Color::RGB(0x33, 0xFF, 0x33));
+        graphics.set_background(Color::RGB(0x10, 0x20, 0x30));
+        graphics.draw(0, 0, vec![0x80]);
+
+        let (_, _, image) = graphics.screenshot(1);
+        assert_eq!(&image[0..4], &[0x33, 0xFF, 0x33, 0xFF]);
+        assert_eq!(&image[4..8], &[0x10, 0x20, 0x30, 0xFF]);
+    }
+
+    #[test]
+    fn static_displays_are_drawn_once() {
+        let mut graphics = graphics();
+        for _ in 0..10 {
+            graphics.present();
+        }
+        assert_eq!(graphics.presents(), 1);
+
+        // A burst of draws within a frame is drawn once too.
+        graphics.last_draw = 0;
+        for x in 0..8 {
+            graphics.draw(x * 8, 0, vec![0xFF]);
+        }
+        graphics.present();
+        graphics.present();
+        assert_eq!(graphics.presents(), 2);
+    }
+
+    /// Coordinates of every lit pixel, row by row.
+    fn lit_pixels(graphics: &Graphics) -> Vec<(usize, usize)> {
+        graphics.display().iter()
+            .enumerate()
+            .filter(|&(_, &pixel)| pixel != 0)
+            .map(|(index, _)| (index % graphics.width(), index / graphics.width()))
+            .collect()
+    }
+
+    #[test]
+    fn scrolling_moves_the_pattern_and_blanks_the_edge() {
+        let mut graphics = graphics();
+        graphics.draw(0, 0, vec![0x81, 0x00, 0x18]);
+        assert_eq!(lit_pixels(&graphics), [(0, 0), (7, 0), (3, 2), (4, 2)]);
+
+        graphics.scroll_down(3);
+        assert_eq!(lit_pixels(&graphics), [(0, 3), (7, 3), (3, 5), (4, 5)]);
+
+        graphics.scroll_right(4);
+        assert_eq!(lit_pixels(&graphics), [(4, 3), (11, 3), (7, 5), (8, 5)]);
+
+        graphics.scroll_left(8);
+        assert_eq!(lit_pixels(&graphics), [(3, 3), (0, 5)]);
+
+        // Nothing scrolled out comes back.
+        graphics.scroll_down(DISPLAY_HEIGHT);
+        assert!(lit_pixels(&graphics).is_empty());
+    }
+
+    #[test]
+    fn scrolling_follows_the_high_resolution() {
+        let mut graphics = graphics();
+        graphics.set_hires(true);
+        graphics.draw(120, 60, vec![0x01]);
+
+        graphics.scroll_down(2);
+        graphics.scroll_left(4);
+        assert_eq!(lit_pixels(&graphics), [(123, 62)]);
+
+        graphics.scroll_right(4);
+        assert_eq!(lit_pixels(&graphics), [(127, 62)]);
+    }
+
+    #[test]
+    fn screenshots_scale_the_display() {
+        let mut graphics = graphics();
+        graphics.draw(0, 0, vec![0b1000_0000]);
+
+        let (width, height, image) = graphics.screenshot(3);
+        assert_eq!((width, height), (DISPLAY_WIDTH * 3, DISPLAY_HEIGHT * 3));
+        assert_eq!(image.len(), width * height * 4);
+
+        // The lit pixel covers a 3x3 square in the corner.
+        let white = [0xFF, 0xFF, 0xFF, 0xFF];
+        assert_eq!(image[(2 * width + 2) * 4..(2 * width + 3) * 4], white);
+        assert!(image[(3 * width + 3) * 4..(3 * width + 4) * 4] != white);
+    }
+
+    #[test]
+    fn drawing_only_touches_the_selected_planes() {
+        let mut graphics = graphics();
+
+        graphics.set_planes(0b10);
+        graphics.draw(0, 0, vec![0b1100_0000]);
+        assert_eq!(graphics.display()[..3], [0b10, 0b10, 0]);
+
+        graphics.set_planes(0b01);
+        assert_eq!(graphics.draw(1, 0, vec![0b1000_0000]), 0);
+        assert_eq!(graphics.display()[..3], [0b10, 0b11, 0]);
+    }
+
+    #[test]
+    fn both_planes_take_half_the_sprite_each() {
+        let mut graphics = graphics();
+
+        graphics.set_planes(0b11);
+        graphics.draw(0, 0, vec![0b1000_0000, 0b0100_0000]);
+        assert_eq!(graphics.display()[..2], [0b01, 0b10]);
+        assert_eq!(graphics.display()[graphics.width()], 0);
+    }
+
+    #[test]
+    fn clearing_and_scrolling_leave_other_planes_alone() {
+        let mut graphics = graphics();
+        graphics.set_planes(0b11);
+        graphics.draw(0, 0, vec![0b1000_0000, 0b1000_0000]);
+
+        graphics.set_planes(0b10);
+        graphics.scroll_right(4);
+        assert_eq!(graphics.display()[0], 0b01);
+        assert_eq!(graphics.display()[4], 0b10);
+
+        graphics.clear_display();
+        assert_eq!(lit_pixels(&graphics), [(0, 0)]);
+        assert_eq!(graphics.display()[0], 0b01);
+    }
+
+    #[test]
+    fn the_default_plane_draws_like_plain_chip8() {
+        let mut graphics = graphics();
+        graphics.draw(0, 0, vec![0b1010_0000]);
+        assert_eq!(graphics.display()[..4], [1, 0, 1, 0]);
+
+        assert_eq!(graphics.draw(0, 0, vec![0b1000_0000]), 1);
+        assert_eq!(graphics.display()[..4], [0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn frame_rate_limits_hold_back_early_frames() {
+        let mut graphics = graphics();
+        graphics.set_fps(1);
+
+        for x in 0..30 {
+            graphics.draw(x, 0, vec![0b1000_0000]);
+            graphics.present();
+        }
+        assert_eq!(graphics.presents(), 1);
+
+        // The changes held back are drawn once a frame is due.
+        graphics.last_draw -= 1_000_000_000;
+        graphics.present();
+        assert_eq!(graphics.presents(), 2);
+        graphics.present();
+        assert_eq!(graphics.presents(), 2);
     }
 }
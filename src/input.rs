@@ -1,27 +1,154 @@
-use std::thread::sleep;
-use std::time::Duration;
-
+use super::sdl2::controller::{Button, GameController};
 use super::sdl2::event::Event;
 use super::sdl2::keyboard::Keycode;
 use super::sdl2;
 
-// Wait for the duration it takes for an instruction to execute.
-const INPUT_WAIT_DELAY: u64 = 2;
+// Keyboard keys bound to the CHIP-8 keypad by default, indexed by keypad key.
+// The left hand block of the keyboard mirrors the keypad's layout:
+//
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D      Q W E R
+//   7 8 9 E      A S D F
+//   A 0 B F      Z X C V
+pub const DEFAULT_KEYMAP: [Keycode; 16] = [
+    Keycode::X,    Keycode::Num1, Keycode::Num2, Keycode::Num3,
+    Keycode::Q,    Keycode::W,    Keycode::E,    Keycode::A,
+    Keycode::S,    Keycode::D,    Keycode::Z,    Keycode::C,
+    Keycode::Num4, Keycode::R,    Keycode::F,    Keycode::V,
+];
+
+// Controller buttons bound to keypad keys by default. Most games steer with
+// 2, 4, 6 and 8 and act with 5, the remaining buttons cover the other keys
+// games commonly use.
+pub const DEFAULT_BUTTON_MAP: [(Button, u8); 8] = [
+    (Button::DPadUp, 0x2),
+    (Button::DPadLeft, 0x4),
+    (Button::DPadRight, 0x6),
+    (Button::DPadDown, 0x8),
+    (Button::A, 0x5),
+    (Button::B, 0x0),
+    (Button::X, 0xA),
+    (Button::Y, 0xB),
+];
+
+// Restarts the loaded rom.
+const RESET_KEY: Keycode = Keycode::F5;
+
+// Switches between a window and fullscreen.
+const FULLSCREEN_KEY: Keycode = Keycode::F11;
+
+// Saves a screenshot of the display.
+const SCREENSHOT_KEY: Keycode = Keycode::F12;
+
+// Pauses and resumes execution.
+const PAUSE_KEY: Keycode = Keycode::P;
+
+// Saves the machine state to the quicksave slot and restores it.
+const QUICKSAVE_KEY: Keycode = Keycode::F6;
+const QUICKLOAD_KEY: Keycode = Keycode::F9;
+
+// Steps back through the recent frames while held.
+const REWIND_KEY: Keycode = Keycode::Backspace;
+
+// Executes a single instruction while paused.
+const STEP_KEY: Keycode = Keycode::F10;
+
+// Sets or clears a breakpoint at the program counter while paused.
+const BREAKPOINT_KEY: Keycode = Keycode::F8;
+
+// Doubles and halves the emulation speed, and returns it to normal.
+const SPEED_UP_KEY: Keycode = Keycode::Equals;
+const SLOW_DOWN_KEY: Keycode = Keycode::Minus;
+const NORMAL_SPEED_KEY: Keycode = Keycode::Num0;
+
+/// A source of keypad input other than the keyboard, a MIDI or network
+/// bridge for example. Providers are polled once at the start of every
+/// emulated frame and the keys they hold are merged with the keyboard.
+pub trait InputProvider {
+    /// Marks the keys held down through this provider during the passed
+    /// frame, counted from the first poll. Keys held by other sources are
+    /// already set and must be left alone.
+    fn poll(&mut self, frame: u64, keys: &mut [bool; 16]);
+}
 
 pub struct Input {
-    event_pump: sdl2::EventPump,
+    // No events are collected when running headless.
+    event_pump: Option<sdl2::EventPump>,
 
-    // The current keyboard input state.
+    // The keypad state of the current frame, keyboard and providers
+    // combined.
     pub input_state: [bool; 16],
 
-    // Used for input waiting.
-    pub input_dirty: bool,
+    // Keys held on the keyboard alone, and keys that went down on it since
+    // the current frame started.
+    keyboard_state: [bool; 16],
+    keyboard_taps: [bool; 16],
 
-    // Last key pressed.
-    pub last_input: u8,
+    // The keyboard key bound to each keypad key.
+    keymap: [Keycode; 16],
+
+    // Game controllers when the subsystem is available, and every attached
+    // controller.
+    controller_subsystem: Option<sdl2::GameControllerSubsystem>,
+    controllers: Vec<GameController>,
+
+    // The keypad key each controller button is bound to and the keys held
+    // through controllers.
+    button_map: Vec<(Button, u8)>,
+    controller_state: [bool; 16],
+
+    // Additional input sources merged into the keypad state.
+    providers: Vec<Box<dyn InputProvider>>,
+
+    // Keys played back from a recording, used in place of the keyboard and
+    // providers while set.
+    replay_keys: Option<[bool; 16]>,
+
+    // Keys that went down when the current frame started, used for input
+    // waiting.
+    pub pressed: [bool; 16],
+
+    // Number of frames started so far, the time base of providers.
+    frame: u64,
 
     // Set to true when sdl sends a close event.
     pub close_requested: bool,
+
+    // Set when the reset key is pressed, cleared by the cpu once it reset.
+    pub reset_requested: bool,
+
+    // Set when the fullscreen key is pressed, cleared by the cpu once the
+    // display switched.
+    pub fullscreen_requested: bool,
+
+    // Set when the screenshot key is pressed, cleared by the cpu once the
+    // screenshot is saved.
+    pub screenshot_requested: bool,
+
+    // Set when the pause key is pressed, cleared by the cpu once it paused
+    // or resumed.
+    pub pause_requested: bool,
+
+    // Set when the quicksave and quickload keys are pressed, cleared by the
+    // cpu once it saved or loaded.
+    pub quicksave_requested: bool,
+    pub quickload_requested: bool,
+
+    // True while the rewind key is held down.
+    pub rewind_held: bool,
+
+    // Set when the step key is pressed, cleared by the cpu once it stepped.
+    pub step_requested: bool,
+
+    // Set when the breakpoint key is pressed, cleared by the cpu once it
+    // toggled the breakpoint.
+    pub breakpoint_requested: bool,
+
+    // Set when the speed keys are pressed, cleared by the cpu once it changed
+    // speed.
+    pub speed_up_requested: bool,
+    pub slow_down_requested: bool,
+    pub normal_speed_requested: bool,
 }
 
 impl Input {
@@ -29,93 +156,400 @@ impl Input {
         // SDL object used to collect input events.
         let event_pump = sdl_context.event_pump().unwrap();
 
+        let mut input = Input::with_event_pump(Some(event_pump));
+
+        // Controllers are optional, the keyboard works without them.
+        match sdl_context.game_controller() {
+            Ok(subsystem) => {
+                input.controller_subsystem = Some(subsystem);
+                input.open_controllers();
+            },
+            Err(why) => println!("notch: controllers unavailable: {}", why),
+        }
+
+        input
+    }
+
+    /// Creates input without a keyboard. Keys only come from providers.
+    pub fn headless() -> Input {
+        Input::with_event_pump(None)
+    }
+
+    fn with_event_pump(event_pump: Option<sdl2::EventPump>) -> Input {
         Input {
             event_pump: event_pump,
             input_state: [false; 16],
-            input_dirty: false,
-            last_input: 0,
+            keyboard_state: [false; 16],
+            keyboard_taps: [false; 16],
+            keymap: DEFAULT_KEYMAP,
+            controller_subsystem: None,
+            controllers: Vec::new(),
+            button_map: DEFAULT_BUTTON_MAP.to_vec(),
+            controller_state: [false; 16],
+            providers: Vec::new(),
+            replay_keys: None,
+            pressed: [false; 16],
+            frame: 0,
             close_requested: false,
+            reset_requested: false,
+            fullscreen_requested: false,
+            screenshot_requested: false,
+            pause_requested: false,
+            quicksave_requested: false,
+            quickload_requested: false,
+            rewind_held: false,
+            step_requested: false,
+            breakpoint_requested: false,
+            speed_up_requested: false,
+            slow_down_requested: false,
+            normal_speed_requested: false,
+        }
+    }
+
+    /// Whether keys come from a keyboard, which also means a window the
+    /// player can use to pause and resume.
+    pub fn has_keyboard(&self) -> bool {
+        self.event_pump.is_some()
+    }
+
+    /// Labels every keypad key with the name of the keyboard key bound to it,
+    /// for showing the controls to the player.
+    pub fn keypad_labels(&self) -> [(usize, String); 16] {
+        let mut labels: [(usize, String); 16] = Default::default();
+        for (key, label) in labels.iter_mut().enumerate() {
+            *label = (key, self.keymap[key].name());
+        }
+
+        labels
+    }
+
+    /// Binds a keyboard key to a keypad key in place of its default. A
+    /// keyboard key only controls one keypad key, so if it was already bound
+    /// elsewhere the two keypad keys trade keyboard keys.
+    pub fn bind_key(&mut self, key: u8, keycode: Keycode) {
+        let key = key as usize;
+        if let Some(other) = self.keymap.iter().position(|&bound| bound == keycode) {
+            self.keymap[other] = self.keymap[key];
+        }
+        self.keymap[key] = keycode;
+    }
+
+    /// Binds a controller button to a keypad key in place of its default.
+    pub fn bind_button(&mut self, key: u8, button: Button) {
+        self.button_map.retain(|&(bound, _)| bound != button);
+        self.button_map.push((button, key));
+    }
+
+    /// Opens every attached controller, replacing the ones open already.
+    /// Called again whenever controllers are plugged in or pulled out.
+    fn open_controllers(&mut self) {
+        self.controllers.clear();
+        self.controller_state = [false; 16];
+
+        let subsystem = match self.controller_subsystem {
+            Some(ref subsystem) => subsystem,
+            None => return,
+        };
+        let count = subsystem.num_joysticks().unwrap_or(0);
+        for id in (0..count).filter(|&id| subsystem.is_game_controller(id)) {
+            match subsystem.open(id) {
+                Ok(controller) => self.controllers.push(controller),
+                Err(why) => println!("notch: cannot open controller {}: {}", id, why),
+            }
         }
     }
 
-    /// Get input events from SDL and set the input state.
+    /// Adds an input source whose keys are merged with the keyboard.
+    pub fn add_provider(&mut self, provider: Box<dyn InputProvider>) {
+        self.providers.push(provider);
+    }
+
+    /// Holds exactly the passed keys, ignoring the keyboard and providers,
+    /// until the next call. Passing None goes back to live input.
+    pub fn replay(&mut self, keys: Option<[bool; 16]>) {
+        self.replay_keys = keys;
+    }
+
+    /// Get input events from SDL. The keypad state only follows them once
+    /// the next frame starts.
     pub fn handle_input(&mut self) {
         // Collect the events from the iterator ahead of time so we are not
         // borrowing when we need to set the input state.
-        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+        let events: Vec<Event> = match self.event_pump {
+            Some(ref mut event_pump) => event_pump.poll_iter().collect(),
+            None => Vec::new(),
+        };
 
         for event in events {
-            match event {
-                Event::Quit {..} => {
-                    // Detect close button or escape button events.
-                    // The interpreter is then signaled to halt and stop
-                    // executing code when the cpu reads this value.
-                    self.close_requested = true;
-                },
-
-                // Keyboard to CHIP-8 keycode mapping.
-                Event::KeyDown { keycode: Some(Keycode::Num0), .. } => { self.set_input(0x0, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::Num0), .. } => { self.set_input(0x0, false); },
-                Event::KeyDown { keycode: Some(Keycode::Num1), .. } => { self.set_input(0x1, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::Num1), .. } => { self.set_input(0x1, false); },
-                Event::KeyDown { keycode: Some(Keycode::Num2), .. } => { self.set_input(0x2, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::Num2), .. } => { self.set_input(0x2, false); },
-                Event::KeyDown { keycode: Some(Keycode::Num3), .. } => { self.set_input(0x3, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::Num3), .. } => { self.set_input(0x3, false); },
-                Event::KeyDown { keycode: Some(Keycode::Num4), .. } => { self.set_input(0x4, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::Num4), .. } => { self.set_input(0x4, false); },
-                Event::KeyDown { keycode: Some(Keycode::Num5), .. } => { self.set_input(0x5, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::Num5), .. } => { self.set_input(0x5, false); },
-                Event::KeyDown { keycode: Some(Keycode::Num6), .. } => { self.set_input(0x6, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::Num6), .. } => { self.set_input(0x6, false); },
-                Event::KeyDown { keycode: Some(Keycode::Num7), .. } => { self.set_input(0x7, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::Num7), .. } => { self.set_input(0x7, false); },
-                Event::KeyDown { keycode: Some(Keycode::Num8), .. } => { self.set_input(0x8, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::Num8), .. } => { self.set_input(0x8, false); },
-                Event::KeyDown { keycode: Some(Keycode::Num9), .. } => { self.set_input(0x9, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::Num9), .. } => { self.set_input(0x9, false); },
-                Event::KeyDown { keycode: Some(Keycode::A),    .. } => { self.set_input(0xa, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::A),    .. } => { self.set_input(0xa, false); },
-                Event::KeyDown { keycode: Some(Keycode::B),    .. } => { self.set_input(0xb, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::B),    .. } => { self.set_input(0xb, false); },
-                Event::KeyDown { keycode: Some(Keycode::C),    .. } => { self.set_input(0xc, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::C),    .. } => { self.set_input(0xc, false); },
-                Event::KeyDown { keycode: Some(Keycode::D),    .. } => { self.set_input(0xd, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::D),    .. } => { self.set_input(0xd, false); },
-                Event::KeyDown { keycode: Some(Keycode::E),    .. } => { self.set_input(0xe, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::E),    .. } => { self.set_input(0xe, false); },
-                Event::KeyDown { keycode: Some(Keycode::F),    .. } => { self.set_input(0xf, true ); },
-                Event::KeyUp   { keycode: Some(Keycode::F),    .. } => { self.set_input(0xf, false); },
-                _ => {}
-            }
+            self.handle_event(event);
         }
     }
 
-    /// Wait until an input event comes through and return the key for that
-    /// input event.
-    pub fn wait_input(&mut self) -> u8 {
-        // Input dirtiness is used to determine if a key has been pressed,
-        // regardless if the input state changed at all.
-        self.input_dirty = false;
+    /// Applies a single SDL event to the keyboard and controller state and
+    /// the requests passed on to the cpu.
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Quit {..} |
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                // Detect close button or escape button events.
+                // The interpreter is then signaled to halt and stop
+                // executing code when the cpu reads this value.
+                self.close_requested = true;
+            },
 
-        loop {
-            // Poll for input from SDL.
-            self.handle_input();
+            // Keys bound to the keypad go to the keypad, even keys that are
+            // also hotkeys, so any key can be bound.
+            Event::KeyDown { keycode: Some(keycode), .. } if self.keypad_key(keycode).is_some() => {
+                let key = self.keypad_key(keycode).unwrap();
+                self.set_input(key, true);
+            },
+            Event::KeyUp { keycode: Some(keycode), .. } if self.keypad_key(keycode).is_some() => {
+                let key = self.keypad_key(keycode).unwrap();
+                self.set_input(key, false);
+            },
 
-            // Return the key that was pressed to make the input state dirty.
-            if self.input_dirty {
-                break;
-            }
+            Event::KeyDown { keycode: Some(keycode), .. } if keycode == RESET_KEY => {
+                self.reset_requested = true;
+            },
+            Event::KeyDown { keycode: Some(keycode), .. } if keycode == FULLSCREEN_KEY => {
+                self.fullscreen_requested = true;
+            },
+            Event::KeyDown { keycode: Some(keycode), .. } if keycode == SCREENSHOT_KEY => {
+                self.screenshot_requested = true;
+            },
+            Event::KeyDown { keycode: Some(keycode), .. } if keycode == PAUSE_KEY => {
+                self.pause_requested = true;
+            },
+            Event::KeyDown { keycode: Some(keycode), .. } if keycode == QUICKSAVE_KEY => {
+                self.quicksave_requested = true;
+            },
+            Event::KeyDown { keycode: Some(keycode), .. } if keycode == QUICKLOAD_KEY => {
+                self.quickload_requested = true;
+            },
+            Event::KeyDown { keycode: Some(keycode), .. } if keycode == REWIND_KEY => {
+                self.rewind_held = true;
+            },
+            Event::KeyUp { keycode: Some(keycode), .. } if keycode == REWIND_KEY => {
+                self.rewind_held = false;
+            },
+            Event::KeyDown { keycode: Some(keycode), .. } if keycode == STEP_KEY => {
+                self.step_requested = true;
+            },
+            Event::KeyDown { keycode: Some(keycode), .. } if keycode == BREAKPOINT_KEY => {
+                self.breakpoint_requested = true;
+            },
+            Event::KeyDown { keycode: Some(keycode), .. } if keycode == SPEED_UP_KEY => {
+                self.speed_up_requested = true;
+            },
+            Event::KeyDown { keycode: Some(keycode), .. } if keycode == SLOW_DOWN_KEY => {
+                self.slow_down_requested = true;
+            },
+            Event::KeyDown { keycode: Some(keycode), .. } if keycode == NORMAL_SPEED_KEY => {
+                self.normal_speed_requested = true;
+            },
 
-            sleep(Duration::from_millis(INPUT_WAIT_DELAY));
+            Event::ControllerButtonDown { button, .. } => {
+                if let Some(key) = self.controller_key(button) {
+                    self.controller_state[key as usize] = true;
+                }
+            },
+            Event::ControllerButtonUp { button, .. } => {
+                if let Some(key) = self.controller_key(button) {
+                    self.controller_state[key as usize] = false;
+                }
+            },
+            Event::ControllerDeviceAdded { .. } |
+            Event::ControllerDeviceRemoved { .. } => {
+                self.open_controllers();
+            },
+            _ => {}
         }
+    }
+
+    /// Starts the next emulated frame by combining the keyboard state with
+    /// every provider's keys. The keypad holds that state for the whole
+    /// frame so runs don't depend on when SDL delivers events. Keys newly
+    /// held down count as presses for input waiting during the frame.
+    /// Controllers count as part of the keyboard, and keys tapped since the
+    /// last frame are held for this one so short taps aren't lost. A replay
+    /// replaces all of them.
+    pub fn next_frame(&mut self) {
+        let keys = match self.replay_keys {
+            Some(keys) => keys,
+            None => {
+                let mut keys = self.keyboard_state;
+                for (key, (&held, &tapped)) in keys.iter_mut()
+                    .zip(self.controller_state.iter().zip(self.keyboard_taps.iter())) {
+                    *key = *key || held || tapped;
+                }
+                for provider in &mut self.providers {
+                    provider.poll(self.frame, &mut keys);
+                }
+                keys
+            },
+        };
+        self.keyboard_taps = [false; 16];
+
+        for (pressed, (&held, &was_held)) in self.pressed.iter_mut()
+            .zip(keys.iter().zip(self.input_state.iter())) {
+            *pressed = held && !was_held;
+        }
+        self.input_state = keys;
+        self.frame += 1;
+    }
+
+    /// Takes the key pressed when the current frame started, so the same
+    /// press isn't taken twice. When several keys are pressed at once the
+    /// lowest numbered key wins, so the result doesn't depend on the order
+    /// SDL reports the events in.
+    pub fn take_press(&mut self) -> Option<u8> {
+        let key = self.pressed.iter().position(|&pressed| pressed)?;
+        self.pressed = [false; 16];
+        Some(key as u8)
+    }
 
-        self.last_input
+    /// Forgets the keys pressed when the current frame started, so only
+    /// presses from the next frame on are taken.
+    pub fn clear_presses(&mut self) {
+        self.pressed = [false; 16];
+    }
+
+    /// Looks up the keypad key a keyboard key is bound to.
+    fn keypad_key(&self, keycode: Keycode) -> Option<u8> {
+        self.keymap.iter().position(|&bound| bound == keycode).map(|key| key as u8)
+    }
+
+    /// Looks up the keypad key a controller button is bound to.
+    fn controller_key(&self, button: Button) -> Option<u8> {
+        self.button_map.iter()
+            .find(|&&(bound, _)| bound == button)
+            .map(|&(_, key)| key)
     }
 
     fn set_input(&mut self, key: u8, down: bool) {
-        self.input_state[key as usize] = down;
-        self.last_input = key;
-        self.input_dirty = true;
+        self.keyboard_state[key as usize] = down;
+        if down {
+            self.keyboard_taps[key as usize] = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use super::super::sdl2::keyboard::NOMOD;
+
+    fn key_event(keycode: Keycode, down: bool) -> Event {
+        if down {
+            Event::KeyDown {
+                timestamp: 0,
+                window_id: 0,
+                keycode: Some(keycode),
+                scancode: None,
+                keymod: NOMOD,
+                repeat: false,
+            }
+        } else {
+            Event::KeyUp {
+                timestamp: 0,
+                window_id: 0,
+                keycode: Some(keycode),
+                scancode: None,
+                keymod: NOMOD,
+                repeat: false,
+            }
+        }
+    }
+
+    #[test]
+    fn rebound_keys_toggle_their_keypad_key() {
+        let mut input = Input::headless();
+        input.bind_key(0x5, Keycode::K);
+
+        input.handle_event(key_event(Keycode::K, true));
+        input.next_frame();
+        assert!(input.input_state[0x5]);
+
+        input.handle_event(key_event(Keycode::K, false));
+        input.next_frame();
+        assert!(!input.input_state[0x5]);
+
+        // The default binding no longer reaches the keypad.
+        input.handle_event(key_event(DEFAULT_KEYMAP[0x5], true));
+        input.next_frame();
+        assert!(!input.input_state[0x5]);
+    }
+
+    #[test]
+    fn bound_keys_win_over_hotkeys() {
+        let mut input = Input::headless();
+        input.handle_event(key_event(SPEED_UP_KEY, true));
+        assert!(input.speed_up_requested);
+
+        let mut input = Input::headless();
+        input.bind_key(0x5, SPEED_UP_KEY);
+        input.handle_event(key_event(SPEED_UP_KEY, true));
+        input.next_frame();
+        assert!(input.input_state[0x5]);
+        assert!(!input.speed_up_requested);
+
+        input.handle_event(key_event(SPEED_UP_KEY, false));
+        input.next_frame();
+        assert!(!input.input_state[0x5]);
+    }
+
+    #[test]
+    fn the_last_keypad_key_is_tracked() {
+        let mut input = Input::headless();
+        input.input_state[0xF] = true;
+        assert!(input.input_state[0xF]);
+        assert!(!input.input_state[0xE]);
+
+        // Pressing it on the keyboard reaches the keypad too.
+        let mut input = Input::headless();
+        input.handle_event(key_event(DEFAULT_KEYMAP[0xF], true));
+        input.next_frame();
+        assert!(input.input_state[0xF]);
+        assert_eq!(input.take_press(), Some(0xF));
+    }
+
+    /// The keypad keys held this frame, lowest first.
+    fn keys_held(input: &Input) -> Vec<usize> {
+        (0..16).filter(|&key| input.input_state[key]).collect()
+    }
+
+    /// Holds the key matching the frame number and notes which keys were
+    /// already held when polled.
+    struct FrameProvider {
+        seen: Rc<RefCell<Vec<[bool; 16]>>>,
+    }
+
+    impl InputProvider for FrameProvider {
+        fn poll(&mut self, frame: u64, keys: &mut [bool; 16]) {
+            self.seen.borrow_mut().push(*keys);
+            keys[frame as usize] = true;
+        }
+    }
+
+    #[test]
+    fn provider_keys_merge_with_the_keyboard() {
+        let seen = Rc::default();
+        let mut input = Input::headless();
+        input.add_provider(Box::new(FrameProvider { seen: Rc::clone(&seen) }));
+        input.handle_event(key_event(DEFAULT_KEYMAP[0x8], true));
+
+        input.next_frame();
+        assert_eq!(keys_held(&input), [0x0, 0x8]);
+
+        input.next_frame();
+        assert_eq!(keys_held(&input), [0x1, 0x8]);
+
+        // Providers see the keyboard keys and have to leave them alone.
+        let seen = seen.borrow();
+        assert_eq!(seen.len(), 2);
+        assert!(seen.iter().all(|keys| keys[0x8]));
     }
 }
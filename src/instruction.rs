@@ -0,0 +1,399 @@
+use std::fmt;
+
+use super::error::CpuError;
+
+/// Instruction sets that extend CHIP-8. Some of their instructions take the
+/// place of plain CHIP-8 ones, so decoding depends on which are enabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Extensions {
+    // The Chip-8X color instructions, BXYN replaces BNNN.
+    pub chip8x: bool,
+
+    // The Super-CHIP scrolling and high resolution instructions.
+    pub schip: bool,
+
+    // The XO-CHIP long load, F000 NNNN, and plane selection.
+    pub xochip: bool,
+}
+
+/// A decoded instruction. Fields are named after the parts of the word they
+/// come from: `regx` and `regy` are the second and third nibbles, `addr` the
+/// lowest 12 bits, `byte` the lowest 8 bits and `nibble` the lowest 4 bits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    // 00E0 - CLS
+    Cls,
+
+    // 00EE - RET
+    Ret,
+
+    // 00FD - EXIT
+    Exit,
+
+    // 0000 - HALT
+    Halt,
+
+    // 0NNN - SYS NNN
+    Sys { addr: u16 },
+
+    // 02A0 - Chip-8X: Step background color
+    StepBackground,
+
+    // 00CN - Super-CHIP: SCD N
+    ScrollDown { nibble: u8 },
+
+    // 00FB - Super-CHIP: SCR
+    ScrollRight,
+
+    // 00FC - Super-CHIP: SCL
+    ScrollLeft,
+
+    // 00FE - Super-CHIP: LOW
+    LowRes,
+
+    // 00FF - Super-CHIP: HIGH
+    HighRes,
+
+    // 1NNN - JP NNN
+    Jump { addr: u16 },
+
+    // 2NNN - CALL NNN
+    Call { addr: u16 },
+
+    // 3XNN - SE VX, NN
+    SkipEqualByte { regx: u8, byte: u8 },
+
+    // 4XNN - SNE VX, NN
+    SkipNotEqualByte { regx: u8, byte: u8 },
+
+    // 5XY0 - SE VX, VY
+    SkipEqual { regx: u8, regy: u8 },
+
+    // 6XNN - LD VX, NN
+    LoadByte { regx: u8, byte: u8 },
+
+    // 7XNN - ADD VX, NN
+    AddByte { regx: u8, byte: u8 },
+
+    // 8XY0 - LD VX, VY
+    Load { regx: u8, regy: u8 },
+
+    // 8XY1 - OR VX, VY
+    Or { regx: u8, regy: u8 },
+
+    // 8XY2 - AND VX, VY
+    And { regx: u8, regy: u8 },
+
+    // 8XY3 - XOR VX, VY
+    Xor { regx: u8, regy: u8 },
+
+    // 8XY4 - ADD VX, VY
+    Add { regx: u8, regy: u8 },
+
+    // 8XY5 - SUB VX, VY
+    Sub { regx: u8, regy: u8 },
+
+    // 8XY6 - SHR VX {, VY}
+    ShiftRight { regx: u8, regy: u8 },
+
+    // 8XY7 - SUBN VX, VY
+    SubN { regx: u8, regy: u8 },
+
+    // 8XYE - SHL VX {, VY}
+    ShiftLeft { regx: u8, regy: u8 },
+
+    // 9XY0 - SNE VX, VY
+    SkipNotEqual { regx: u8, regy: u8 },
+
+    // ANNN - LD I, NNN
+    LoadI { addr: u16 },
+
+    // BNNN - JP V0, NNN
+    JumpOffset { addr: u16 },
+
+    // BXYN - Chip-8X: Set foreground color
+    SetZoneColor { regx: u8, regy: u8, nibble: u8 },
+
+    // CXNN - RND VX, NN
+    Random { regx: u8, byte: u8 },
+
+    // DXYN - DRW VX, VY, N
+    Draw { regx: u8, regy: u8, nibble: u8 },
+
+    // EX9E - SKP VX
+    SkipKey { regx: u8 },
+
+    // EXA1 - SKNP VX
+    SkipNotKey { regx: u8 },
+
+    // FX07 - LD VX, DT
+    LoadDelay { regx: u8 },
+
+    // FX0A - LD VX, K
+    WaitKey { regx: u8 },
+
+    // FX15 - LD DT, VX
+    SetDelay { regx: u8 },
+
+    // FX18 - LD ST, VX
+    SetSound { regx: u8 },
+
+    // FX1E - ADD I, VX
+    AddI { regx: u8 },
+
+    // FX29 - LD F, VX
+    LoadFont { regx: u8 },
+
+    // FX33 - LD B, VX
+    Bcd { regx: u8 },
+
+    // FX55 - LD [I], VX
+    StoreRegisters { regx: u8 },
+
+    // FX65 - LD VX, [I]
+    LoadRegisters { regx: u8 },
+
+    // FX75 - Super-CHIP: LD R, VX
+    StoreFlags { regx: u8 },
+
+    // FX85 - Super-CHIP: LD VX, R
+    LoadFlags { regx: u8 },
+
+    // F000 NNNN - XO-CHIP: LD I, NNNN. The address is the word after the
+    // instruction, so it isn't decoded here.
+    LoadLongI,
+
+    // FN01 - XO-CHIP: PLANE N. The mask is the second nibble.
+    SelectPlanes { mask: u8 },
+}
+
+/// Decodes a word read from `addr` into the instruction it encodes with the
+/// passed extensions enabled. Words that aren't an instruction are an
+/// unknown opcode at `addr`.
+pub fn decode(instr: u16, addr: u16, extensions: Extensions) -> Result<Instruction, CpuError> {
+    let unknown = CpuError::UnknownOpcode { instr: instr, addr: addr };
+    let opcode = (instr >> 12) as u8;
+    let regx = ((instr << 4) >> 12) as u8;
+    let regy = ((instr << 8) >> 12) as u8;
+    let nibble = ((instr << 12) >> 12) as u8;
+    let byte = ((instr << 8) >> 8) as u8;
+    let nnn = (instr << 4) >> 4;
+
+    let decoded = match opcode {
+        0x0 => match instr {
+            0x00E0 => Instruction::Cls,
+            0x00EE => Instruction::Ret,
+            0x00FD => Instruction::Exit,
+            0x0000 => Instruction::Halt,
+            0x02A0 if extensions.chip8x => Instruction::StepBackground,
+            0x00C0..=0x00CF if extensions.schip => Instruction::ScrollDown { nibble: nibble },
+            0x00FB if extensions.schip => Instruction::ScrollRight,
+            0x00FC if extensions.schip => Instruction::ScrollLeft,
+            0x00FE if extensions.schip => Instruction::LowRes,
+            0x00FF if extensions.schip => Instruction::HighRes,
+            _ => Instruction::Sys { addr: nnn },
+        },
+        0x1 => Instruction::Jump { addr: nnn },
+        0x2 => Instruction::Call { addr: nnn },
+        0x3 => Instruction::SkipEqualByte { regx: regx, byte: byte },
+        0x4 => Instruction::SkipNotEqualByte { regx: regx, byte: byte },
+
+        // The low nibble has to be zero, anything else is not a valid
+        // instruction. The same goes for 9XY0.
+        0x5 if nibble == 0 => Instruction::SkipEqual { regx: regx, regy: regy },
+        0x6 => Instruction::LoadByte { regx: regx, byte: byte },
+        0x7 => Instruction::AddByte { regx: regx, byte: byte },
+        0x8 => match nibble {
+            0x0 => Instruction::Load { regx: regx, regy: regy },
+            0x1 => Instruction::Or { regx: regx, regy: regy },
+            0x2 => Instruction::And { regx: regx, regy: regy },
+            0x3 => Instruction::Xor { regx: regx, regy: regy },
+            0x4 => Instruction::Add { regx: regx, regy: regy },
+            0x5 => Instruction::Sub { regx: regx, regy: regy },
+            0x6 => Instruction::ShiftRight { regx: regx, regy: regy },
+            0x7 => Instruction::SubN { regx: regx, regy: regy },
+            0xe => Instruction::ShiftLeft { regx: regx, regy: regy },
+            _ => return Err(unknown),
+        },
+        0x9 if nibble == 0 => Instruction::SkipNotEqual { regx: regx, regy: regy },
+        0xa => Instruction::LoadI { addr: nnn },
+        0xb if extensions.chip8x => {
+            Instruction::SetZoneColor { regx: regx, regy: regy, nibble: nibble }
+        },
+        0xb => Instruction::JumpOffset { addr: nnn },
+        0xc => Instruction::Random { regx: regx, byte: byte },
+        0xd => Instruction::Draw { regx: regx, regy: regy, nibble: nibble },
+        0xe => match byte {
+            0x9e => Instruction::SkipKey { regx: regx },
+            0xa1 => Instruction::SkipNotKey { regx: regx },
+            _ => return Err(unknown),
+        },
+        0xf => match byte {
+            0x00 if extensions.xochip && instr == 0xF000 => Instruction::LoadLongI,
+            0x01 if extensions.xochip => Instruction::SelectPlanes { mask: regx },
+            0x07 => Instruction::LoadDelay { regx: regx },
+            0x0a => Instruction::WaitKey { regx: regx },
+            0x15 => Instruction::SetDelay { regx: regx },
+            0x18 => Instruction::SetSound { regx: regx },
+            0x1e => Instruction::AddI { regx: regx },
+            0x29 => Instruction::LoadFont { regx: regx },
+            0x33 => Instruction::Bcd { regx: regx },
+            0x55 => Instruction::StoreRegisters { regx: regx },
+            0x65 => Instruction::LoadRegisters { regx: regx },
+            0x75 => Instruction::StoreFlags { regx: regx },
+            0x85 => Instruction::LoadFlags { regx: regx },
+            _ => return Err(unknown),
+        },
+        _ => return Err(unknown),
+    };
+
+    Ok(decoded)
+}
+
+/// Formats the instruction as its mnemonic, "LD V0, 0x1F" for example.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Halt => write!(f, "HALT"),
+            Instruction::Sys { addr } => write!(f, "SYS 0x{:03X}", addr),
+            Instruction::StepBackground => write!(f, "BGND"),
+            Instruction::ScrollDown { nibble } => write!(f, "SCD {}", nibble),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::Jump { addr } => write!(f, "JP 0x{:03X}", addr),
+            Instruction::Call { addr } => write!(f, "CALL 0x{:03X}", addr),
+            Instruction::SkipEqualByte { regx, byte } => write!(f, "SE V{:X}, 0x{:02X}", regx, byte),
+            Instruction::SkipNotEqualByte { regx, byte } => {
+                write!(f, "SNE V{:X}, 0x{:02X}", regx, byte)
+            },
+            Instruction::SkipEqual { regx, regy } => write!(f, "SE V{:X}, V{:X}", regx, regy),
+            Instruction::LoadByte { regx, byte } => write!(f, "LD V{:X}, 0x{:02X}", regx, byte),
+            Instruction::AddByte { regx, byte } => write!(f, "ADD V{:X}, 0x{:02X}", regx, byte),
+            Instruction::Load { regx, regy } => write!(f, "LD V{:X}, V{:X}", regx, regy),
+            Instruction::Or { regx, regy } => write!(f, "OR V{:X}, V{:X}", regx, regy),
+            Instruction::And { regx, regy } => write!(f, "AND V{:X}, V{:X}", regx, regy),
+            Instruction::Xor { regx, regy } => write!(f, "XOR V{:X}, V{:X}", regx, regy),
+            Instruction::Add { regx, regy } => write!(f, "ADD V{:X}, V{:X}", regx, regy),
+            Instruction::Sub { regx, regy } => write!(f, "SUB V{:X}, V{:X}", regx, regy),
+            Instruction::ShiftRight { regx, regy } => write!(f, "SHR V{:X}, V{:X}", regx, regy),
+            Instruction::SubN { regx, regy } => write!(f, "SUBN V{:X}, V{:X}", regx, regy),
+            Instruction::ShiftLeft { regx, regy } => write!(f, "SHL V{:X}, V{:X}", regx, regy),
+            Instruction::SkipNotEqual { regx, regy } => write!(f, "SNE V{:X}, V{:X}", regx, regy),
+            Instruction::LoadI { addr } => write!(f, "LD I, 0x{:03X}", addr),
+            Instruction::JumpOffset { addr } => write!(f, "JP V0, 0x{:03X}", addr),
+            Instruction::SetZoneColor { regx, regy, nibble } => {
+                write!(f, "COL V{:X}, V{:X}, {}", regx, regy, nibble)
+            },
+            Instruction::Random { regx, byte } => write!(f, "RND V{:X}, 0x{:02X}", regx, byte),
+            Instruction::Draw { regx, regy, nibble } => {
+                write!(f, "DRW V{:X}, V{:X}, {}", regx, regy, nibble)
+            },
+            Instruction::SkipKey { regx } => write!(f, "SKP V{:X}", regx),
+            Instruction::SkipNotKey { regx } => write!(f, "SKNP V{:X}", regx),
+            Instruction::LoadDelay { regx } => write!(f, "LD V{:X}, DT", regx),
+            Instruction::WaitKey { regx } => write!(f, "LD V{:X}, K", regx),
+            Instruction::SetDelay { regx } => write!(f, "LD DT, V{:X}", regx),
+            Instruction::SetSound { regx } => write!(f, "LD ST, V{:X}", regx),
+            Instruction::AddI { regx } => write!(f, "ADD I, V{:X}", regx),
+            Instruction::LoadFont { regx } => write!(f, "LD F, V{:X}", regx),
+            Instruction::Bcd { regx } => write!(f, "LD B, V{:X}", regx),
+            Instruction::StoreRegisters { regx } => write!(f, "LD [I], V{:X}", regx),
+            Instruction::LoadRegisters { regx } => write!(f, "LD V{:X}, [I]", regx),
+            Instruction::StoreFlags { regx } => write!(f, "LD R, V{:X}", regx),
+            Instruction::LoadFlags { regx } => write!(f, "LD V{:X}, R", regx),
+            Instruction::LoadLongI => write!(f, "LD I, LONG"),
+            Instruction::SelectPlanes { mask } => write!(f, "PLANE {}", mask),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: Extensions = Extensions { chip8x: true, schip: true, xochip: true };
+    const NONE: Extensions = Extensions { chip8x: false, schip: false, xochip: false };
+
+    #[test]
+    fn every_opcode_form_decodes_its_fields() {
+        let forms = [
+            (0x00E0, Instruction::Cls),
+            (0x00EE, Instruction::Ret),
+            (0x00FD, Instruction::Exit),
+            (0x0000, Instruction::Halt),
+            (0x0123, Instruction::Sys { addr: 0x123 }),
+            (0x02A0, Instruction::StepBackground),
+            (0x00C7, Instruction::ScrollDown { nibble: 0x7 }),
+            (0x00FB, Instruction::ScrollRight),
+            (0x00FC, Instruction::ScrollLeft),
+            (0x00FE, Instruction::LowRes),
+            (0x00FF, Instruction::HighRes),
+            (0x1ABC, Instruction::Jump { addr: 0xABC }),
+            (0x2ABC, Instruction::Call { addr: 0xABC }),
+            (0x31EF, Instruction::SkipEqualByte { regx: 0x1, byte: 0xEF }),
+            (0x42EF, Instruction::SkipNotEqualByte { regx: 0x2, byte: 0xEF }),
+            (0x5340, Instruction::SkipEqual { regx: 0x3, regy: 0x4 }),
+            (0x65EF, Instruction::LoadByte { regx: 0x5, byte: 0xEF }),
+            (0x76EF, Instruction::AddByte { regx: 0x6, byte: 0xEF }),
+            (0x8780, Instruction::Load { regx: 0x7, regy: 0x8 }),
+            (0x8781, Instruction::Or { regx: 0x7, regy: 0x8 }),
+            (0x8782, Instruction::And { regx: 0x7, regy: 0x8 }),
+            (0x8783, Instruction::Xor { regx: 0x7, regy: 0x8 }),
+            (0x8784, Instruction::Add { regx: 0x7, regy: 0x8 }),
+            (0x8785, Instruction::Sub { regx: 0x7, regy: 0x8 }),
+            (0x8786, Instruction::ShiftRight { regx: 0x7, regy: 0x8 }),
+            (0x8787, Instruction::SubN { regx: 0x7, regy: 0x8 }),
+            (0x878E, Instruction::ShiftLeft { regx: 0x7, regy: 0x8 }),
+            (0x9AB0, Instruction::SkipNotEqual { regx: 0xA, regy: 0xB }),
+            (0xAABC, Instruction::LoadI { addr: 0xABC }),
+            (0xB123, Instruction::SetZoneColor { regx: 0x1, regy: 0x2, nibble: 0x3 }),
+            (0xCDEF, Instruction::Random { regx: 0xD, byte: 0xEF }),
+            (0xD123, Instruction::Draw { regx: 0x1, regy: 0x2, nibble: 0x3 }),
+            (0xE39E, Instruction::SkipKey { regx: 0x3 }),
+            (0xE4A1, Instruction::SkipNotKey { regx: 0x4 }),
+            (0xF507, Instruction::LoadDelay { regx: 0x5 }),
+            (0xF60A, Instruction::WaitKey { regx: 0x6 }),
+            (0xF715, Instruction::SetDelay { regx: 0x7 }),
+            (0xF818, Instruction::SetSound { regx: 0x8 }),
+            (0xF91E, Instruction::AddI { regx: 0x9 }),
+            (0xFA29, Instruction::LoadFont { regx: 0xA }),
+            (0xFB33, Instruction::Bcd { regx: 0xB }),
+            (0xFC55, Instruction::StoreRegisters { regx: 0xC }),
+            (0xFD65, Instruction::LoadRegisters { regx: 0xD }),
+            (0xFE75, Instruction::StoreFlags { regx: 0xE }),
+            (0xFF85, Instruction::LoadFlags { regx: 0xF }),
+            (0xF000, Instruction::LoadLongI),
+            (0xF201, Instruction::SelectPlanes { mask: 0x2 }),
+        ];
+
+        for &(instr, expected) in forms.iter() {
+            assert_eq!(decode(instr, 0x200, ALL).unwrap(), expected, "decoding {:04X}", instr);
+        }
+    }
+
+    #[test]
+    fn extension_forms_fall_back_without_their_extension() {
+        assert_eq!(decode(0x02A0, 0x200, NONE).unwrap(), Instruction::Sys { addr: 0x2A0 });
+        assert_eq!(decode(0x00FF, 0x200, NONE).unwrap(), Instruction::Sys { addr: 0x0FF });
+        assert_eq!(decode(0xB123, 0x200, NONE).unwrap(), Instruction::JumpOffset { addr: 0x123 });
+        assert!(decode(0xF000, 0x200, NONE).is_err());
+        assert!(decode(0xF201, 0x200, NONE).is_err());
+    }
+
+    #[test]
+    fn unknown_words_name_their_address() {
+        for &instr in [0x5121, 0x812F, 0x9AB1, 0xE3A0, 0xF3FF, 0xF100].iter() {
+            match decode(instr, 0x2F0, ALL) {
+                Err(CpuError::UnknownOpcode { instr: word, addr }) => {
+                    assert_eq!((word, addr), (instr, 0x2F0));
+                },
+                other => panic!("expected {:04X} to be unknown, got {:?}", instr, other),
+            }
+        }
+    }
+}
@@ -1,9 +1,16 @@
 use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
 
 use super::sdl2;
 
-use super::graphics::Graphics;
+use super::display::{WINDOW_TITLE, HeadlessDisplay, SdlDisplay, TerminalDisplay};
+use super::error::{MemoryError, RomError};
+use super::gif::GifRecorder;
+use super::graphics::{DISPLAY_HEIGHT, DISPLAY_WIDTH, Graphics};
 use super::memory::Memory;
+use super::png;
 use super::input::Input;
 use super::sound::Sound;
 
@@ -11,7 +18,7 @@ pub struct Interconnect {
     // Memory handles allocation along with reading and writing memory.
     pub memory: Memory,
 
-    // Graphics manages drawing with SDL.
+    // Graphics manages drawing to the display backend.
     pub graphics: Graphics,
 
     // Input handles SDL input events and the input state.
@@ -22,21 +29,179 @@ pub struct Interconnect {
 }
 
 impl Interconnect {
-    pub fn new(rom: Vec<u8>) -> Interconnect {
+    /// Creates the peripherals with `ram_size` bytes of ram, opening a window
+    /// `scale` times the size of the display or covering the screen when
+    /// `fullscreen` is set.
+    pub fn new<R: AsRef<[u8]>>(rom: R, ram_size: usize, scale: u32, fullscreen: bool)
+                               -> Result<Interconnect, RomError> {
+        // Load the rom first so a bad rom doesn't open a window.
+        let memory = Memory::new(rom, ram_size)?;
+
         // Setup SDL for graphics and audio.
         let sdl_context = sdl2::init().unwrap();
 
         // Initialize all the peripherals needed by the virtual machine.
-        let memory = Memory::new(rom);
-        let graphics = Graphics::new(&sdl_context);
+        let display = SdlDisplay::new(&sdl_context, DISPLAY_WIDTH, DISPLAY_HEIGHT, scale,
+                                      fullscreen);
+        let graphics = Graphics::new(Box::new(display));
         let input = Input::new(&sdl_context);
         let sound = Sound::new(&sdl_context);
 
-        Interconnect {
+        Ok(Interconnect {
             memory: memory,
             graphics: graphics,
             input: input,
             sound: sound,
+        })
+    }
+
+    /// Creates an interconnect that doesn't touch SDL at all. Nothing is
+    /// shown or played and keys only come from input providers, which is
+    /// enough to run roms in automated tests.
+    pub fn headless<R: AsRef<[u8]>>(rom: R, ram_size: usize) -> Result<Interconnect, RomError> {
+        Ok(Interconnect {
+            memory: Memory::new(rom, ram_size)?,
+            graphics: Graphics::new(Box::new(HeadlessDisplay)),
+            input: Input::headless(),
+            sound: Sound::headless(),
+        })
+    }
+
+    /// Creates a headless interconnect running the rom stored in a file. Fails
+    /// with `RomError::Unreadable` when the file can't be read and with
+    /// `RomError::TooLarge` when the rom doesn't fit in `ram_size` bytes.
+    pub fn from_file<P: AsRef<Path>>(path: P, ram_size: usize) -> Result<Interconnect, RomError> {
+        Interconnect::headless(Memory::read_rom_file(path)?, ram_size)
+    }
+
+    /// Creates a headless interconnect running the rom read from `reader` to
+    /// its end, a network stream or an archive entry for example. Fails with
+    /// `RomError::Unreadable` when reading fails.
+    pub fn from_reader<R: Read>(reader: R, ram_size: usize) -> Result<Interconnect, RomError> {
+        Interconnect::headless(Memory::read_rom(reader)?, ram_size)
+    }
+
+    /// Creates an interconnect that draws the display in the terminal it was
+    /// started from. SDL isn't touched, so like `headless` keys only come
+    /// from input providers and nothing is played.
+    pub fn terminal<R: AsRef<[u8]>>(rom: R, ram_size: usize) -> Result<Interconnect, RomError> {
+        Ok(Interconnect {
+            memory: Memory::new(rom, ram_size)?,
+            graphics: Graphics::new(Box::new(TerminalDisplay::new())),
+            input: Input::headless(),
+            sound: Sound::headless(),
+        })
+    }
+
+    /// Writes the display as it looks on screen to a PNG file, every pixel
+    /// drawn `scale` times larger.
+    pub fn screenshot<P: AsRef<Path>>(&mut self, path: P, scale: usize) -> io::Result<()> {
+        let (width, height, image) = self.graphics.screenshot(scale);
+        let mut file = File::create(path)?;
+        file.write_all(&png::encode(width, height, &image))
+    }
+
+    /// Starts recording the frames shown to an animated GIF at the passed
+    /// path, until `stop_gif` is called or the recording gets too long.
+    pub fn start_gif<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let (width, height) = self.graphics.gif_size();
+        let recorder = GifRecorder::create(path, width, height)?;
+        self.graphics.start_gif(recorder);
+
+        Ok(())
+    }
+
+    /// Ends the GIF recording, if any.
+    pub fn stop_gif(&mut self) -> io::Result<()> {
+        self.graphics.stop_gif()
+    }
+
+    /// Reads the byte at `addr` for tools like memory viewers. Addresses are
+    /// never mirrored, anything past the end of ram is an error.
+    pub fn peek(&self, addr: usize) -> Result<u8, MemoryError> {
+        Ok(self.peek_range(addr, 1)?[0])
+    }
+
+    /// Writes `byte` at `addr`, with the same bounds as `peek`.
+    pub fn poke(&mut self, addr: usize, byte: u8) -> Result<(), MemoryError> {
+        self.check_range(addr, 1)?;
+        self.memory.write(addr, byte);
+
+        Ok(())
+    }
+
+    /// Reads `len` bytes of ram starting at `start`.
+    pub fn peek_range(&self, start: usize, len: usize) -> Result<&[u8], MemoryError> {
+        self.check_range(start, len)?;
+        Ok(&self.memory.ram()[start..start + len])
+    }
+
+    /// The display as it is drawn, one byte per pixel in row-major order,
+    /// starting at the top left. Each pixel holds a bit for every plane.
+    pub fn framebuffer(&self) -> &[u8] {
+        self.graphics.display()
+    }
+
+    /// The display packed 8 pixels to a byte, in the same row-major order as
+    /// `framebuffer` with the leftmost pixel in the highest bit. A pixel is
+    /// set when it is lit on any plane. Both resolutions are a multiple of 8
+    /// wide, so every row starts on a byte of its own.
+    pub fn framebuffer_packed(&self) -> Vec<u8> {
+        self.framebuffer()
+            .chunks(8)
+            .map(|pixels| {
+                pixels.iter().fold(0, |byte, &pixel| (byte << 1) | (pixel != 0) as u8)
+            })
+            .collect()
+    }
+
+    /// Number of pixels the most recent sprite erased, VF only tells whether
+    /// any were.
+    pub fn last_collision_count(&self) -> usize {
+        self.graphics.last_collision_count()
+    }
+
+    /// Shows the emulation speed in the window title when it isn't normal,
+    /// and the frame rate and pause state when `status` holds the frame
+    /// rate. Meant to be called when something changes or about once a
+    /// second, not every frame.
+    pub fn show_status(&mut self, status: Option<f64>, speed: f64, paused: bool) {
+        let mut title = WINDOW_TITLE.to_string();
+        if let Some(fps) = status {
+            title.push_str(&format!(" - {:.0} fps", fps));
+        }
+        if speed != 1.0 {
+            title.push_str(&format!(" - {}x speed", speed));
+        }
+        if status.is_some() && paused {
+            title.push_str(" - paused");
+        }
+
+        self.graphics.set_title(&title);
+    }
+
+    /// Labels every keypad key with the keyboard key it is bound to.
+    pub fn keypad_labels(&self) -> [(usize, String); 16] {
+        self.input.keypad_labels()
+    }
+}
+
+impl Drop for Interconnect {
+    /// Closes the audio device before the window goes, so a beep can't keep
+    /// playing while the display is torn down. Headless instances have
+    /// neither, dropping them doesn't touch SDL.
+    fn drop(&mut self) {
+        self.sound.close();
+    }
+}
+
+impl Interconnect {
+    /// Checks that `len` bytes starting at `start` lie within ram.
+    fn check_range(&self, start: usize, len: usize) -> Result<(), MemoryError> {
+        let size = self.memory.size();
+        match start.checked_add(len) {
+            Some(end) if end <= size => Ok(()),
+            _ => Err(MemoryError::OutOfRange { start: start, len: len, size: size }),
         }
     }
 }
@@ -46,3 +211,157 @@ impl fmt::Debug for Interconnect {
         write!(f, "interconnect")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::*;
+    use super::super::memory::RAM_SIZE;
+    use super::super::sdl2::keyboard::Keycode;
+    use super::super::sound::BeepLog;
+
+    fn interconnect() -> Interconnect {
+        Interconnect::headless([0x12, 0x00], RAM_SIZE).unwrap()
+    }
+
+    #[test]
+    fn pokes_are_read_back_by_peeks() {
+        let mut interconnect = interconnect();
+
+        assert_eq!(interconnect.peek(0x200).unwrap(), 0x12);
+        interconnect.poke(0x300, 0xAB).unwrap();
+        interconnect.poke(RAM_SIZE - 1, 0xCD).unwrap();
+        assert_eq!(interconnect.peek(0x300).unwrap(), 0xAB);
+        assert_eq!(interconnect.peek(RAM_SIZE - 1).unwrap(), 0xCD);
+        assert_eq!(interconnect.peek_range(0x1FF, 3).unwrap(), &[0x00, 0x12, 0x00]);
+        assert_eq!(interconnect.peek_range(RAM_SIZE, 0).unwrap(), &[]);
+    }
+
+    #[test]
+    fn accesses_past_the_end_of_ram_are_out_of_range() {
+        let mut interconnect = interconnect();
+        let ram = interconnect.memory.ram().to_vec();
+
+        assert!(matches!(interconnect.peek(RAM_SIZE),
+                         Err(MemoryError::OutOfRange { start: RAM_SIZE, len: 1, size: RAM_SIZE })));
+        assert!(interconnect.poke(RAM_SIZE, 0xAB).is_err());
+        assert!(interconnect.peek_range(RAM_SIZE - 1, 2).is_err());
+        assert!(interconnect.peek_range(usize::MAX, 2).is_err());
+        assert_eq!(interconnect.memory.ram(), &ram[..]);
+    }
+
+    /// Writes `rom` to a file of its own in the temporary directory.
+    fn rom_file(name: &str, rom: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("notch-{}-{}.ch8", process::id(), name));
+        File::create(&path).unwrap().write_all(rom).unwrap();
+        path
+    }
+
+    #[test]
+    fn roms_load_from_files() {
+        let path = rom_file("valid", &[0x60, 0x2A, 0x12, 0x02]);
+        let interconnect = Interconnect::from_file(&path, RAM_SIZE).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(interconnect.memory.rom(), &[0x60, 0x2A, 0x12, 0x02]);
+        assert_eq!(interconnect.peek_range(0x200, 4).unwrap(), &[0x60, 0x2A, 0x12, 0x02]);
+    }
+
+    #[test]
+    fn every_constructor_loads_the_same_ram() {
+        let rom: Vec<u8> = (0..=255).collect();
+        let path = rom_file("constructors", &rom);
+        let from_file = Interconnect::from_file(&path, RAM_SIZE).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let owned = Interconnect::headless(rom.clone(), RAM_SIZE).unwrap();
+        let slice = Interconnect::headless(&rom[..], RAM_SIZE).unwrap();
+        let reader = Interconnect::from_reader(Cursor::new(rom.clone()), RAM_SIZE).unwrap();
+
+        assert_eq!(owned.memory.rom(), &rom[..]);
+        assert_eq!(slice.memory.ram(), owned.memory.ram());
+        assert_eq!(reader.memory.ram(), owned.memory.ram());
+        assert_eq!(from_file.memory.ram(), owned.memory.ram());
+    }
+
+    #[test]
+    fn missing_rom_files_are_unreadable() {
+        let path = env::temp_dir().join(format!("notch-{}-missing.ch8", process::id()));
+
+        match Interconnect::from_file(&path, RAM_SIZE) {
+            Err(RomError::Unreadable { why }) => assert_eq!(why.kind(), io::ErrorKind::NotFound),
+            other => panic!("expected an unreadable rom, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn oversized_rom_files_are_too_large() {
+        let path = rom_file("oversized", &[0; RAM_SIZE]);
+        let result = Interconnect::from_file(&path, RAM_SIZE);
+        fs::remove_file(&path).unwrap();
+
+        match result {
+            Err(RomError::TooLarge { size, max }) => {
+                assert_eq!((size, max), (RAM_SIZE, RAM_SIZE - 0x200));
+            },
+            other => panic!("expected a rom too large, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn packed_framebuffers_hold_eight_pixels_a_byte() {
+        let mut interconnect = interconnect();
+        interconnect.graphics.draw(4, 1, vec![0b1010_0101, 0b1111_0000]);
+
+        // Every row of 64 pixels takes 8 bytes.
+        let mut expected = vec![0; 64 * 32 / 8];
+        expected[8] = 0b0000_1010;
+        expected[9] = 0b0101_0000;
+        expected[16] = 0b0000_1111;
+        assert_eq!(interconnect.framebuffer_packed(), expected);
+        assert_eq!(interconnect.framebuffer()[64 + 4..64 + 12], [1, 0, 1, 0, 0, 1, 0, 1]);
+
+        interconnect.graphics.set_hires(true);
+        assert_eq!(interconnect.framebuffer_packed().len(), 128 * 64 / 8);
+    }
+
+    #[test]
+    fn dropping_stops_the_beep() {
+        let log = BeepLog::default();
+        let mut beeping = interconnect();
+        beeping.sound.add_listener(Box::new(log.clone()));
+        beeping.sound.start_beep();
+        drop(beeping);
+        assert_eq!(*log.0.borrow(), [true, false]);
+
+        // Quiet headless instances have nothing to shut down.
+        let log = BeepLog::default();
+        let mut interconnect = interconnect();
+        interconnect.sound.add_listener(Box::new(log.clone()));
+        drop(interconnect);
+        assert!(log.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn keypad_labels_follow_the_keymap() {
+        let mut interconnect = interconnect();
+        let labels = interconnect.keypad_labels();
+        assert_eq!(labels[0x0], (0x0, "X".to_string()));
+        assert_eq!(labels[0x5], (0x5, "W".to_string()));
+
+        interconnect.input.bind_key(0x5, Keycode::K);
+        let labels = interconnect.keypad_labels();
+        assert_eq!(labels[0x5], (0x5, "K".to_string()));
+
+        // Keys bound twice trade places.
+        interconnect.input.bind_key(0x0, Keycode::K);
+        let labels = interconnect.keypad_labels();
+        assert_eq!(labels[0x0], (0x0, "K".to_string()));
+        assert_eq!(labels[0x5], (0x5, "X".to_string()));
+    }
+}
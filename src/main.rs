@@ -6,18 +6,53 @@ extern crate time;
 
 use std::env;
 use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 
 use getopts::Options;
+use sdl2::controller::Button;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
 
+use changelog::Changelog;
+use config::Config;
+use flags::FlagStorage;
+use interconnect::Interconnect;
+use memory::{END_RESERVED, Memory, XO_RAM_SIZE};
+use platform::Platform;
+use providers::{ScriptedProvider, StreamProvider};
+use reference::ReferenceTrace;
+use replay::InputLog;
+use sound::StreamListener;
+use state::StateSlot;
+use timing::DEFAULT_CYCLES_PER_FRAME;
+use wav::WavRecorder;
+
+mod changelog;
+mod config;
 mod cpu;
+mod disasm;
+mod display;
+mod error;
+mod flags;
+mod gif;
 mod graphics;
 mod input;
+mod instruction;
 mod interconnect;
 mod memory;
+mod pacing;
+mod platform;
+mod png;
+mod providers;
+mod quirks;
+mod reference;
+mod replay;
 mod sound;
+mod state;
+mod timing;
 mod vm;
+mod wav;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -26,6 +61,109 @@ fn main() {
     let mut opts = Options::new();
     opts.optflag("v", "version", "print version information");
     opts.optflag("h", "help", "Print this message");
+    opts.optflag("k", "show-keys", "print the keypad bindings at startup");
+    opts.optmulti("", "bind", "bind keyboard key NAME to keypad key KEY, for \
+                               example --bind 5=Space, can be repeated", "KEY=NAME");
+    opts.optmulti("", "bind-button", "bind controller BUTTON to keypad key \
+                                      KEY, for example --bind-button 5=start", "KEY=BUTTON");
+    opts.optflag("d", "disassemble", "print the rom's instructions and exit");
+    opts.optopt("s", "scale", "draw every pixel as an N by N square \
+                               (default 10)", "N");
+    opts.optflag("", "fullscreen", "start covering the whole screen, F11 \
+                                    switches back to a window");
+    opts.optopt("", "fps", "draw at most N frames a second, from 1 to \
+                            60 (the default)", "N");
+    opts.optflag("", "title-status", "show the frame rate, speed and pause \
+                                      state in the window title");
+    opts.optopt("", "screenshot-scale", "draw every pixel of F12 screenshots \
+                                         as an N by N square (default 1)", "N");
+    opts.optopt("", "foreground", "draw pixels in COLOR, for example 33ff33 \
+                                   for green phosphor", "RRGGBB");
+    opts.optopt("", "background", "fill the background with COLOR", "RRGGBB");
+    opts.optflag("", "headless", "run without a window or audio, keys only \
+                                  come from --stdin-keys or --input-script");
+    opts.optflag("", "terminal", "draw the display in the terminal instead of \
+                                  a window, keys only come from --stdin-keys or \
+                                  --input-script");
+    opts.optopt("w", "warp", "begin execution at ADDR instead of 0x200", "ADDR");
+    opts.optmulti("", "poke", "write BYTE to ram at ADDR before starting, \
+                               repeatable", "ADDR=BYTE");
+    opts.optopt("a", "record-audio", "record the beeper to a WAV file", "FILE");
+    opts.optopt("", "beep-frequency", "beep at HZ (default 440)", "HZ");
+    opts.optopt("", "volume", "beep at N percent of full loudness, from 0 to \
+                               100 (default 25)", "N");
+    opts.optopt("", "record-gif", "record what is shown to an animated GIF, \
+                                   at most two minutes long", "FILE");
+    opts.optopt("", "reference", "stop at the first step that differs from \
+                                  the trace in FILE", "FILE");
+    opts.optflag("", "debug", "start paused, F10 executes one instruction \
+                               and prints the cpu state, P resumes");
+    opts.optmulti("b", "break", "pause before executing the instruction at \
+                                 ADDR, can be repeated (F8 toggles one at \
+                                 PC while paused)", "ADDR");
+    opts.optflagopt("", "trace", "log every executed instruction to stderr, \
+                                  or to FILE when given", "FILE");
+    opts.optopt("", "load-state", "start from the save state in FILE", "FILE");
+    opts.optopt("", "save-state", "write a save state to FILE on exit", "FILE");
+    opts.optopt("", "dump-display", "write the display to FILE on exit, 8 \
+                                     pixels to a byte row by row", "FILE");
+    opts.optopt("", "save-dir", "keep the F6/F9 quicksave in DIR instead of \
+                                 next to the rom", "DIR");
+    opts.optopt("", "rewind-frames", "keep N frames to rewind through by \
+                                      holding backspace, 0 disables rewinding \
+                                      (default 600)", "N");
+    opts.optflag("f", "persist-flags", "keep SUPER-CHIP flags in ROM.flags");
+    opts.optopt("", "changelog", "write the state changed by every \
+                                  instruction to FILE", "FILE");
+    opts.optopt("", "fill-registers", "start V0-VF at BYTE instead of 0", "BYTE");
+    opts.optopt("", "seed", "seed the random number generator with N for \
+                             reproducible runs", "N");
+    opts.optopt("", "max-instructions", "stop after executing N instructions, \
+                                         for runs that have to end", "N");
+    opts.optopt("", "platform", "set up the instructions and quirks of the \
+                                 platform the rom was written for, auto \
+                                 guesses it from the rom", "auto|chip8|chip8x|schip|xochip");
+    opts.optflag("", "chip8x", "enable the Chip-8X color instructions");
+    opts.optflag("", "schip", "enable the Super-CHIP instructions");
+    opts.optflag("", "xochip", "enable the XO-CHIP long load, drawing planes \
+                                and 64kB of ram");
+    opts.optopt("", "quirks", "behave like the COSMAC VIP, Super-CHIP or a \
+                               modern interpreter (default modern), the \
+                               quirk flags below adjust the preset", "vip|schip|modern");
+    opts.optflag("", "vf-reset", "reset VF after 8XY1, 8XY2 and 8XY3");
+    opts.optflag("", "shift-vy", "shift VY into VX with 8XY6 and 8XYE");
+    opts.optflag("", "jump-vx", "jump to NNN plus VX with BNNN");
+    opts.optflag("", "i-overflow-flag", "set VF when FX1E pushes I past 0xFFF");
+    opts.optflag("", "increment-i", "advance I past the registers FX55 and \
+                                     FX65 transfer");
+    opts.optflag("", "pad-sprites", "read sprite bytes past the end of ram \
+                                     as zero instead of failing");
+    opts.optflag("", "mirror-ram", "wrap accesses past the end of ram");
+    opts.optflag("", "clip-sprites", "cut sprites off at the display edges \
+                                      instead of wrapping them");
+    opts.optflag("", "stdin-keys", "read 'down KEY' and 'up KEY' lines from \
+                                    stdin as keypad input");
+    opts.optflag("", "stdout-beeps", "write a 'start' or 'stop' line to \
+                                      stdout whenever the beep changes");
+    opts.optopt("", "input-script", "hold keys as listed in FILE, one \
+                                     'FRAME KEY DURATION' per line", "FILE");
+    opts.optopt("", "record-input", "write the keys held during every frame \
+                                     to FILE on exit", "FILE");
+    opts.optopt("", "replay-input", "play back the keys recorded in FILE \
+                                     instead of live input", "FILE");
+    opts.optopt("", "timing", "emulate the instruction speed of a COSMAC \
+                               VIP, CHIP-48 or a fast modern interpreter", "vip|chip48|fast");
+    opts.optopt("", "clock", "execute HZ instructions per second instead of \
+                              following a timing profile (500-1000 suits \
+                              most games)", "HZ");
+    opts.optflagopt("", "cycles-per-frame", "execute N instructions every \
+                                             60Hz frame instead of following \
+                                             a timing profile (default 10)", "N");
+    opts.optflag("", "turbo", "run as fast as possible without drawing, \
+                               the timers count instruction time instead \
+                               of wall clock time");
+    opts.optopt("", "pacing", "wait between instructions by sleeping, \
+                               spinning or both (default hybrid)", "sleep|busy|hybrid");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(f) => {
@@ -53,12 +191,421 @@ fn main() {
         std::process::exit(1);
     };
 
-    let rom = read_bin(&rom_file_name);
+    // Disassemble the rom the way it is loaded into ram, so a rom that
+    // can't be run is reported here as well. A rom named `-` is read from
+    // standard input.
+    if matches.opt_present("d") {
+        let loaded = if rom_file_name == "-" {
+            Interconnect::from_reader(io::stdin(), XO_RAM_SIZE)
+        } else {
+            Interconnect::from_file(&rom_file_name, XO_RAM_SIZE)
+        };
+        match loaded {
+            Ok(interconnect) => print_disassembly(interconnect.memory.rom()),
+            Err(why) => {
+                println!("notch: {}: {}", rom_file_name, why);
+                std::process::exit(2);
+            },
+        }
+        return;
+    }
+
+    let rom = match Memory::read_rom_file(&rom_file_name) {
+        Ok(rom) => rom,
+        Err(why) => {
+            println!("notch: {}: {}", rom_file_name, why);
+            std::process::exit(2);
+        },
+    };
     println!("Loading rom: {}", rom_file_name);
 
+    let mut config = Config {
+        headless: matches.opt_present("headless"),
+        terminal: matches.opt_present("terminal"),
+        fullscreen: matches.opt_present("fullscreen"),
+        title_status: matches.opt_present("title-status"),
+        foreground: matches.opt_str("foreground").map(|color| parse_color_or_exit(&color)),
+        background: matches.opt_str("background").map(|color| parse_color_or_exit(&color)),
+        ..Default::default()
+    };
+    if let Some(scale) = matches.opt_str("s") {
+        config.scale = match scale.parse() {
+            Ok(scale) if scale >= 1 => Some(scale),
+            _ => {
+                println!("notch: invalid scale '{}', must be at least 1", scale);
+                std::process::exit(1);
+            },
+        };
+    }
+    if let Some(fps) = matches.opt_str("fps") {
+        config.fps = match fps.parse() {
+            Ok(fps) if (1..=60).contains(&fps) => Some(fps),
+            _ => {
+                println!("notch: invalid frame rate '{}', must be 1-60", fps);
+                std::process::exit(1);
+            },
+        };
+    }
+    if let Some(scale) = matches.opt_str("screenshot-scale") {
+        config.screenshot_scale = match scale.parse() {
+            Ok(scale) if scale >= 1 => Some(scale),
+            _ => {
+                println!("notch: invalid screenshot scale '{}', must be at least 1", scale);
+                std::process::exit(1);
+            },
+        };
+    }
+    if let Some(frequency) = matches.opt_str("beep-frequency") {
+        config.beep_frequency = match frequency.parse() {
+            Ok(frequency) if frequency > 0.0 => Some(frequency),
+            _ => {
+                println!("notch: invalid beep frequency '{}', must be above 0", frequency);
+                std::process::exit(1);
+            },
+        };
+    }
+    if let Some(volume) = matches.opt_str("volume") {
+        config.volume = match volume.parse::<u8>() {
+            Ok(volume) if volume <= 100 => Some(volume as f32 / 100.0),
+            _ => {
+                println!("notch: invalid volume '{}', must be 0-100", volume);
+                std::process::exit(1);
+            },
+        };
+    }
+    if let Some(path) = matches.opt_str("a") {
+        config.audio_recorder = match WavRecorder::create(&path) {
+            Ok(recorder) => Some(recorder),
+            Err(why) => {
+                println!("notch: cannot create '{}': {}", path, why);
+                std::process::exit(2);
+            },
+        };
+    }
+
+    if let Some(path) = matches.opt_str("changelog") {
+        config.changelog = match Changelog::create(&path) {
+            Ok(changelog) => Some(changelog),
+            Err(why) => {
+                println!("notch: cannot create '{}': {}", path, why);
+                std::process::exit(2);
+            },
+        };
+    }
+
+    if let Some(path) = matches.opt_str("reference") {
+        let trace = String::from_utf8_lossy(&read_bin(&path)).into_owned();
+        config.reference = match ReferenceTrace::parse(&trace) {
+            Ok(reference) => Some(reference),
+            Err(why) => {
+                println!("notch: {}: {}", path, why);
+                std::process::exit(1);
+            },
+        };
+    }
+
+    if matches.opt_present("trace") {
+        config.trace = match matches.opt_str("trace") {
+            Some(path) => match File::create(&path) {
+                Ok(file) => Some(Box::new(BufWriter::new(file))),
+                Err(why) => {
+                    println!("notch: cannot create '{}': {}", path, why);
+                    std::process::exit(2);
+                },
+            },
+            None => Some(Box::new(io::stderr())),
+        };
+    }
+
+    // Every rom has its own quicksave slot, named after the rom.
+    let slot_name = format!("{}.state", Path::new(&rom_file_name)
+        .file_name()
+        .map_or("rom".into(), |name| name.to_string_lossy()));
+    let slot_path = match matches.opt_str("save-dir") {
+        Some(dir) => Path::new(&dir).join(slot_name),
+        None => Path::new(&rom_file_name).with_file_name(slot_name),
+    };
+    config.quicksave_slot = Some(StateSlot::new(slot_path));
+
+    if let Some(frames) = matches.opt_str("rewind-frames") {
+        config.rewind_frames = match frames.parse() {
+            Ok(frames) => Some(frames),
+            Err(_) => {
+                println!("notch: invalid rewind frame count '{}'", frames);
+                std::process::exit(1);
+            },
+        };
+    }
+
+    if matches.opt_present("f") {
+        let path = PathBuf::from(format!("{}.flags", rom_file_name));
+        config.flag_storage = Some(FlagStorage::new(path));
+    }
+
+    if let Some(fill) = matches.opt_str("fill-registers") {
+        config.register_fill = match parse_hex(&fill) {
+            Some(byte) if byte <= 0xFF => byte as u8,
+            _ => {
+                println!("notch: invalid register fill '{}'", fill);
+                std::process::exit(1);
+            },
+        };
+    }
+
+    if let Some(seed) = matches.opt_str("seed") {
+        config.seed = match seed.parse() {
+            Ok(seed) => Some(seed),
+            Err(_) => {
+                println!("notch: invalid seed '{}'", seed);
+                std::process::exit(1);
+            },
+        };
+    }
+
+    let max_instructions = match matches.opt_str("max-instructions") {
+        Some(count) => match count.parse() {
+            Ok(count) => Some(count),
+            Err(_) => {
+                println!("notch: invalid instruction count '{}'", count);
+                std::process::exit(1);
+            },
+        },
+        None => None,
+    };
+
+    config.debug = matches.opt_present("debug");
+    for addr in matches.opt_strs("b") {
+        match parse_hex(&addr) {
+            Some(addr) => config.breakpoints.push(addr),
+            None => {
+                println!("notch: invalid breakpoint address '{}'", addr);
+                std::process::exit(1);
+            },
+        }
+    }
+    // The platform only picks defaults, the flags below still override it.
+    if let Some(name) = matches.opt_str("platform") {
+        let platform = if name == "auto" {
+            let platform = platform::detect_platform(&rom);
+            println!("Detected platform: {}", platform);
+            platform
+        } else {
+            match name.parse() {
+                Ok(platform) => platform,
+                Err(why) => {
+                    println!("notch: {}", why);
+                    std::process::exit(1);
+                },
+            }
+        };
+        if platform == Platform::XoChip {
+            println!("notch: only the long load and planes of the XO-CHIP \
+                      instructions are supported");
+        }
+
+        let extensions = platform.extensions();
+        config.chip8x = extensions.chip8x;
+        config.schip = extensions.schip;
+        config.xochip = extensions.xochip;
+        config.quirks = platform.quirks();
+    }
+    config.chip8x |= matches.opt_present("chip8x");
+    config.schip |= matches.opt_present("schip");
+    config.xochip |= matches.opt_present("xochip");
+    if let Some(quirks) = matches.opt_str("quirks") {
+        config.quirks = match quirks.parse() {
+            Ok(quirks) => quirks,
+            Err(why) => {
+                println!("notch: {}", why);
+                std::process::exit(1);
+            },
+        };
+    }
+    config.quirks.vf_reset |= matches.opt_present("vf-reset");
+    config.quirks.shift_vy |= matches.opt_present("shift-vy");
+    config.quirks.jump_vx |= matches.opt_present("jump-vx");
+    config.quirks.i_overflow_flag |= matches.opt_present("i-overflow-flag");
+    config.quirks.increment_i |= matches.opt_present("increment-i");
+    config.quirks.clip_sprites |= matches.opt_present("clip-sprites");
+    config.quirks.pad_sprites |= matches.opt_present("pad-sprites");
+    config.mirror_ram = matches.opt_present("mirror-ram");
+    if let Some(timing) = matches.opt_str("timing") {
+        config.timing = match timing.parse() {
+            Ok(timing) => timing,
+            Err(why) => {
+                println!("notch: {}", why);
+                std::process::exit(1);
+            },
+        };
+    }
+    if let Some(clock) = matches.opt_str("clock") {
+        config.clock_hz = match clock.parse() {
+            Ok(hz) if hz > 0 => Some(hz),
+            _ => {
+                println!("notch: invalid clock rate '{}'", clock);
+                std::process::exit(1);
+            },
+        };
+    }
+    if matches.opt_present("cycles-per-frame") {
+        config.cycles_per_frame = match matches.opt_str("cycles-per-frame") {
+            Some(cycles) => match cycles.parse() {
+                Ok(cycles) if cycles > 0 => Some(cycles),
+                _ => {
+                    println!("notch: invalid cycles per frame '{}'", cycles);
+                    std::process::exit(1);
+                },
+            },
+            None => Some(DEFAULT_CYCLES_PER_FRAME),
+        };
+    }
+    config.turbo = matches.opt_present("turbo");
+    if let Some(pacing) = matches.opt_str("pacing") {
+        config.pacing = match pacing.parse() {
+            Ok(pacing) => pacing,
+            Err(why) => {
+                println!("notch: {}", why);
+                std::process::exit(1);
+            },
+        };
+    }
+
+    for binding in matches.opt_strs("bind") {
+        let (key, name) = parse_binding_or_exit(&binding);
+        match Keycode::from_name(name) {
+            Some(keycode) => config.key_bindings.push((key, keycode)),
+            None => {
+                println!("notch: unknown key '{}'", name);
+                std::process::exit(1);
+            },
+        }
+    }
+    for binding in matches.opt_strs("bind-button") {
+        let (key, name) = parse_binding_or_exit(&binding);
+        match Button::from_string(name) {
+            Some(button) => config.button_bindings.push((key, button)),
+            None => {
+                println!("notch: unknown controller button '{}'", name);
+                std::process::exit(1);
+            },
+        }
+    }
+
+    if matches.opt_present("stdin-keys") {
+        let provider = StreamProvider::spawn(std::io::stdin());
+        config.input_providers.push(Box::new(provider));
+    }
+    if matches.opt_present("stdout-beeps") {
+        let listener = StreamListener::new(Box::new(io::stdout()));
+        config.beep_listeners.push(Box::new(listener));
+    }
+
+    if let Some(path) = matches.opt_str("input-script") {
+        let script = String::from_utf8_lossy(&read_bin(&path)).into_owned();
+        let provider = match ScriptedProvider::parse(&script) {
+            Ok(provider) => provider,
+            Err(why) => {
+                println!("notch: {}: {}", path, why);
+                std::process::exit(1);
+            },
+        };
+        config.input_providers.push(Box::new(provider));
+    }
+
+    config.record_input = matches.opt_present("record-input");
+    if let Some(path) = matches.opt_str("replay-input") {
+        config.input_replay = match InputLog::decode(&read_bin(&path)) {
+            Ok(log) => Some(log),
+            Err(why) => {
+                println!("notch: {}: {}", path, why);
+                std::process::exit(1);
+            },
+        };
+    }
+
     // Initialize the virtual machine and boot the rom.
-    let mut vm = vm::VirtualMachine::new(rom);
-    vm.run();
+    let mut vm = match vm::VirtualMachine::new(rom, config) {
+        Ok(vm) => vm,
+        Err(why) => {
+            println!("notch: {}: {}", rom_file_name, why);
+            std::process::exit(1);
+        },
+    };
+
+    if let Some(path) = matches.opt_str("record-gif") {
+        if let Err(why) = vm.start_gif(&path) {
+            println!("notch: cannot create '{}': {}", path, why);
+            std::process::exit(2);
+        }
+    }
+
+    // Jump straight to a routine when requested, useful when exploring roms.
+    if let Some(warp) = matches.opt_str("w") {
+        let addr = match parse_hex(&warp) {
+            Some(addr) => addr,
+            None => {
+                println!("notch: invalid address '{}'", warp);
+                std::process::exit(1);
+            },
+        };
+        if let Err(why) = vm.warp_to(addr) {
+            println!("notch: {}", why);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(path) = matches.opt_str("load-state") {
+        if let Err(why) = vm.load_state(&read_bin(&path)) {
+            println!("notch: {}: {}", path, why);
+            std::process::exit(1);
+        }
+    }
+
+    // Patch ram after the rom or save state is loaded, for trying out
+    // changes to a rom.
+    for poke in matches.opt_strs("poke") {
+        let (addr, byte) = parse_poke_or_exit(&poke);
+        if let Err(why) = vm.poke(addr, byte) {
+            println!("notch: {}", why);
+            std::process::exit(1);
+        }
+    }
+
+    if matches.opt_present("k") {
+        vm.print_keys();
+    }
+
+    let result = match max_instructions {
+        Some(cycles) => vm.run_for(cycles).map(|(_, stopped)| stopped),
+        None => vm.run(),
+    };
+
+    if let Some(path) = matches.opt_str("record-input") {
+        if let Err(why) = write_bin(&path, &vm.stop_recording().encode()) {
+            println!("notch: cannot write '{}': {}", path, why);
+            std::process::exit(2);
+        }
+    }
+
+    // Save even when execution failed, the state shows what went wrong.
+    if let Some(path) = matches.opt_str("save-state") {
+        if let Err(why) = write_bin(&path, &vm.save_state()) {
+            println!("notch: cannot write '{}': {}", path, why);
+            std::process::exit(2);
+        }
+    }
+
+    if let Some(path) = matches.opt_str("dump-display") {
+        if let Err(why) = write_bin(&path, &vm.framebuffer_packed()) {
+            println!("notch: cannot write '{}': {}", path, why);
+            std::process::exit(2);
+        }
+    }
+
+    if let Err(why) = result {
+        println!("notch: {}", why);
+        std::process::exit(1);
+    }
 }
 
 /// Reads a file into a vector of unsigned bytes.
@@ -80,6 +627,75 @@ fn read_bin<P: AsRef<Path>>(path: P) -> Vec<u8> {
     buffer
 }
 
+/// Writes a vector of bytes to a file, replacing it if it exists.
+fn write_bin<P: AsRef<Path>>(path: P, bytes: &[u8]) -> io::Result<()> {
+    File::create(path)?.write_all(bytes)
+}
+
+/// Parses a hexadecimal number with an optional "0x" prefix.
+fn parse_hex(hex: &str) -> Option<u16> {
+    let digits = hex.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Parses a color written as six hexadecimal digits, RRGGBB, exiting when it
+/// is malformed.
+fn parse_color_or_exit(hex: &str) -> Color {
+    let digits = hex.trim_start_matches('#');
+    match u32::from_str_radix(digits, 16) {
+        Ok(rgb) if digits.len() == 6 => {
+            Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+        },
+        _ => {
+            println!("notch: invalid color '{}', expected RRGGBB", hex);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Prints every instruction of a rom next to the address it is loaded at and
+/// its raw bytes.
+fn print_disassembly(rom: &[u8]) {
+    for (offset, len, line) in disasm::disassemble(rom, 0, rom.len()) {
+        let bytes: String = rom[offset..offset + len]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        println!("{:#06x}  {:<8}  {}", END_RESERVED + offset, bytes, line);
+    }
+}
+
+/// Splits a binding written as KEY=NAME into the hexadecimal keypad key and
+/// the name of what is bound to it, exiting when it is malformed.
+fn parse_binding_or_exit(binding: &str) -> (u8, &str) {
+    let mut parts = binding.splitn(2, '=');
+    let key = parts.next().and_then(|key| u8::from_str_radix(key, 16).ok());
+
+    match (key, parts.next()) {
+        (Some(key), Some(name)) if key <= 0xF => (key, name),
+        _ => {
+            println!("notch: invalid key binding '{}', expected KEY=NAME", binding);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Parses a ram patch written as ADDR=BYTE, both hexadecimal, exiting when
+/// it is malformed.
+fn parse_poke_or_exit(poke: &str) -> (usize, u8) {
+    let mut parts = poke.splitn(2, '=');
+    let addr = parts.next().and_then(parse_hex);
+    let byte = parts.next().and_then(parse_hex);
+
+    match (addr, byte) {
+        (Some(addr), Some(byte)) if byte <= 0xFF => (addr as usize, byte as u8),
+        _ => {
+            println!("notch: invalid poke '{}', expected ADDR=BYTE", poke);
+            std::process::exit(1);
+        },
+    }
+}
+
 /// Prints the application name alongside the cargo version.
 fn print_version() {
     println!("notch {}", env!("CARGO_PKG_VERSION"));
@@ -1,62 +1,146 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
 use super::byteorder::{BigEndian, ByteOrder};
 
+use super::error::RomError;
+
 // Size of the memory map of a CHIP-8 interpreter is 4kB.
 pub const RAM_SIZE: usize = 4096;
 
+// XO-CHIP interpreters have 64kB, all of it addressable by I.
+pub const XO_RAM_SIZE: usize = 0x10000;
+
 // Memory map constraints for CHIP-8 virtual machines.
 pub const END_RESERVED: usize = 0x200;
+pub const END_PROGRAM_SPACE: usize = 0xFFF;
 
 // Here are some additional unused constrains for documentation purposes. These
 // are defined in the CHIP-8 spec but are not referenced in code for this
 // specific implementation.
 //
 //     pub const START_RESERVED: usize = 0x000;
-//     pub const END_PROGRAM_SPACE: usize = 0xFFF;
 
 // Font size constants.
-const CHARACTER_SIZE: usize = 5;
+pub const CHARACTER_SIZE: usize = 5;
 const CHARACTER_COUNT: usize = 16;
 
 // Where fonts are stored in interpreter memory.
-const FONT_OFFSET: usize = 0;
+pub const FONT_OFFSET: usize = 0;
 
 pub struct Memory {
     // Memory allocated for the running CHIP-8 application.
     ram: Vec<u8>,
+
+    // Wrap accesses past the end of ram back to the start instead of failing.
+    mirror: bool,
+
+    // Length of the rom loaded at the start of program space.
+    rom_len: usize,
 }
 
 impl Memory {
-    /// Allocate some memory for ram and load a rom into it.
-    pub fn new(rom: Vec<u8>) -> Memory {
-        // Allocate 4kB of memory as defined in the many specifications.
-        let mut ram = vec![0; RAM_SIZE];
+    /// Allocate `size` bytes of ram, RAM_SIZE or XO_RAM_SIZE for XO-CHIP
+    /// roms, and load a rom into it. Fails if the rom is empty or doesn't fit
+    /// in ram past reserved space. The rom is copied into ram, so a borrowed
+    /// slice works as well as an owned vector.
+    pub fn new<R: AsRef<[u8]>>(rom: R, size: usize) -> Result<Memory, RomError> {
+        let rom = rom.as_ref();
+        let max = size - END_RESERVED;
+        if rom.is_empty() {
+            return Err(RomError::Empty);
+        }
+        if rom.len() > max {
+            return Err(RomError::TooLarge { size: rom.len(), max: max });
+        }
 
-        Memory::dump_rom(&mut ram, &rom);
+        let mut ram = vec![0; size];
+
+        Memory::dump_rom(&mut ram, rom);
         Memory::dump_fonts(&mut ram);
 
-        Memory {
+        Ok(Memory {
             ram: ram,
+            mirror: false,
+            rom_len: rom.len(),
+        })
+    }
+
+    /// Writes the fonts back into ram, undoing anything a rom wrote over them.
+    pub fn reload_fonts(&mut self) {
+        Memory::dump_fonts(&mut self.ram);
+    }
+
+    /// Enables or disables ram mirroring. Some interpreters wrap accesses
+    /// past the end of ram around to the start and a few programs rely on it.
+    pub fn set_mirroring(&mut self, mirror: bool) {
+        self.mirror = mirror;
+    }
+
+    /// The number of bytes of ram.
+    pub fn size(&self) -> usize {
+        self.ram.len()
+    }
+
+    /// The part of ram the rom was loaded into, as it is now.
+    pub fn rom(&self) -> &[u8] {
+        &self.ram[END_RESERVED..END_RESERVED + self.rom_len]
+    }
+
+    /// The entire contents of ram.
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Reads a rom to its end, from a file or any other reader, so it can be
+    /// passed on to `new`.
+    pub fn read_rom<R: Read>(mut reader: R) -> Result<Vec<u8>, RomError> {
+        let mut rom = Vec::new();
+        match reader.read_to_end(&mut rom) {
+            Ok(_) => Ok(rom),
+            Err(why) => Err(RomError::Unreadable { why: why }),
+        }
+    }
+
+    /// Reads the rom stored in a file. Failing to open the file is reported
+    /// the same way as failing to read it, a rom too large for ram is only
+    /// caught once it is passed on to `new`.
+    pub fn read_rom_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, RomError> {
+        match File::open(path) {
+            Ok(file) => Memory::read_rom(file),
+            Err(why) => Err(RomError::Unreadable { why: why }),
         }
     }
 
+    /// Replaces the entire contents of ram, which must be exactly `size`
+    /// bytes.
+    pub fn load_ram(&mut self, ram: &[u8]) {
+        self.ram.copy_from_slice(ram);
+    }
+
     /// Simply returns an 8-bit word at the specified address.
     #[inline(always)]
     pub fn read(&self, addr: usize) -> u8 {
-        self.ram[addr]
+        self.ram[self.map(addr)]
     }
 
     /// Write an 8-bit byte at a specific address. There is no concern over
     /// endianess since this function operates on single bytes.
     #[inline(always)]
     pub fn write(&mut self, addr: usize, byte: u8) {
+        let addr = self.map(addr);
         self.ram[addr] = byte;
     }
 
     /// Reads a 16-bit word from ram. This function is used mainly to read and
-    /// execute instructions as their word size is 16 bits.
+    /// execute instructions as their word size is 16 bits. Like `read` it
+    /// panics past the end of ram without mirroring, check with `contains`
+    /// first.
     #[inline(always)]
     pub fn read_word(&self, addr: u16) -> u16 {
-        BigEndian::read_u16(&self.ram[addr as usize..])
+        let addr = addr as usize;
+        BigEndian::read_u16(&[self.read(addr), self.read(addr + 1)])
     }
 
     /// Find the memory address of the requested character.
@@ -65,9 +149,29 @@ impl Memory {
         FONT_OFFSET as u16 + font as u16 * CHARACTER_SIZE as u16
     }
 
+    /// True if `len` bytes starting at `addr` can be accessed. Any range is
+    /// accessible when mirroring is enabled.
+    pub fn contains(&self, addr: usize, len: usize) -> bool {
+        self.mirror || addr + len <= self.ram.len()
+    }
+
+    /// Translates an address into an index into ram, wrapping it when
+    /// mirroring is enabled.
+    #[inline(always)]
+    fn map(&self, addr: usize) -> usize {
+        let size = self.ram.len();
+        if self.mirror {
+            addr % size
+        } else if addr >= size {
+            panic!("Memory access out of range: {:#x}, ram ends at {:#x}", addr, size - 1);
+        } else {
+            addr
+        }
+    }
+
     /// Dumps a passed rom containing executable code into ram starting at
     /// program space (right after reserved space ends).
-    fn dump_rom(ram: &mut Vec<u8>, rom: &Vec<u8>) {
+    fn dump_rom(ram: &mut Vec<u8>, rom: &[u8]) {
         // Dump the rom containing the executable code of the program into ram
         // starting at the start of the program space.
         for i in 0..rom.len() {
@@ -114,3 +218,64 @@ impl Memory {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn roms_filling_program_space_load() {
+        let rom = vec![0xAB; RAM_SIZE - END_RESERVED];
+        let memory = Memory::new(&rom, RAM_SIZE).unwrap();
+        assert_eq!(&memory.ram()[END_RESERVED..], &rom[..]);
+    }
+
+    #[test]
+    fn oversized_roms_are_rejected() {
+        let rom = vec![0; RAM_SIZE - END_RESERVED + 1];
+        match Memory::new(&rom, RAM_SIZE) {
+            Err(RomError::TooLarge { size, max }) => {
+                assert_eq!((size, max), (rom.len(), RAM_SIZE - END_RESERVED));
+            },
+            other => panic!("expected a rom too large, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn empty_roms_are_rejected() {
+        match Memory::new([], RAM_SIZE) {
+            Err(RomError::Empty) => {},
+            other => panic!("expected an empty rom, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn every_way_of_passing_a_rom_fills_ram_the_same() {
+        let rom: Vec<u8> = (0..=255).collect();
+        let owned = Memory::new(rom.clone(), RAM_SIZE).unwrap();
+        let slice = Memory::new(&rom[..], RAM_SIZE).unwrap();
+        let read = Memory::read_rom(Cursor::new(rom.clone())).unwrap();
+        let reader = Memory::new(read, RAM_SIZE).unwrap();
+
+        assert_eq!(owned.ram(), slice.ram());
+        assert_eq!(owned.ram(), reader.ram());
+        assert_eq!(&owned.ram()[END_RESERVED..END_RESERVED + 256], &rom[..]);
+    }
+
+    #[test]
+    fn mirroring_wraps_accesses_past_the_end_of_ram() {
+        let mut memory = Memory::new([0x12, 0x34], RAM_SIZE).unwrap();
+        assert!(!memory.contains(RAM_SIZE + END_RESERVED, 1));
+        assert!(!memory.contains(RAM_SIZE + 0x300, 1));
+
+        memory.set_mirroring(true);
+        assert!(memory.contains(RAM_SIZE + END_RESERVED, 1));
+        assert_eq!(memory.read(RAM_SIZE + END_RESERVED), 0x12);
+        assert_eq!(memory.read_word((RAM_SIZE + END_RESERVED) as u16), 0x1234);
+
+        memory.write(RAM_SIZE + 0x300, 0xAB);
+        assert_eq!(memory.read(0x300), 0xAB);
+    }
+}
@@ -0,0 +1,157 @@
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::Duration;
+
+use super::time;
+
+// Hybrid pacing stops sleeping this many nanoseconds before the deadline and
+// spins the rest of the way, since sleeps tend to overshoot by about that much.
+const SPIN_MARGIN: u64 = 250_000;
+
+/// How the execution loop waits between instructions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Pacing {
+    // Sleep until the deadline. Cheapest, but sleeps can overshoot on some
+    // platforms which causes jitter.
+    Sleep,
+
+    // Sleep most of the way and spin through the last stretch.
+    #[default]
+    Hybrid,
+
+    // Spin until the deadline. Most precise but keeps a core busy.
+    BusyWait,
+}
+
+/// The time source pacing waits on, the system clock outside of tests.
+pub trait Clock {
+    /// The current time in nanoseconds.
+    fn now(&mut self) -> u64;
+
+    /// Blocks for about the passed number of nanoseconds.
+    fn sleep(&mut self, ns: u64);
+}
+
+/// The wall clock, on the same time base as `time::precise_time_ns`.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&mut self) -> u64 {
+        time::precise_time_ns()
+    }
+
+    fn sleep(&mut self, ns: u64) {
+        sleep(Duration::from_nanos(ns));
+    }
+}
+
+impl Pacing {
+    /// Blocks until the deadline passes. Deadlines are in nanoseconds on the
+    /// time base of the passed clock.
+    pub fn wait_until<C: Clock>(&self, clock: &mut C, deadline: u64) {
+        match *self {
+            Pacing::Sleep => sleep_until(clock, deadline, 0),
+            Pacing::Hybrid => {
+                sleep_until(clock, deadline, SPIN_MARGIN);
+                spin_until(clock, deadline);
+            },
+            Pacing::BusyWait => spin_until(clock, deadline),
+        }
+    }
+}
+
+impl FromStr for Pacing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Pacing, String> {
+        match s {
+            "sleep" => Ok(Pacing::Sleep),
+            "hybrid" => Ok(Pacing::Hybrid),
+            "busy" => Ok(Pacing::BusyWait),
+            _ => Err(format!("unknown pacing '{}', expected sleep, hybrid or busy", s)),
+        }
+    }
+}
+
+/// Sleeps until `margin` nanoseconds before the deadline, if there is that
+/// much time left.
+fn sleep_until<C: Clock>(clock: &mut C, deadline: u64, margin: u64) {
+    let now = clock.now();
+    if deadline > now + margin {
+        clock.sleep(deadline - now - margin);
+    }
+}
+
+fn spin_until<C: Clock>(clock: &mut C, deadline: u64) {
+    while clock.now() < deadline {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sleeps on the fake clock overshoot by this much, like real sleeps do.
+    const OVERSHOOT: u64 = 200_000;
+
+    // Every reading of the fake clock moves it forward this much, the time
+    // a spin takes.
+    const SPIN_STEP: u64 = 1_000;
+
+    /// A clock that only moves when it is read or slept on.
+    struct FakeClock {
+        now: u64,
+        sleeps: usize,
+    }
+
+    impl Clock for FakeClock {
+        fn now(&mut self) -> u64 {
+            self.now += SPIN_STEP;
+            self.now
+        }
+
+        fn sleep(&mut self, ns: u64) {
+            self.now += ns + OVERSHOOT;
+            self.sleeps += 1;
+        }
+    }
+
+    /// Waits for a deadline 1ms away and returns how late the wait ended
+    /// and how many times it slept.
+    fn wait(pacing: Pacing) -> (u64, usize) {
+        let mut clock = FakeClock { now: 0, sleeps: 0 };
+        let deadline = 1_000_000;
+        pacing.wait_until(&mut clock, deadline);
+
+        assert!(clock.now >= deadline);
+        (clock.now - deadline, clock.sleeps)
+    }
+
+    #[test]
+    fn sleep_reaches_the_deadline_with_the_overshoot() {
+        let (late, sleeps) = wait(Pacing::Sleep);
+        assert_eq!(sleeps, 1);
+        assert_eq!(late, OVERSHOOT);
+    }
+
+    #[test]
+    fn hybrid_spins_through_the_overshoot() {
+        let (late, sleeps) = wait(Pacing::Hybrid);
+        assert_eq!(sleeps, 1);
+        assert!(late < SPIN_STEP);
+    }
+
+    #[test]
+    fn busy_wait_never_sleeps() {
+        let (late, sleeps) = wait(Pacing::BusyWait);
+        assert_eq!(sleeps, 0);
+        assert!(late < SPIN_STEP);
+    }
+
+    #[test]
+    fn past_deadlines_return_right_away() {
+        let mut clock = FakeClock { now: 5_000_000, sleeps: 0 };
+        Pacing::Hybrid.wait_until(&mut clock, 1_000_000);
+        assert_eq!(clock.sleeps, 0);
+        assert_eq!(clock.now, 5_000_000 + 2 * SPIN_STEP);
+    }
+}
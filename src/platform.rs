@@ -0,0 +1,224 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::instruction::{self, Extensions, Instruction};
+use super::memory::END_RESERVED;
+use super::quirks::Quirks;
+
+/// The interpreters a rom can be written for. Each one adds instructions on
+/// top of plain CHIP-8, XO-CHIP on top of the Super-CHIP.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Platform {
+    Chip8,
+    Chip8X,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    /// The instruction sets to enable for roms written for the platform.
+    /// Of XO-CHIP's own instructions only the long load and plane selection
+    /// are supported.
+    pub fn extensions(&self) -> Extensions {
+        match *self {
+            Platform::Chip8 => Extensions::default(),
+            Platform::Chip8X => Extensions { chip8x: true, schip: false, xochip: false },
+            Platform::SuperChip => Extensions { chip8x: false, schip: true, xochip: false },
+            Platform::XoChip => Extensions { chip8x: false, schip: true, xochip: true },
+        }
+    }
+
+    /// The quirks roms written for the platform expect. Chip-8X ran on the
+    /// COSMAC VIP and XO-CHIP follows the modern behavior.
+    pub fn quirks(&self) -> Quirks {
+        match *self {
+            Platform::Chip8 | Platform::XoChip => Quirks::modern(),
+            Platform::Chip8X => Quirks::cosmac_vip(),
+            Platform::SuperChip => Quirks::schip(),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Platform::Chip8 => write!(f, "CHIP-8"),
+            Platform::Chip8X => write!(f, "Chip-8X"),
+            Platform::SuperChip => write!(f, "Super-CHIP"),
+            Platform::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Platform, String> {
+        match s {
+            "chip8" => Ok(Platform::Chip8),
+            "chip8x" => Ok(Platform::Chip8X),
+            "schip" => Ok(Platform::SuperChip),
+            "xochip" => Ok(Platform::XoChip),
+            _ => Err(format!("unknown platform '{}', expected chip8, chip8x, schip or \
+                              xochip", s)),
+        }
+    }
+}
+
+/// Guesses the platform a rom was written for from the instructions only
+/// that platform has. Only code reachable from the start of the rom is
+/// looked at, following jumps, calls and skips, so sprites and other data
+/// can't be mistaken for instructions. Jumps through BNNN can't be followed,
+/// code only reached that way is missed. This is a guess, roms that happen
+/// not to use any of the extra instructions come out as plain CHIP-8.
+pub fn detect_platform(rom: &[u8]) -> Platform {
+    // Super-CHIP instructions are decoded so code using them is followed.
+    // The XO-CHIP long load is handled before decoding.
+    let extensions = Extensions { chip8x: false, schip: true, xochip: false };
+
+    let mut visited = vec![false; rom.len()];
+    let mut pending = vec![0];
+    let mut found = Vec::new();
+
+    while let Some(offset) = pending.pop() {
+        if offset + 1 >= rom.len() || visited[offset] {
+            continue;
+        }
+        visited[offset] = true;
+
+        let word = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+        let next = offset + 2;
+        if let Some(platform) = marker(word) {
+            found.push(platform);
+        }
+
+        // XO-CHIP's F000 NNNN is the only instruction four bytes long, its
+        // skips jump over all of it.
+        if word == 0xF000 {
+            pending.push(next + 2);
+            continue;
+        }
+
+        match instruction::decode(word, (END_RESERVED + offset) as u16, extensions) {
+            Ok(Instruction::Jump { addr }) => pending.extend(rom_offset(addr)),
+            Ok(Instruction::Call { addr }) => {
+                pending.extend(rom_offset(addr));
+                pending.push(next);
+            },
+            Ok(Instruction::SkipEqualByte { .. }) |
+            Ok(Instruction::SkipNotEqualByte { .. }) |
+            Ok(Instruction::SkipEqual { .. }) |
+            Ok(Instruction::SkipNotEqual { .. }) |
+            Ok(Instruction::SkipKey { .. }) |
+            Ok(Instruction::SkipNotKey { .. }) => {
+                let skipped = if rom[next..].starts_with(&[0xF0, 0x00]) { 4 } else { 2 };
+                pending.push(next);
+                pending.push(next + skipped);
+            },
+            Ok(Instruction::Ret) |
+            Ok(Instruction::Exit) |
+            Ok(Instruction::Halt) |
+            Ok(Instruction::JumpOffset { .. }) => {},
+            Ok(_) => pending.push(next),
+
+            // Words the decoder doesn't know are still code when they are
+            // one of the other platforms' instructions, anything else ends
+            // the path.
+            Err(_) if marker(word).is_some() => pending.push(next),
+            Err(_) => {},
+        }
+    }
+
+    if found.contains(&Platform::XoChip) {
+        Platform::XoChip
+    } else if found.contains(&Platform::SuperChip) {
+        Platform::SuperChip
+    } else if found.contains(&Platform::Chip8X) {
+        Platform::Chip8X
+    } else {
+        Platform::Chip8
+    }
+}
+
+/// The platform an instruction belongs to when plain CHIP-8 doesn't have it.
+fn marker(word: u16) -> Option<Platform> {
+    match word {
+        // F000 NNNN long load, F002 audio pattern, FN01 plane selection,
+        // FX3A pitch, 00DN scroll up and 5XY2/5XY3 register ranges.
+        0xF000 | 0xF002 => Some(Platform::XoChip),
+        _ if word & 0xF0FF == 0xF001 || word & 0xF0FF == 0xF03A => Some(Platform::XoChip),
+        _ if word & 0xFFF0 == 0x00D0 => Some(Platform::XoChip),
+        _ if word & 0xF00F == 0x5002 || word & 0xF00F == 0x5003 => Some(Platform::XoChip),
+
+        // Scrolling, EXIT, the resolution switches, FX30 large digits and
+        // FX75/FX85 flag registers.
+        0x00FB..=0x00FF => Some(Platform::SuperChip),
+        0x00C1..=0x00CF => Some(Platform::SuperChip),
+        _ if word & 0xF0FF == 0xF030 => Some(Platform::SuperChip),
+        _ if word & 0xF0FF == 0xF075 || word & 0xF0FF == 0xF085 => Some(Platform::SuperChip),
+
+        // Background color stepping and 5XY1 nibble addition.
+        0x02A0 => Some(Platform::Chip8X),
+        _ if word & 0xF00F == 0x5001 => Some(Platform::Chip8X),
+
+        _ => None,
+    }
+}
+
+/// Where an address in program space is in the rom, if it is in the rom at
+/// all.
+fn rom_offset(addr: u16) -> Option<usize> {
+    (addr as usize).checked_sub(END_RESERVED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_roms_are_chip8() {
+        // LD V0, 0x01, DRW V0, V0, 5, JP 0x202
+        assert_eq!(detect_platform(&[0x60, 0x01, 0xD0, 0x05, 0x12, 0x02]), Platform::Chip8);
+    }
+
+    #[test]
+    fn schip_instructions_mark_schip_roms() {
+        // HIGH, SCR, JP 0x202
+        assert_eq!(detect_platform(&[0x00, 0xFF, 0x00, 0xFB, 0x12, 0x02]), Platform::SuperChip);
+        // SCD 4, EXIT
+        assert_eq!(detect_platform(&[0x00, 0xC4, 0x00, 0xFD]), Platform::SuperChip);
+        // LD R, V3, EXIT
+        assert_eq!(detect_platform(&[0xF3, 0x75, 0x00, 0xFD]), Platform::SuperChip);
+    }
+
+    #[test]
+    fn xochip_wins_over_schip() {
+        // LOW, LD I, 0x1234, HIGH, EXIT
+        let rom = [0x00, 0xFE, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFF, 0x00, 0xFD];
+        assert_eq!(detect_platform(&rom), Platform::XoChip);
+    }
+
+    #[test]
+    fn chip8x_instructions_mark_chip8x_roms() {
+        // BGND, JP 0x200
+        assert_eq!(detect_platform(&[0x02, 0xA0, 0x12, 0x00]), Platform::Chip8X);
+    }
+
+    #[test]
+    fn data_that_is_never_run_is_ignored() {
+        // JP 0x204, then 00FF as sprite data, then JP 0x204.
+        assert_eq!(detect_platform(&[0x12, 0x04, 0x00, 0xFF, 0x12, 0x04]), Platform::Chip8);
+    }
+
+    #[test]
+    fn calls_and_skips_are_followed() {
+        // CALL 0x206, SE V0, 0x00, HIGH, RET. HIGH is only reached once the
+        // call returns, past the skip.
+        let rom = [0x22, 0x06, 0x30, 0x00, 0x00, 0xFF, 0x00, 0xEE];
+        assert_eq!(detect_platform(&rom), Platform::SuperChip);
+
+        // CALL 0x204, HALT, LOW, RET. LOW is only reached through the call.
+        let rom = [0x22, 0x04, 0x00, 0x00, 0x00, 0xFE, 0x00, 0xEE];
+        assert_eq!(detect_platform(&rom), Platform::SuperChip);
+    }
+}
@@ -0,0 +1,147 @@
+use super::byteorder::{BigEndian, ByteOrder};
+
+// Every PNG file starts with these bytes.
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+// Bytes per pixel of the images written, red, green, blue and alpha.
+const BYTES_PER_PIXEL: usize = 4;
+
+// The longest block of uncompressed data deflate can store.
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+/// Encodes an RGBA image, four bytes per pixel in rows from the top, as a
+/// PNG file. The pixel data isn't compressed: screenshots of the CHIP-8
+/// display are small enough that it isn't worth a compression library.
+pub fn encode(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+    assert_eq!(rgba.len(), width * height * BYTES_PER_PIXEL, "Image size doesn't match its pixels");
+
+    let mut out = SIGNATURE.to_vec();
+
+    // Eight bits per channel, color type 6 (RGBA), default compression,
+    // filtering and no interlacing.
+    let mut header = [0; 13];
+    BigEndian::write_u32(&mut header[0..4], width as u32);
+    BigEndian::write_u32(&mut header[4..8], height as u32);
+    header[8] = 8;
+    header[9] = 6;
+    write_chunk(&mut out, b"IHDR", &header);
+
+    // Every row starts with its filter type, 0 leaves the row as it is.
+    let mut scanlines = Vec::with_capacity((width * BYTES_PER_PIXEL + 1) * height);
+    for row in rgba.chunks(width * BYTES_PER_PIXEL) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&scanlines));
+
+    write_chunk(&mut out, b"IEND", &[]);
+
+    out
+}
+
+/// Appends a chunk: its length, type, data and the checksum of the type and
+/// data.
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let mut length = [0; 4];
+    BigEndian::write_u32(&mut length, data.len() as u32);
+    out.extend_from_slice(&length);
+
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+
+    let mut crc = [0; 4];
+    BigEndian::write_u32(&mut crc, crc32(&out[start..]));
+    out.extend_from_slice(&crc);
+}
+
+/// Wraps data in a zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window and no preset dictionary.
+    let mut out = vec![0x78, 0x01];
+
+    let blocks: Vec<&[u8]> = data.chunks(MAX_STORED_BLOCK).collect();
+    if blocks.is_empty() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    for (index, block) in blocks.iter().enumerate() {
+        // The first bit marks the last block, the next two being zero mark
+        // the block as stored. The length follows in little endian along
+        // with its complement.
+        let last = index == blocks.len() - 1;
+        let length = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&[length as u8, (length >> 8) as u8]);
+        out.extend_from_slice(&[!length as u8, (!length >> 8) as u8]);
+        out.extend_from_slice(block);
+    }
+
+    let mut adler = [0; 4];
+    BigEndian::write_u32(&mut adler, adler32(data));
+    out.extend_from_slice(&adler);
+
+    out
+}
+
+/// The CRC-32 checksum PNG chunks end with.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+/// The Adler-32 checksum zlib streams end with.
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        bytes[at..at + 4].iter().fold(0, |number, &byte| number << 8 | byte as u32)
+    }
+
+    #[test]
+    fn images_start_with_the_signature_and_their_size() {
+        let png = encode(3, 2, &[0x80; 3 * 2 * BYTES_PER_PIXEL]);
+
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(u32_at(&png, 8), 13);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(u32_at(&png, 16), 3);
+        assert_eq!(u32_at(&png, 20), 2);
+        assert_eq!(&png[24..26], &[8, 6]);
+        assert_eq!(u32_at(&png, 29), crc32(&png[12..29]));
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+
+    #[test]
+    fn pixels_are_stored_row_by_row_after_a_filter_byte() {
+        let rgba: Vec<u8> = (0..2 * 2 * BYTES_PER_PIXEL as u8).collect();
+        let png = encode(2, 2, &rgba);
+
+        // IDAT follows the 25 byte IHDR chunk, the zlib header and the
+        // stored block header.
+        let idat = 8 + 25;
+        assert_eq!(&png[idat + 4..idat + 8], b"IDAT");
+        let data = &png[idat + 8 + 2 + 5..];
+        assert_eq!(data[0], 0);
+        assert_eq!(&data[1..9], &rgba[..8]);
+        assert_eq!(data[9], 0);
+        assert_eq!(&data[10..18], &rgba[8..]);
+    }
+}
@@ -0,0 +1,165 @@
+use std::io::{BufRead, BufReader, Read};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use super::input::InputProvider;
+
+/// Reads keypad events as lines of text, one event per line in the form
+/// `down KEY` or `up KEY` where KEY is a hexadecimal keypad value. This lets
+/// any outside program drive the keypad through a pipe, a bridge translating
+/// MIDI notes for example.
+pub struct StreamProvider {
+    // Lines are read on their own thread so polling never blocks.
+    lines: Receiver<String>,
+
+    // Keys currently held through the stream.
+    keys: [bool; 16],
+}
+
+impl StreamProvider {
+    /// Starts reading events from the passed reader in the background.
+    pub fn spawn<R: Read + Send + 'static>(reader: R) -> StreamProvider {
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                match line {
+                    Ok(line) => {
+                        // Stop once the emulator has gone away.
+                        if sender.send(line).is_err() {
+                            break;
+                        }
+                    },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        StreamProvider {
+            lines: receiver,
+            keys: [false; 16],
+        }
+    }
+
+    /// Applies a single event line to the held keys.
+    fn handle_line(&mut self, line: &str) {
+        let mut words = line.split_whitespace();
+        let down = match words.next() {
+            Some("down") => true,
+            Some("up") => false,
+            Some(_) => {
+                println!("notch: ignoring input event '{}'", line);
+                return;
+            },
+            None => return,
+        };
+
+        match words.next().and_then(|key| u8::from_str_radix(key, 16).ok()) {
+            Some(key) if (key as usize) < self.keys.len() => {
+                self.keys[key as usize] = down;
+            },
+            _ => println!("notch: ignoring input event '{}'", line),
+        }
+    }
+}
+
+impl InputProvider for StreamProvider {
+    fn poll(&mut self, _frame: u64, keys: &mut [bool; 16]) {
+        while let Ok(line) = self.lines.try_recv() {
+            self.handle_line(&line);
+        }
+
+        for (key, &held) in keys.iter_mut().zip(self.keys.iter()) {
+            *key = *key || held;
+        }
+    }
+}
+
+/// A single scripted key press.
+struct ScriptEntry {
+    frame: u64,
+    key: usize,
+    duration: u64,
+}
+
+/// Holds keys down according to a script of `FRAME KEY DURATION` lines. Each
+/// line presses KEY (hexadecimal) at FRAME and holds it for DURATION frames,
+/// both counted in emulated frames from the first poll. Blank lines and
+/// lines starting with `#` are ignored.
+pub struct ScriptedProvider {
+    entries: Vec<ScriptEntry>,
+}
+
+impl ScriptedProvider {
+    /// Parses a script, reporting the first malformed line.
+    pub fn parse(script: &str) -> Result<ScriptedProvider, String> {
+        let mut entries = Vec::new();
+
+        for (number, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let entry = match fields.as_slice() {
+                [frame, key, duration] => {
+                    match (frame.parse(), u8::from_str_radix(key, 16), duration.parse()) {
+                        (Ok(frame), Ok(key), Ok(duration)) if key < 0x10 => ScriptEntry {
+                            frame: frame,
+                            key: key as usize,
+                            duration: duration,
+                        },
+                        _ => return Err(format!("invalid script entry on line {}: '{}'",
+                                                number + 1, line)),
+                    }
+                },
+                _ => return Err(format!("expected FRAME KEY DURATION on line {}: '{}'",
+                                        number + 1, line)),
+            };
+            entries.push(entry);
+        }
+
+        Ok(ScriptedProvider {
+            entries: entries,
+        })
+    }
+}
+
+impl InputProvider for ScriptedProvider {
+    fn poll(&mut self, frame: u64, keys: &mut [bool; 16]) {
+        for entry in &self.entries {
+            if frame >= entry.frame && frame < entry.frame + entry.duration {
+                keys[entry.key] = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::input::Input;
+
+    #[test]
+    fn scripted_keys_are_held_for_their_duration() {
+        let script = ScriptedProvider::parse("# hold key A\n2 a 3\n").unwrap();
+        let mut input = Input::headless();
+        input.add_provider(Box::new(script));
+
+        let mut held = Vec::new();
+        for _ in 0..8 {
+            input.next_frame();
+            held.push(input.input_state[0xA]);
+        }
+
+        assert_eq!(held, [false, false, true, true, true, false, false, false]);
+    }
+
+    #[test]
+    fn malformed_script_lines_are_reported() {
+        assert!(ScriptedProvider::parse("1 a").is_err());
+        assert!(ScriptedProvider::parse("1 10 2").is_err());
+        assert!(ScriptedProvider::parse("x a 2").is_err());
+    }
+}
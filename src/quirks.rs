@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+/// Behaviors CHIP-8 interpreters disagree on. Roms are usually written
+/// against one interpreter and can break on the others, so each difference
+/// can be switched on its own. The presets match the interpreters most roms
+/// target, the default is the modern behavior most newer roms expect.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    // 8XY1, 8XY2 and 8XY3 reset VF to 0, a side effect of how the COSMAC VIP
+    // interpreter does logic.
+    pub vf_reset: bool,
+
+    // 8XY6 and 8XYE shift VY and store the result in VX, as on the COSMAC
+    // VIP. Otherwise VX is shifted in place and VY is ignored.
+    pub shift_vy: bool,
+
+    // FX55 and FX65 leave I pointing right after the last register
+    // transferred, as on the COSMAC VIP. Otherwise I is left unchanged.
+    pub increment_i: bool,
+
+    // Sprites are cut off at the edges of the display instead of wrapping
+    // around to the other side. The position a sprite starts at wraps either
+    // way.
+    pub clip_sprites: bool,
+
+    // BNNN jumps to NNN plus VX, X being the highest nibble of NNN, as on
+    // the CHIP-48 and Super-CHIP. Otherwise it jumps to NNN plus V0.
+    pub jump_vx: bool,
+
+    // FX1E sets VF to 1 when I ends up past 0xFFF, or 0xFFFF with XO-CHIP,
+    // and to 0 otherwise, like the Amiga interpreter. A few roms, Spacefight 2091! among them, depend
+    // on this. None of the presets enable it.
+    pub i_overflow_flag: bool,
+
+    // DXYN reads sprite bytes past the end of ram as zero instead of failing.
+    // Real interpreters read whatever follows ram, this only keeps roms that
+    // point I too close to the end running. None of the presets enable it.
+    pub pad_sprites: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter.
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            vf_reset: true,
+            shift_vy: true,
+            increment_i: true,
+            clip_sprites: true,
+            jump_vx: false,
+            i_overflow_flag: false,
+            pad_sprites: false,
+        }
+    }
+
+    /// The Super-CHIP interpreter on the HP48 calculators, and the CHIP-48
+    /// it was based on.
+    pub fn schip() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            shift_vy: false,
+            increment_i: false,
+            clip_sprites: true,
+            jump_vx: true,
+            i_overflow_flag: false,
+            pad_sprites: false,
+        }
+    }
+
+    /// What most modern interpreters do and most newer roms expect.
+    pub fn modern() -> Quirks {
+        Quirks::default()
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Quirks, String> {
+        match s {
+            "vip" => Ok(Quirks::cosmac_vip()),
+            "schip" => Ok(Quirks::schip()),
+            "modern" => Ok(Quirks::modern()),
+            _ => Err(format!("unknown quirks preset '{}', expected vip, schip or modern", s)),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+/// The expected machine state at the start of a step.
+#[derive(Debug)]
+struct TraceStep {
+    pc: u16,
+    i: u16,
+    registers: [u8; 16],
+}
+
+/// The outcome of comparing the machine against the next step of a trace.
+pub enum TraceCheck {
+    Match,
+    Mismatch(String),
+    Finished,
+}
+
+/// An execution trace recorded by another emulator. Each line holds the
+/// state at the start of a step as hexadecimal numbers separated by spaces:
+/// PC, I, then V0 through VF. Blank lines and lines starting with `#` are
+/// ignored.
+#[derive(Debug)]
+pub struct ReferenceTrace {
+    steps: Vec<TraceStep>,
+
+    // The step the machine is expected to be at next.
+    position: usize,
+}
+
+impl ReferenceTrace {
+    /// Parses a trace, reporting the first malformed line.
+    pub fn parse(trace: &str) -> Result<ReferenceTrace, String> {
+        let mut steps = Vec::new();
+
+        for (number, line) in trace.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values: Result<Vec<u16>, _> = line.split_whitespace()
+                .map(|value| u16::from_str_radix(value, 16))
+                .collect();
+            let values = match values {
+                Ok(values) if values.len() == 18 && values[2..].iter().all(|&v| v <= 0xFF) => values,
+                _ => return Err(format!("expected PC, I and V0-VF on line {}: '{}'",
+                                        number + 1, line)),
+            };
+
+            let mut registers = [0; 16];
+            for (reg, &value) in registers.iter_mut().zip(&values[2..]) {
+                *reg = value as u8;
+            }
+            steps.push(TraceStep {
+                pc: values[0],
+                i: values[1],
+                registers: registers,
+            });
+        }
+
+        Ok(ReferenceTrace {
+            steps: steps,
+            position: 0,
+        })
+    }
+
+    /// Compares the machine against the next step and moves on to the one
+    /// after it. Mismatches describe every value that differs.
+    pub fn check(&mut self, pc: u16, i: u16, registers: &[u8; 16]) -> TraceCheck {
+        let step = match self.steps.get(self.position) {
+            Some(step) => step,
+            None => return TraceCheck::Finished,
+        };
+
+        let mut differences = Vec::new();
+        if step.pc != pc {
+            differences.push(format!("pc expected {:#06x} got {:#06x}", step.pc, pc));
+        }
+        if step.i != i {
+            differences.push(format!("i expected {:#06x} got {:#06x}", step.i, i));
+        }
+        for (reg, (&expected, &got)) in step.registers.iter().zip(registers.iter()).enumerate() {
+            if expected != got {
+                differences.push(format!("v{:x} expected {:#04x} got {:#04x}",
+                                         reg, expected, got));
+            }
+        }
+
+        let position = self.position;
+        self.position += 1;
+
+        if differences.is_empty() {
+            TraceCheck::Match
+        } else {
+            TraceCheck::Mismatch(format!("diverged from the reference at step {}: {}",
+                                         position, differences.join(", ")))
+        }
+    }
+
+    /// Number of steps the trace holds.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two steps of LD V0, 0x2A followed by LD I, 0x300.
+    const TRACE: &str = "# pc i v0-vf
+                         200 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0
+                         202 0 2a 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0";
+
+    #[test]
+    fn matching_runs_finish_the_trace() {
+        let mut trace = ReferenceTrace::parse(TRACE).unwrap();
+        let mut registers = [0; 16];
+
+        assert!(matches!(trace.check(0x200, 0, &registers), TraceCheck::Match));
+        registers[0] = 0x2A;
+        assert!(matches!(trace.check(0x202, 0, &registers), TraceCheck::Match));
+        assert!(matches!(trace.check(0x204, 0x300, &registers), TraceCheck::Finished));
+    }
+
+    #[test]
+    fn diverging_runs_report_the_step() {
+        let mut trace = ReferenceTrace::parse(TRACE).unwrap();
+        let registers = [0; 16];
+
+        assert!(matches!(trace.check(0x200, 0, &registers), TraceCheck::Match));
+        match trace.check(0x202, 0, &registers) {
+            TraceCheck::Mismatch(why) => {
+                assert_eq!(why, "diverged from the reference at step 1: \
+                                 v0 expected 0x2a got 0x00");
+            },
+            _ => panic!("expected a mismatch"),
+        }
+    }
+
+    #[test]
+    fn short_lines_are_rejected() {
+        assert!(ReferenceTrace::parse("200 0 0").is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use super::byteorder::{BigEndian, ByteOrder};
+
+// Every input log starts with these bytes.
+const MAGIC: &[u8] = b"NOTCHINP";
+
+/// The keypad state of every frame of a run, for playing the run back
+/// later. Together with a seeded random number generator a replay presses
+/// exactly the same keys at the same points in the rom, as long as it runs
+/// on the same schedule the recording did.
+#[derive(Debug, Default)]
+pub struct InputLog {
+    // Held keys of each frame, one bit per key with key 0 in the lowest bit.
+    frames: Vec<u16>,
+}
+
+impl InputLog {
+    /// Appends the keys held during the next frame.
+    pub fn push(&mut self, keys: &[bool; 16]) {
+        let mask = keys.iter()
+            .enumerate()
+            .fold(0, |mask, (key, &held)| mask | (held as u16) << key);
+        self.frames.push(mask);
+    }
+
+    /// The keys held during the passed frame, None past the end of the log.
+    pub fn frame(&self, frame: usize) -> Option<[bool; 16]> {
+        self.frames.get(frame).map(|&mask| {
+            let mut keys = [false; 16];
+            for (key, held) in keys.iter_mut().enumerate() {
+                *held = mask & (1 << key) != 0;
+            }
+            keys
+        })
+    }
+
+    /// Number of frames in the log.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Writes the log as runs of identical frames, since keys are usually
+    /// held for many frames in a row. Each run is the key mask followed by
+    /// the number of frames, both big endian.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        let mut index = 0;
+
+        while index < self.frames.len() {
+            let mask = self.frames[index];
+            let length = self.frames[index..]
+                .iter()
+                .take(u16::MAX as usize)
+                .take_while(|&&frame| frame == mask)
+                .count();
+
+            let mut run = [0; 4];
+            BigEndian::write_u16(&mut run[0..2], mask);
+            BigEndian::write_u16(&mut run[2..4], length as u16);
+            out.extend_from_slice(&run);
+            index += length;
+        }
+
+        out
+    }
+
+    /// Reads a log written by `encode`.
+    pub fn decode(bytes: &[u8]) -> Result<InputLog, String> {
+        if !bytes.starts_with(MAGIC) {
+            return Err("not an input log".to_string());
+        }
+
+        let runs = &bytes[MAGIC.len()..];
+        if !runs.len().is_multiple_of(4) {
+            return Err("input log is truncated".to_string());
+        }
+
+        let mut frames = Vec::new();
+        for run in runs.chunks(4) {
+            let mask = BigEndian::read_u16(&run[0..2]);
+            let length = BigEndian::read_u16(&run[2..4]) as usize;
+            frames.extend(vec![mask; length]);
+        }
+
+        Ok(InputLog {
+            frames: frames,
+        })
+    }
+}
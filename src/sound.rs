@@ -1,12 +1,73 @@
+use std::io::Write;
+
 use super::sdl2::audio::{AudioCallback, AudioSpecDesired};
 use super::sdl2;
+use super::time;
+
+use super::wav::{self, WavRecorder};
+
+// Default pitch and loudness of the beep.
+const BEEP_FREQUENCY: f32 = 440.0;
+const BEEP_VOLUME: f32 = 0.25;
+
+/// Told when the beep starts and stops, for frontends that play sound their
+/// own way. The built-in beep plays either way, unless running headless.
+pub trait BeepListener {
+    fn beep_changed(&mut self, beeping: bool);
+}
+
+/// Writes a `start` or `stop` line for every change of the beep, so any
+/// outside program can follow the beeper through a pipe.
+pub struct StreamListener {
+    out: Box<dyn Write>,
+}
+
+impl StreamListener {
+    pub fn new(out: Box<dyn Write>) -> StreamListener {
+        StreamListener {
+            out: out,
+        }
+    }
+}
+
+impl BeepListener for StreamListener {
+    fn beep_changed(&mut self, beeping: bool) {
+        let line = if beeping { "start" } else { "stop" };
+
+        // The other end going away shouldn't stop the emulator.
+        let _ = writeln!(self.out, "{}", line).and_then(|_| self.out.flush());
+    }
+}
+
+/// Keeps every change of the beep where tests can still see it.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct BeepLog(pub ::std::rc::Rc<::std::cell::RefCell<Vec<bool>>>);
+
+#[cfg(test)]
+impl BeepListener for BeepLog {
+    fn beep_changed(&mut self, beeping: bool) {
+        self.0.borrow_mut().push(beeping);
+    }
+}
 
 pub struct Sound {
-    // SDL objects for communication with the window system.
-    audio_device: sdl2::audio::AudioDevice<BeepCallback>,
+    // SDL objects for communication with the window system. Nothing is
+    // played when running headless.
+    audio_device: Option<sdl2::audio::AudioDevice<BeepCallback>>,
 
     // When true beeping audio will play.
-    pub beeping: bool,
+    beeping: bool,
+
+    // Pitch in Hz and loudness from 0.0 to 1.0 of the beep.
+    frequency: f32,
+    volume: f32,
+
+    // Optional recording of everything the beeper outputs.
+    recorder: Option<Recording>,
+
+    // Told about every start and stop of the beep.
+    listeners: Vec<Box<dyn BeepListener>>,
 }
 
 impl Sound {
@@ -15,50 +76,251 @@ impl Sound {
 
         // Setup beep sound parameters.
         let desired_spec = AudioSpecDesired {
-            freq: Some(44100), // I think this is healthy?
-            channels: Some(1), // Mono.
-            samples: None,     // Default sample size.
+            freq: Some(wav::SAMPLE_RATE as i32), // I think this is healthy?
+            channels: Some(1),                   // Mono.
+            samples: None,                       // Default sample size.
         };
         let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
             BeepCallback {
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25
+                wave: SquareWave::new(spec.freq as f32, BEEP_FREQUENCY, BEEP_VOLUME),
             }
         }).unwrap();
 
+        Sound::with_device(Some(device))
+    }
+
+    /// Creates a silent beeper, recordings still work.
+    pub fn headless() -> Sound {
+        Sound::with_device(None)
+    }
+
+    fn with_device(device: Option<sdl2::audio::AudioDevice<BeepCallback>>) -> Sound {
         Sound {
             audio_device: device,
             beeping: false,
+            frequency: BEEP_FREQUENCY,
+            volume: BEEP_VOLUME,
+            recorder: None,
+            listeners: Vec::new(),
         }
     }
 
-    pub fn handle_sound(&self) {
+    /// Starts writing the beeper output to the passed recorder. Silence is
+    /// recorded too so the recording lines up with wall clock time.
+    pub fn record(&mut self, recorder: WavRecorder) {
+        self.recorder = Some(Recording {
+            recorder: recorder,
+            wave: SquareWave::new(wav::SAMPLE_RATE as f32, self.frequency, self.volume),
+            start_time: time::precise_time_ns(),
+            samples_written: 0,
+        });
+    }
+
+    /// Changes the pitch of the beep in Hz, including a beep already
+    /// playing or being recorded.
+    pub fn set_beep_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+        self.retune();
+    }
+
+    /// Changes the loudness of the beep, from 0.0 for silence to 1.0.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+        self.retune();
+    }
+
+    /// Tells the passed listener whenever the beep starts or stops from now
+    /// on.
+    pub fn add_listener(&mut self, listener: Box<dyn BeepListener>) {
+        self.listeners.push(listener);
+    }
+
+    /// True while the beep is playing.
+    pub fn beeping(&self) -> bool {
+        self.beeping
+    }
+
+    /// Starts playing the beep.
+    pub fn start_beep(&mut self) {
+        self.handle_recording();
+        if !self.beeping {
+            self.notify_listeners(true);
+        }
+        self.beeping = true;
+        if let Some(ref device) = self.audio_device {
+            device.resume();
+        }
+    }
+
+    /// Stops playing the beep.
+    pub fn stop_beep(&mut self) {
+        self.handle_recording();
         if self.beeping {
-            self.audio_device.resume();
-        } else {
-            self.audio_device.pause();
+            self.notify_listeners(false);
+        }
+        self.beeping = false;
+        if let Some(ref device) = self.audio_device {
+            device.pause();
+        }
+    }
+
+    pub fn handle_sound(&mut self) {
+        self.handle_recording();
+    }
+
+    /// Stops the beeper and finalizes the recording, if any, so the file
+    /// holds everything played up until now.
+    pub fn shutdown(&mut self) {
+        self.stop_beep();
+
+        if let Some(mut recording) = self.recorder.take() {
+            let result = recording.catch_up(self.beeping)
+                .and_then(|_| recording.recorder.finish());
+            if let Err(why) = result {
+                println!("notch: cannot finish audio recording: {}", why);
+            }
+        }
+    }
+
+    /// Stops the beep and closes the audio device. Nothing plays afterwards,
+    /// recording keeps working.
+    pub fn close(&mut self) {
+        self.stop_beep();
+        self.audio_device = None;
+    }
+
+    /// Passes the current pitch and loudness on to the playing and recorded
+    /// square waves.
+    fn retune(&mut self) {
+        let (frequency, volume) = (self.frequency, self.volume);
+        if let Some(ref mut device) = self.audio_device {
+            device.lock().wave.tune(frequency, volume);
+        }
+        if let Some(ref mut recording) = self.recorder {
+            recording.wave.tune(frequency, volume);
+        }
+    }
+
+    fn notify_listeners(&mut self, beeping: bool) {
+        for listener in self.listeners.iter_mut() {
+            listener.beep_changed(beeping);
+        }
+    }
+
+    /// Catches the recording up to the current time, generating the same
+    /// square wave that is being played while beeping.
+    fn handle_recording(&mut self) {
+        let beeping = self.beeping;
+        let failed = match self.recorder {
+            Some(ref mut recording) => recording.catch_up(beeping).is_err(),
+            None => false,
+        };
+
+        // Stop recording rather than failing on every call afterwards.
+        if failed {
+            println!("notch: audio recording failed, stopping the recording");
+            self.recorder = None;
         }
     }
 }
 
-struct BeepCallback {
+struct Recording {
+    recorder: WavRecorder,
+
+    // A separate generator so the recording doesn't depend on the playback
+    // sample rate.
+    wave: SquareWave,
+
+    // Used to work out how many samples the recording is behind by.
+    start_time: u64,
+    samples_written: u64,
+}
+
+impl Recording {
+    /// Writes every sample between the last call and now.
+    fn catch_up(&mut self, beeping: bool) -> ::std::io::Result<()> {
+        let elapsed = time::precise_time_ns() - self.start_time;
+        let target = elapsed * wav::SAMPLE_RATE as u64 / 1_000_000_000;
+
+        while self.samples_written < target {
+            let sample = if beeping { self.wave.next_sample() } else { 0.0 };
+            self.recorder.write_sample(sample)?;
+            self.samples_written += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Generates the square wave used for the beep, one sample at a time.
+struct SquareWave {
+    sample_rate: f32,
     phase_inc: f32,
     phase: f32,
     volume: f32
 }
 
+impl SquareWave {
+    fn new(sample_rate: f32, frequency: f32, volume: f32) -> SquareWave {
+        SquareWave {
+            sample_rate: sample_rate,
+            phase_inc: frequency / sample_rate,
+            phase: 0.0,
+            volume: volume,
+        }
+    }
+
+    /// Changes the pitch and loudness, carrying on from the current phase.
+    fn tune(&mut self, frequency: f32, volume: f32) {
+        self.phase_inc = frequency / self.sample_rate;
+        self.volume = volume;
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let sample = match self.phase {
+            0.0...0.5 => self.volume,
+            _ => -self.volume
+        };
+        self.phase = (self.phase + self.phase_inc) % 1.0;
+
+        sample
+    }
+}
+
+struct BeepCallback {
+    wave: SquareWave,
+}
+
 impl AudioCallback for BeepCallback {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
         // Generate a square wave.
         for x in out.iter_mut() {
-            *x = match self.phase {
-                0.0...0.5 => self.volume,
-                _ => -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            *x = self.wave.next_sample();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_stays_within_full_loudness() {
+        let mut sound = Sound::headless();
+        sound.set_volume(2.0);
+        assert_eq!(sound.volume, 1.0);
+        sound.set_volume(-1.0);
+        assert_eq!(sound.volume, 0.0);
+    }
+
+    #[test]
+    fn tuned_waves_change_pitch_and_loudness() {
+        let mut wave = SquareWave::new(8.0, 1.0, 0.5);
+        wave.tune(2.0, 0.25);
+
+        let samples: Vec<f32> = (0..4).map(|_| wave.next_sample()).collect();
+        assert_eq!(samples, [0.25, 0.25, 0.25, -0.25]);
+    }
+}
@@ -0,0 +1,345 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use super::byteorder::{BigEndian, ByteOrder};
+
+use super::error::StateError;
+use super::flags::FLAG_COUNT;
+use super::graphics::{BACKGROUND_COLORS, DEFAULT_PLANES, DISPLAY_HEIGHT, DISPLAY_WIDTH,
+                      DisplayColors, PLANE_COUNT, ZONE_COUNT};
+use super::memory::RAM_SIZE;
+
+// Every save state starts with these bytes, followed by the format version.
+// Version 1 states always hold 4kB of ram, version 2 stores the size of ram
+// before it. Version 3 adds the keypad, the halt state, the selected XO-CHIP
+// planes and the Chip-8X colors after the display.
+const MAGIC: &[u8] = b"NOTCH";
+const VERSION: u8 = 3;
+
+// Pixels in the low and high resolution displays.
+const LORES_PIXELS: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
+const HIRES_PIXELS: usize = LORES_PIXELS * 4;
+
+/// Everything needed to put the machine back the way it was: the cpu
+/// registers, ram, the display and the keypad.
+#[derive(Debug, PartialEq)]
+pub struct MachineState {
+    pub pc: u16,
+    pub i: u16,
+    pub sp: u8,
+    pub stack: [u16; 16],
+    pub registers: [u8; 16],
+    pub dt: u8,
+    pub st: u8,
+
+    // Time towards the next timer tick, in nanoseconds.
+    pub timer_elapsed: u64,
+
+    pub hp48_flags: [u8; FLAG_COUNT],
+    pub ram: Vec<u8>,
+    pub hires: bool,
+    pub display: Vec<u8>,
+
+    // Keys held during the current frame.
+    pub input_state: [bool; 16],
+
+    // Set once execution stopped for good.
+    pub halted: bool,
+
+    // The XO-CHIP planes drawing works on.
+    pub planes: u8,
+
+    // None for states saved before colors were, the current colors are
+    // kept when loading those.
+    pub colors: Option<DisplayColors>,
+}
+
+impl MachineState {
+    /// Writes the state out in the save state format. Numbers are stored
+    /// big endian and everything is laid out in the order of the fields.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        push_u16(&mut out, self.pc);
+        push_u16(&mut out, self.i);
+        out.push(self.sp);
+        for addr in self.stack.iter() {
+            push_u16(&mut out, *addr);
+        }
+        out.extend_from_slice(&self.registers);
+        out.push(self.dt);
+        out.push(self.st);
+        let mut elapsed = [0; 8];
+        BigEndian::write_u64(&mut elapsed, self.timer_elapsed);
+        out.extend_from_slice(&elapsed);
+        out.extend_from_slice(&self.hp48_flags);
+        let mut ram_size = [0; 4];
+        BigEndian::write_u32(&mut ram_size, self.ram.len() as u32);
+        out.extend_from_slice(&ram_size);
+        out.extend_from_slice(&self.ram);
+        out.push(self.hires as u8);
+        out.extend_from_slice(&self.display);
+        let keys = self.input_state.iter()
+            .enumerate()
+            .fold(0, |mask, (key, &held)| mask | (held as u16) << key);
+        push_u16(&mut out, keys);
+        out.push(self.halted as u8);
+        out.push(self.planes);
+
+        // Without colors the defaults are saved, states always hold them.
+        let colors = self.colors.clone().unwrap_or_else(|| DisplayColors {
+            zones: vec![(255, 255, 255); ZONE_COUNT],
+            background: (0, 0, 0),
+            background_index: BACKGROUND_COLORS.len() - 1,
+        });
+        for &(r, g, b) in colors.zones.iter().chain(Some(&colors.background)) {
+            out.extend_from_slice(&[r, g, b]);
+        }
+        out.push(colors.background_index as u8);
+
+        out
+    }
+
+    /// Reads a state written by `encode`, checking it is complete and makes
+    /// sense before anything is restored from it.
+    pub fn decode(bytes: &[u8]) -> Result<MachineState, StateError> {
+        let mut reader = StateReader { bytes: bytes, position: 0 };
+
+        if reader.take(MAGIC.len()).ok() != Some(MAGIC) {
+            return Err(StateError::UnknownFormat);
+        }
+        let version = reader.byte()?;
+        if version == 0 || version > VERSION {
+            return Err(StateError::UnsupportedVersion { version: version });
+        }
+
+        let pc = reader.word()?;
+        let i = reader.word()?;
+        let sp = reader.byte()?;
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = reader.word()?;
+        }
+        if sp as usize > stack.len() {
+            return Err(StateError::Invalid { reason: "stack pointer past the end of the stack" });
+        }
+
+        let mut registers = [0; 16];
+        registers.copy_from_slice(reader.take(16)?);
+        let dt = reader.byte()?;
+        let st = reader.byte()?;
+        let timer_elapsed = reader.number(8)?;
+        let mut hp48_flags = [0; FLAG_COUNT];
+        hp48_flags.copy_from_slice(reader.take(FLAG_COUNT)?);
+        let ram_size = match version {
+            1 => RAM_SIZE,
+            _ => reader.number(4)? as usize,
+        };
+        let ram = reader.take(ram_size)?.to_vec();
+
+        let hires = match reader.byte()? {
+            0 => false,
+            1 => true,
+            _ => return Err(StateError::Invalid { reason: "unknown display resolution" }),
+        };
+        let pixels = if hires { HIRES_PIXELS } else { LORES_PIXELS };
+        let display = reader.take(pixels)?.to_vec();
+        if display.iter().any(|&pixel| pixel >> PLANE_COUNT != 0) {
+            return Err(StateError::Invalid { reason: "pixels set on planes that don't exist" });
+        }
+
+        let (input_state, halted, planes, colors) = if version >= 3 {
+            let keys = reader.word()?;
+            let mut input_state = [false; 16];
+            for (key, held) in input_state.iter_mut().enumerate() {
+                *held = keys & (1 << key) != 0;
+            }
+
+            let halted = match reader.byte()? {
+                0 => false,
+                1 => true,
+                _ => return Err(StateError::Invalid { reason: "unknown halt state" }),
+            };
+            let planes = reader.byte()?;
+            if planes >> PLANE_COUNT != 0 {
+                return Err(StateError::Invalid { reason: "selected planes don't exist" });
+            }
+
+            let rgb: Vec<(u8, u8, u8)> = reader.take((ZONE_COUNT + 1) * 3)?
+                .chunks(3)
+                .map(|color| (color[0], color[1], color[2]))
+                .collect();
+            let background_index = reader.byte()? as usize;
+            if background_index >= BACKGROUND_COLORS.len() {
+                return Err(StateError::Invalid { reason: "unknown background color" });
+            }
+            let colors = DisplayColors {
+                zones: rgb[..ZONE_COUNT].to_vec(),
+                background: rgb[ZONE_COUNT],
+                background_index: background_index,
+            };
+
+            (input_state, halted, planes, Some(colors))
+        } else {
+            ([false; 16], false, DEFAULT_PLANES, None)
+        };
+
+        if reader.position != bytes.len() {
+            return Err(StateError::Invalid { reason: "unexpected data at the end" });
+        }
+
+        Ok(MachineState {
+            pc: pc,
+            i: i,
+            sp: sp,
+            stack: stack,
+            registers: registers,
+            dt: dt,
+            st: st,
+            timer_elapsed: timer_elapsed,
+            hp48_flags: hp48_flags,
+            ram: ram,
+            hires: hires,
+            display: display,
+            input_state: input_state,
+            halted: halted,
+            planes: planes,
+            colors: colors,
+        })
+    }
+}
+
+/// Reads a save state front to back.
+struct StateReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// Takes the next `len` bytes, failing if the state ends before that.
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.position + len > self.bytes.len() {
+            return Err(StateError::Truncated);
+        }
+
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u16, StateError> {
+        Ok(self.number(2)? as u16)
+    }
+
+    /// Reads a big endian number `len` bytes long. Put together by hand
+    /// since the bytes can be at any alignment.
+    fn number(&mut self, len: usize) -> Result<u64, StateError> {
+        Ok(self.take(len)?.iter().fold(0, |number, &byte| number << 8 | byte as u64))
+    }
+}
+
+fn push_u16(out: &mut Vec<u8>, word: u16) {
+    let mut bytes = [0; 2];
+    BigEndian::write_u16(&mut bytes, word);
+    out.extend_from_slice(&bytes);
+}
+
+/// A file holding the quicksave of a rom, so every game has its own slot.
+#[derive(Debug)]
+pub struct StateSlot {
+    path: PathBuf,
+}
+
+impl StateSlot {
+    pub fn new(path: PathBuf) -> StateSlot {
+        StateSlot {
+            path: path,
+        }
+    }
+
+    /// Where the slot is stored.
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Reads the saved state. Returns None if nothing has been saved to the
+    /// slot yet.
+    pub fn load(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(why) => return Err(why),
+        };
+
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        Ok(Some(buffer))
+    }
+
+    /// Overwrites the saved state.
+    pub fn save(&self, state: &[u8]) -> io::Result<()> {
+        let mut file = File::create(&self.path)?;
+        file.write_all(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> MachineState {
+        MachineState {
+            pc: 0x200,
+            i: 0,
+            sp: 0,
+            stack: [0; 16],
+            registers: [0; 16],
+            dt: 0,
+            st: 0,
+            timer_elapsed: 0,
+            hp48_flags: [0; FLAG_COUNT],
+            ram: vec![0; RAM_SIZE],
+            hires: false,
+            display: vec![0; LORES_PIXELS],
+            input_state: [false; 16],
+            halted: false,
+            planes: DEFAULT_PLANES,
+            colors: None,
+        }
+    }
+
+    #[test]
+    fn version_2_states_load_without_the_new_fields() {
+        let mut bytes = state().encode();
+        bytes[MAGIC.len()] = 2;
+        let extra = 2 + 1 + 1 + (ZONE_COUNT + 1) * 3 + 1;
+        let len = bytes.len();
+        bytes.truncate(len - extra);
+
+        let decoded = MachineState::decode(&bytes).unwrap();
+        assert_eq!(decoded.planes, DEFAULT_PLANES);
+        assert_eq!(decoded.colors, None);
+        assert!(!decoded.halted);
+    }
+
+    #[test]
+    fn planes_that_dont_exist_are_rejected() {
+        let mut state = state();
+        state.planes = 1 << PLANE_COUNT;
+
+        assert!(MachineState::decode(&state.encode()).is_err());
+    }
+
+    #[test]
+    fn empty_slots_load_nothing() {
+        let slot = StateSlot::new(PathBuf::from("/nonexistent/notch/game.state"));
+        assert_eq!(slot.load().unwrap(), None);
+    }
+}
@@ -0,0 +1,159 @@
+use std::str::FromStr;
+
+// Instructions per second when nothing else is chosen. Most roms are playable
+// somewhere between 500 and 1000.
+const DEFAULT_CLOCK_HZ: u64 = 500;
+
+// Instructions per frame when frame based execution is chosen without a
+// count, close to 600 instructions per second.
+pub const DEFAULT_CYCLES_PER_FRAME: u64 = 10;
+
+/// How long instructions take to execute on a particular machine, in
+/// nanoseconds. Most instructions cost the same, the expensive ones are
+/// listed separately. The presets are approximations meant to capture the
+/// feel of the original hardware, they are not cycle exact.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimingProfile {
+    // Anything not listed below.
+    pub base: u64,
+
+    // 00E0, clearing the whole display.
+    pub clear: u64,
+
+    // DXYN, a fixed cost plus a cost for each row of the sprite.
+    pub draw: u64,
+    pub draw_row: u64,
+
+    // FX33, the binary-coded decimal conversion.
+    pub bcd: u64,
+
+    // FX55 and FX65, charged for each register transferred.
+    pub transfer_register: u64,
+}
+
+impl TimingProfile {
+    /// The RCA COSMAC VIP, where the interpreter first ran. Drawing and
+    /// clearing are slow enough to take a good part of a frame.
+    pub fn cosmac_vip() -> TimingProfile {
+        TimingProfile {
+            base: 200_000,
+            clear: 14_000_000,
+            draw: 2_000_000,
+            draw_row: 250_000,
+            bcd: 1_500_000,
+            transfer_register: 60_000,
+        }
+    }
+
+    /// CHIP-48 on the HP48 calculators, quicker than the VIP across the board.
+    pub fn chip48() -> TimingProfile {
+        TimingProfile {
+            base: 100_000,
+            clear: 2_000_000,
+            draw: 1_000_000,
+            draw_row: 100_000,
+            bcd: 400_000,
+            transfer_register: 20_000,
+        }
+    }
+
+    /// A modern interpreter where every instruction is equally cheap.
+    pub fn fast() -> TimingProfile {
+        TimingProfile::uniform(500_000)
+    }
+
+    /// Executes the passed number of instructions per second, whichever
+    /// instructions they are. Typical values are 500-1000Hz, faster clocks
+    /// make most games too quick to play.
+    pub fn clock(hz: u64) -> TimingProfile {
+        TimingProfile::uniform(1_000_000_000 / hz)
+    }
+
+    /// Every instruction takes the same amount of time.
+    pub fn uniform(cost: u64) -> TimingProfile {
+        TimingProfile {
+            base: cost,
+            clear: cost,
+            draw: cost,
+            draw_row: 0,
+            bcd: cost,
+            transfer_register: 0,
+        }
+    }
+
+    /// Returns how long the passed instruction takes to execute.
+    pub fn cost(&self, instr: u16) -> u64 {
+        let nibble = (instr & 0xF) as u64;
+        let regx = ((instr >> 8) & 0xF) as u64;
+
+        match (instr >> 12, instr & 0xFF) {
+            (0x0, 0xE0) if instr == 0x00E0 => self.clear,
+            (0xd, _) => self.draw + self.draw_row * nibble,
+            (0xf, 0x33) => self.bcd,
+            (0xf, 0x55) | (0xf, 0x65) => self.base + self.transfer_register * (regx + 1),
+            _ => self.base,
+        }
+    }
+}
+
+impl Default for TimingProfile {
+    /// Around 500 instructions per second regardless of the instruction.
+    fn default() -> TimingProfile {
+        TimingProfile::clock(DEFAULT_CLOCK_HZ)
+    }
+}
+
+impl FromStr for TimingProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TimingProfile, String> {
+        match s {
+            "vip" => Ok(TimingProfile::cosmac_vip()),
+            "chip48" => Ok(TimingProfile::chip48()),
+            "fast" => Ok(TimingProfile::fast()),
+            _ => Err(format!("unknown timing profile '{}', expected vip, chip48 or fast", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::cpu::Cpu;
+    use super::super::interconnect::Interconnect;
+    use super::super::memory::RAM_SIZE;
+
+    /// The instructions executed until `frames` frames have passed with the
+    /// passed profile.
+    fn cycles_for(timing: TimingProfile, frames: u64) -> u64 {
+        // 0x200: CLS, LD I, 0x000, DRW V0, V0, 5, ADD V1, 0x01, JP 0x200
+        let rom = [0x00, 0xE0, 0xA0, 0x00, 0xD0, 0x05, 0x71, 0x01, 0x12, 0x00];
+        let interconnect = Interconnect::headless(&rom[..], RAM_SIZE).unwrap();
+        let mut cpu = Cpu::with_seed(interconnect, 0);
+        cpu.enable_turbo();
+        cpu.set_timing(timing);
+
+        while cpu.frames() < frames {
+            cpu.step().unwrap();
+        }
+        cpu.cycles()
+    }
+
+    #[test]
+    fn profiles_run_at_different_speeds() {
+        let vip = cycles_for(TimingProfile::cosmac_vip(), 60);
+        let chip48 = cycles_for(TimingProfile::chip48(), 60);
+        let fast = cycles_for(TimingProfile::fast(), 60);
+
+        assert!(vip < chip48, "vip {} chip48 {}", vip, chip48);
+        assert!(chip48 < fast, "chip48 {} fast {}", chip48, fast);
+    }
+
+    #[test]
+    fn clocks_run_their_rate_every_second() {
+        for &hz in [500, 1000].iter() {
+            let cycles = cycles_for(TimingProfile::clock(hz), 60);
+            assert!((hz..hz + 2).contains(&cycles), "{}Hz ran {} cycles", hz, cycles);
+        }
+    }
+}
@@ -1,24 +1,204 @@
-use super::cpu::Cpu;
+use std::io;
+
+use super::config::Config;
+use super::cpu::{Cpu, Stopped};
+use super::display::DEFAULT_SCALE;
+use super::error::{CpuError, MemoryError, RomError, StateError};
 use super::interconnect::Interconnect;
+use super::memory::{RAM_SIZE, XO_RAM_SIZE};
+use super::replay::InputLog;
 
 pub struct VirtualMachine {
     cpu: Cpu,
 }
 
 impl VirtualMachine {
-    pub fn new(rom: Vec<u8>) -> VirtualMachine {
+    pub fn new<R: AsRef<[u8]>>(rom: R, config: Config) -> Result<VirtualMachine, RomError> {
         // Create a clean cpu state and interconnect (manages memory/input/etc).
-        let interconnect = Interconnect::new(rom);
-        let cpu = Cpu::new(interconnect);
+        let ram_size = if config.xochip { XO_RAM_SIZE } else { RAM_SIZE };
+        let mut interconnect = if config.headless {
+            Interconnect::headless(rom, ram_size)?
+        } else if config.terminal {
+            Interconnect::terminal(rom, ram_size)?
+        } else {
+            Interconnect::new(rom, ram_size, config.scale.unwrap_or(DEFAULT_SCALE),
+                              config.fullscreen)?
+        };
+
+        // Hook up the optional peripherals before the cpu takes ownership.
+        interconnect.memory.set_mirroring(config.mirror_ram);
+        if let Some(fps) = config.fps {
+            interconnect.graphics.set_fps(fps);
+        }
+        if let Some(color) = config.foreground {
+            interconnect.graphics.set_foreground(color);
+        }
+        if let Some(color) = config.background {
+            interconnect.graphics.set_background(color);
+        }
+        if let Some(frequency) = config.beep_frequency {
+            interconnect.sound.set_beep_frequency(frequency);
+        }
+        if let Some(volume) = config.volume {
+            interconnect.sound.set_volume(volume);
+        }
+        if let Some(recorder) = config.audio_recorder {
+            interconnect.sound.record(recorder);
+        }
+        for (key, keycode) in config.key_bindings {
+            interconnect.input.bind_key(key, keycode);
+        }
+        for (key, button) in config.button_bindings {
+            interconnect.input.bind_button(key, button);
+        }
+        for provider in config.input_providers {
+            interconnect.input.add_provider(provider);
+        }
+        for listener in config.beep_listeners {
+            interconnect.sound.add_listener(listener);
+        }
+
+        let mut cpu = match config.seed {
+            Some(seed) => Cpu::with_seed(interconnect, seed),
+            None => Cpu::new(interconnect),
+        };
+        cpu.fill_registers(config.register_fill);
+        cpu.set_timing(config.timing);
+        if let Some(hz) = config.clock_hz {
+            cpu.set_clock_hz(hz);
+        }
+        if let Some(cycles) = config.cycles_per_frame {
+            cpu.set_cycles_per_frame(cycles);
+        }
+        cpu.set_pacing(config.pacing);
+        if config.turbo {
+            cpu.enable_turbo();
+        }
+        if let Some(changelog) = config.changelog {
+            cpu.record_changes(changelog);
+        }
+        if let Some(reference) = config.reference {
+            cpu.compare_against(reference);
+        }
+        if let Some(trace) = config.trace {
+            cpu.trace_to(trace);
+        }
+        if config.chip8x {
+            cpu.enable_chip8x();
+        }
+        if config.schip {
+            cpu.enable_schip();
+        }
+        if config.xochip {
+            cpu.enable_xochip();
+        }
+        cpu.set_quirks(config.quirks);
+        if let Some(storage) = config.flag_storage {
+            cpu.persist_flags(storage);
+        }
+        for addr in config.breakpoints {
+            cpu.add_breakpoint(addr);
+        }
+        if let Some(slot) = config.quicksave_slot {
+            cpu.set_quicksave_slot(slot);
+        }
+        if let Some(frames) = config.rewind_frames {
+            cpu.set_rewind_capacity(frames);
+        }
+        if config.record_input {
+            cpu.start_recording();
+        }
+        if let Some(log) = config.input_replay {
+            cpu.replay(log);
+        }
+        if let Some(scale) = config.screenshot_scale {
+            cpu.set_screenshot_scale(scale);
+        }
+        if config.title_status && !config.headless && !config.terminal {
+            cpu.show_title_status();
+        }
+        if config.debug {
+            cpu.pause();
+        }
 
-        VirtualMachine {
+        Ok(VirtualMachine {
             cpu: cpu,
+        })
+    }
+
+    /// Prints which keyboard key each keypad key is bound to, laid out like
+    /// the original hexadecimal keypad.
+    pub fn print_keys(&self) {
+        const KEYPAD_LAYOUT: [[usize; 4]; 4] = [
+            [0x1, 0x2, 0x3, 0xC],
+            [0x4, 0x5, 0x6, 0xD],
+            [0x7, 0x8, 0x9, 0xE],
+            [0xA, 0x0, 0xB, 0xF],
+        ];
+
+        let labels = self.cpu.interconnect().keypad_labels();
+        for row in KEYPAD_LAYOUT.iter() {
+            let keys: Vec<String> = row.iter()
+                .map(|&key| format!("{:X}: {:<8}", labels[key].0, labels[key].1))
+                .collect();
+            println!("{}", keys.join(" ").trim_end());
         }
     }
 
+    /// Wrapper for the cpu's warp function. Execution will begin at the passed
+    /// address instead of the start of program space.
+    pub fn warp_to(&mut self, addr: u16) -> Result<(), String> {
+        self.cpu.warp_to(addr)
+    }
+
+    /// Writes a byte to ram before the rom runs, patching it.
+    pub fn poke(&mut self, addr: usize, byte: u8) -> Result<(), MemoryError> {
+        self.cpu.interconnect_mut().poke(addr, byte)
+    }
+
+    /// Starts recording the display to an animated GIF, finished when the
+    /// virtual machine shuts down.
+    pub fn start_gif(&mut self, path: &str) -> io::Result<()> {
+        self.cpu.interconnect_mut().start_gif(path)
+    }
+
+    /// Wrapper for the cpu's save state function.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.cpu.save_state()
+    }
+
+    /// Wrapper for the cpu's load state function.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        self.cpu.load_state(bytes)
+    }
+
+    /// The display packed 8 pixels to a byte, see
+    /// `Interconnect::framebuffer_packed`.
+    pub fn framebuffer_packed(&self) -> Vec<u8> {
+        self.cpu.interconnect().framebuffer_packed()
+    }
+
+    /// Wrapper for the cpu's function to stop recording input.
+    pub fn stop_recording(&mut self) -> InputLog {
+        self.cpu.stop_recording()
+    }
+
     /// Wrapper for the cpu's run function. Simply starts code execution at the
-    /// end of reserved program memory.
-    pub fn run(&mut self) {
-        self.cpu.run();
+    /// end of reserved program memory, then shuts down cleanly once it halts,
+    /// whether or not execution failed.
+    pub fn run(&mut self) -> Result<Stopped, CpuError> {
+        let result = self.cpu.run();
+        self.cpu.shutdown();
+
+        result
+    }
+
+    /// Like `run`, but stops after `cycles` instructions at the latest.
+    /// Returns how many instructions were executed and why execution stopped.
+    pub fn run_for(&mut self, cycles: u64) -> Result<(u64, Stopped), CpuError> {
+        let result = self.cpu.run_for(cycles);
+        self.cpu.shutdown();
+
+        result
     }
 }
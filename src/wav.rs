@@ -0,0 +1,133 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::byteorder::{ByteOrder, LittleEndian};
+
+// Recordings are 16-bit mono PCM at the same rate the beeper is played at.
+pub const SAMPLE_RATE: u32 = 44100;
+const CHANNELS: u16 = 1;
+const BITS_PER_SAMPLE: u16 = 16;
+const BYTES_PER_SAMPLE: u32 = (BITS_PER_SAMPLE / 8) as u32;
+
+// Size of the RIFF header that precedes the sample data.
+const HEADER_SIZE: u32 = 44;
+
+pub struct WavRecorder {
+    // Buffered so a sample at a time doesn't turn into a syscall at a time.
+    file: BufWriter<File>,
+
+    // Number of bytes of sample data written so far.
+    data_size: u32,
+
+    // Set once the header has been patched with the final sizes.
+    finished: bool,
+}
+
+impl WavRecorder {
+    /// Creates a WAV file at the given path. The header is written right away
+    /// with empty sizes and then patched once the recording is finished.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<WavRecorder> {
+        let mut recorder = WavRecorder {
+            file: BufWriter::new(File::create(path)?),
+            data_size: 0,
+            finished: false,
+        };
+        recorder.write_header()?;
+
+        Ok(recorder)
+    }
+
+    /// Appends a single sample in the range -1.0 to 1.0 to the recording.
+    pub fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        let mut buf = [0; 2];
+        LittleEndian::write_i16(&mut buf, (sample * i16::MAX as f32) as i16);
+        self.file.write_all(&buf)?;
+        self.data_size += BYTES_PER_SAMPLE;
+
+        Ok(())
+    }
+
+    /// Patches the header with the final sizes and flushes the file. Files
+    /// that are never finished are still playable by most software but will
+    /// report a length of zero.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.flush()?;
+        self.finished = true;
+
+        Ok(())
+    }
+
+    /// Writes the RIFF header describing the sample format and data size.
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut header = [0; HEADER_SIZE as usize];
+        let byte_rate = SAMPLE_RATE * CHANNELS as u32 * BYTES_PER_SAMPLE;
+        let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+
+        header[0..4].copy_from_slice(b"RIFF");
+        LittleEndian::write_u32(&mut header[4..8], HEADER_SIZE - 8 + self.data_size);
+        header[8..12].copy_from_slice(b"WAVE");
+
+        // Format chunk, 1 signals uncompressed PCM.
+        header[12..16].copy_from_slice(b"fmt ");
+        LittleEndian::write_u32(&mut header[16..20], 16);
+        LittleEndian::write_u16(&mut header[20..22], 1);
+        LittleEndian::write_u16(&mut header[22..24], CHANNELS);
+        LittleEndian::write_u32(&mut header[24..28], SAMPLE_RATE);
+        LittleEndian::write_u32(&mut header[28..32], byte_rate);
+        LittleEndian::write_u16(&mut header[32..34], block_align);
+        LittleEndian::write_u16(&mut header[34..36], BITS_PER_SAMPLE);
+
+        // Data chunk, the samples follow directly after the header.
+        header[36..40].copy_from_slice(b"data");
+        LittleEndian::write_u32(&mut header[40..44], self.data_size);
+
+        self.file.write_all(&header)
+    }
+}
+
+impl Drop for WavRecorder {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn recordings_last_as_long_as_the_beep() {
+        let path = env::temp_dir().join(format!("notch-{}-beep.wav", process::id()));
+
+        // A sound timer of 30 beeps for half a second at 60Hz.
+        let samples = SAMPLE_RATE * 30 / 60;
+        let mut recorder = WavRecorder::create(&path).unwrap();
+        for sample in 0..samples {
+            recorder.write_sample(if sample % 100 < 50 { 0.25 } else { -0.25 }).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let data_size = samples * BYTES_PER_SAMPLE;
+        assert_eq!(wav.len(), (HEADER_SIZE + data_size) as usize);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(LittleEndian::read_u32(&wav[4..8]), HEADER_SIZE - 8 + data_size);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(LittleEndian::read_u32(&wav[40..44]), data_size);
+
+        // The length players report is the data size over the byte rate.
+        let byte_rate = LittleEndian::read_u32(&wav[28..32]);
+        assert_eq!(data_size as f64 / byte_rate as f64, 0.5);
+    }
+}